@@ -127,7 +127,7 @@ fn setup_system(state: &Setup, device: &wgpu::Device) -> CellSimulation {
     simulation.add_reaction(vec!["A", "B"], vec!["C"], 5.82);
     simulation.add_reaction(vec!["C"], vec!["A", "B"], 0.351);
 
-    simulation.prepare_for_gpu(&uniform_buffer, &texture, device);
+    simulation.prepare_for_gpu(&uniform_buffer, &texture, device).unwrap();
 
     let stats_container = make_all_stats(vec!["A"]);
 
@@ -151,7 +151,7 @@ fn step_system(
     let frame_num = simulation.uniform_buffer.data.frame_num;
     let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-    simulation.simulation.step(frame_num, &mut command_encoder, device);
+    simulation.simulation.step(frame_num, &mut command_encoder, device, queue);
     mouse_slice = simulation.renderer.render(mouse_slice, &mut command_encoder, &view);
 
     simulation.uniform_buffer.data.frame_num += 1;
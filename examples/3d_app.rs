@@ -152,7 +152,7 @@ fn setup_system(state: &Setup, device: &wgpu::Device) -> CellSimulation {
     simulation.add_reaction(vec!["A", "B"], vec!["C"], 5.82);
     simulation.add_reaction(vec!["C"], vec!["A", "B"], 0.351);
 
-    simulation.prepare_for_gpu(&uniform_buffer, &texture, device);
+    simulation.prepare_for_gpu(&uniform_buffer, &texture, device).unwrap();
 
     let renderer = Render3D::new(&texture, &simulation.lattice_params, &render_params, &state.config(), device);
 
@@ -177,7 +177,7 @@ fn step_system(
     let frame_num = simulation.uniform_buffer.data.frame_num;
     //let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-    simulation.simulation.step(frame_num, command_encoder, device, 50);
+    simulation.simulation.step(frame_num, command_encoder, device, queue, 50);
     
     simulation.uniform_buffer.data.frame_num += 1;
     simulation.uniform_buffer.data.itime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u32;
@@ -82,7 +82,7 @@ fn step_system(
     let frame_num = simulation.uniform_buffer.data.frame_num;
     //let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-    simulation.simulation.step(frame_num, command_encoder, device, 50);
+    simulation.simulation.step(frame_num, command_encoder, device, queue, 50);
     
     simulation.uniform_buffer.data.frame_num += 1;
     simulation.uniform_buffer.data.itime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u32;
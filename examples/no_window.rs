@@ -1,4 +1,4 @@
-use std::{time::{SystemTime, UNIX_EPOCH}, collections::{HashMap, VecDeque}};
+use std::collections::{HashMap, VecDeque};
 use std::time::Instant;
 use log::debug;
 use simulation::{Simulation, Setup, Uniform, UniformBuffer, LatticeParams, Texture};
@@ -102,7 +102,7 @@ fn setup_system(device: &wgpu::Device) -> CellSimulation {
     simulation.add_reaction(vec!["A", "B"], vec!["C"], 5.82);
     simulation.add_reaction(vec!["C"], vec!["A", "B"], 0.351);
 
-    simulation.prepare_for_gpu(&uniform_buffer, &texture, device);
+    simulation.prepare_for_gpu(&uniform_buffer, &texture, device).unwrap();
 
     let stats_container = make_all_stats(vec!["A", "C"]);
     
@@ -121,12 +121,9 @@ fn step_system(
     let frame_num = simulation.uniform_buffer.data.frame_num;
     let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-    simulation.simulation.step(frame_num, &mut command_encoder, device, 25);
+    simulation.simulation.step(frame_num, &mut command_encoder, device, queue, 25);
 
     simulation.uniform_buffer.data.frame_num += 1;
-    let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u32;
-    assert_ne!(t, simulation.uniform_buffer.data.itime);
-    simulation.uniform_buffer.data.itime = t;
 
     queue.write_buffer(&simulation.uniform_buffer.buffer, 0, bytemuck::cast_slice(&[simulation.uniform_buffer.data]));
     queue.submit(Some(command_encoder.finish()));
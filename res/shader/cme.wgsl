@@ -8,114 +8,435 @@ struct Lattice {
 @group(0) @binding(0) var<uniform> params: LatticeParams;
 @group(0) @binding(1) var<uniform> reaction_params: ReactionParams;
 @group(0) @binding(2) var<uniform> unif: Uniforms;
+@group(0) @binding(3) var<storage> regions: array<u32>;
 
 @group(1) @binding(1) var<storage, read_write> latticeDest: Lattice;
 @group(1) @binding(3) var<storage, read_write> occupancyDest: array<atomic<u32>>;
+// Voxels being changed by a surface reaction
+@group(1) @binding(5) var<storage, read_write> lock: array<atomic<u32>>;
 
 @group(2) @binding(0) var <storage, read_write> concentrations: array<i32>;
 @group(2) @binding(1) var <storage, read> stoichiometry: array<i32>;
 @group(2) @binding(2) var <storage, read> reactions_idx: array<i32>;
 @group(2) @binding(3) var <storage, read> reaction_rates: array<f32>;
+@group(2) @binding(4) var <storage, read> reaction_mask: array<u32>;
+@group(2) @binding(5) var <storage, read> rate_laws: array<f32>;
+// Surface reactions: region of the neighbour + 1 (0 for other reactions), host region, reactants taken from the
+// neighbour and the change of each species in the neighbour. Keep in sync with Simulation::build_surface_table
+@group(2) @binding(6) var <storage, read> surface_reactions: array<i32>;
 @group(4) @binding(0) var<storage> reservoirs: array<u32>;
+// Rows of [kind, species, region, particles per voxel and step], see boundary.rs. The first row is empty
+@group(4) @binding(1) var<storage> boundary_conditions: array<f32>;
 
 // Statistics bindings. It would be ideal to have them together in the same binding. Is that possible?
 @group(3) @binding(0) var <storage, read_write> concentrations_stat: array<atomic<i32>>;
+// Firings of each reaction: the total, then one per region
+@group(3) @binding(1) var <storage, read_write> reactions_stat: array<atomic<i32>>;
 
 
-@compute @workgroup_size(2, 1, 1)  // Why repeat it? Is it necessary to add it here?
-fn cme(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    // Solve CME with Gillespie algorithm
-    let idx_occupancy: i32 = get_index_occupancy(global_id, params);
-    let idx_concentration: i32 = idx_occupancy * i32(reaction_params.num_species + 1u);
+let SINGLE_EVENT: u32 = 0u;
+let TAU_LEAPING: u32 = 2u;
+// Kinds of rate law, as in rates.rs
+let MASS_ACTION: u32 = 0u;
+let MICHAELIS_MENTEN: u32 = 1u;
+let HILL_ACTIVATOR: u32 = 2u;
+let HILL_REPRESSOR: u32 = 3u;
+let PIECEWISE: u32 = 4u;
+// Kinds of boundary condition, as in boundary.rs
+let CLAMP: u32 = 1u;
+let FLUX: u32 = 2u;
+// Gillespie steps of a voxel in one time step, so that a voxel with a huge propensity cannot stall the kernel.
+// CME::new sets it from solver::MAX_EVENTS
+//!define MAX_EVENTS 1024u
+
+// Size of the propensity table, one more than the reactions. CME::new sets it from the simulation
+//!define MAX_REACTIONS 100u
+
+var<private> cumm_propensity: array<f32, MAX_REACTIONS>;
+
+// Number of events of a Poisson process with the given mean. Normal approximation for large means
+fn poisson(mean: f32) -> i32 {
+    if (mean > 30.) {
+        let u1 = 1. - next_float();
+        let u2 = next_float();
+        let normal = sqrt(-2. * log(u1)) * cos(6.2831853 * u2);
+        return max(i32(round(mean + sqrt(mean) * normal)), 0);
+    }
+    let limit = exp(-mean);
+    var product = next_float();
+    var events = 0;
+    while (product > limit) {
+        product *= next_float();
+        events += 1;
+    }
+    return events;
+}
+
+// Rate law other than mass action of `x` particles, with its parameters from i_params in rate_laws
+fn rate_law(kind: u32, x: f32, i_params: u32) -> f32 {
+    if (kind == MICHAELIS_MENTEN) {
+        return x / (rate_laws[i_params] + x);
+    }
+    if (kind == HILL_ACTIVATOR | kind == HILL_REPRESSOR) {
+        // pow is undefined for x = 0
+        var ratio: f32 = 0.;
+        if (x > 0.) {
+            ratio = pow(x / rate_laws[i_params + 1u], rate_laws[i_params]);
+        }
+        if (kind == HILL_ACTIVATOR) {
+            return ratio / (1. + ratio);
+        }
+        return 1. / (1. + ratio);
+    }
+    if (kind == PIECEWISE) {
+        // Number of thresholds, first value, then each threshold with the value from it on
+        let steps = u32(rate_laws[i_params]);
+        var value: f32 = rate_laws[i_params + 1u];
+        for (var i: u32 = 0u; i < steps; i += 1u) {
+            if (x >= rate_laws[i_params + 2u + 2u * i]) {
+                value = rate_laws[i_params + 3u + 2u * i];
+            }
+        }
+        return value;
+    }
+    return 1.;
+}
+
+// Number of distinct sets of reactants of a reaction in a voxel: nA * nB for A + B, nA * (nA - 1) / 2 for A + A.
+// Keep in sync with rates::propensity
+fn reactant_sets(idx_concentration: i32, i_reaction: u32) -> f32 {
+    let i_reaction_idx = i_reaction * reaction_params.reactant_slots;
+    var sets: f32 = 1.;
+    for (var i_slot: u32 = 0u; i_slot < reaction_params.reactant_slots; i_slot += 1u) {
+        let species = reactions_idx[i_reaction_idx + i_slot];
+        if (species == 0) {
+            // Rows are padded with the void species
+            break;
+        }
+        // Particles of this species already taken by the previous slots
+        var taken: i32 = 0;
+        for (var i_previous: u32 = 0u; i_previous < i_slot; i_previous += 1u) {
+            if (reactions_idx[i_reaction_idx + i_previous] == species) {
+                taken += 1;
+            }
+        }
+        sets *= f32(max(concentrations[idx_concentration + species] - taken, 0)) / f32(taken + 1);
+    }
+    return sets;
+}
 
-    concentrations[idx_concentration] = 1; // I make it 1 so that it doesn't interfere later for the propensity
-    // If reactions_idx[i] == 0 --> concentration[0] = 1 --> It doesn't limit the propensity of the reaction
+// The same for the reactants a surface reaction takes from the neighbour voxel, from its row in surface_reactions
+fn neighbour_sets(idx_concentration: i32, i_row: u32) -> f32 {
+    let i_reactants = i_row + 2u;
+    var sets: f32 = 1.;
+    for (var i_slot: u32 = 0u; i_slot < reaction_params.surface_reactant_slots; i_slot += 1u) {
+        let species = surface_reactions[i_reactants + i_slot];
+        if (species == 0) {
+            break;
+        }
+        var taken: i32 = 0;
+        for (var i_previous: u32 = 0u; i_previous < i_slot; i_previous += 1u) {
+            if (surface_reactions[i_reactants + i_previous] == species) {
+                taken += 1;
+            }
+        }
+        sets *= f32(max(concentrations[idx_concentration + species] - taken, 0)) / f32(taken + 1);
+    }
+    return sets;
+}
 
-    // Compute propensities
-    var cumm_propensity: array<f32, MAX_REACTIONS>;
+// Fills cumm_propensity with the cumulative propensities of the voxel and returns the total
+fn compute_propensities(idx_concentration: i32, region: u32) -> f32 {
     cumm_propensity[0] = 0.;
-    var total_propensity: f32 = 0.0f;
-    for (var i_reaction: u32 = 1u; i_reaction <= reaction_params.num_reactions; i_reaction += 1u) { // Make sure if this is like this or with +1 somewhere
-        let k: f32 = reaction_rates[i_reaction];
-        let i_reaction_idx = i_reaction * 3u;
-        
-        var propensity: f32 = k * f32(concentrations[idx_concentration + reactions_idx[i_reaction_idx]] * 
-                                      concentrations[idx_concentration + reactions_idx[i_reaction_idx + 1u]] * 
-                                      concentrations[idx_concentration + reactions_idx[i_reaction_idx + 2u]]);
-        total_propensity += propensity;
+    for (var i_reaction: u32 = 1u; i_reaction <= reaction_params.num_reactions; i_reaction += 1u) {
+        // Reactions restricted to other regions do not happen here
+        let k: f32 = reaction_rates[i_reaction] * f32(reaction_mask[i_reaction * params.n_regions + region]);
+        var propensity: f32 = k * reactant_sets(idx_concentration, i_reaction);
+        // Other rate laws replace the sets of reactants, but the reactants must be there. Keep in sync with rates::law_propensity
+        let i_law = i_reaction * reaction_params.law_slots;
+        let kind = u32(rate_laws[i_law]);
+        if (kind != MASS_ACTION & propensity > 0.) {
+            let x = f32(concentrations[idx_concentration + i32(rate_laws[i_law + 1u])]);
+            propensity = k * rate_law(kind, x, i_law + 2u);
+        }
         cumm_propensity[i_reaction] = cumm_propensity[i_reaction - 1u] + propensity;
     }
+    return cumm_propensity[reaction_params.num_reactions];
+}
+
+// Index of a reaction drawn with probability proportional to its propensity
+fn select_reaction(total_propensity: f32) -> u32 {
+    let threshold = next_float() * total_propensity;
+    var i: u32 = 1u;
+    while (i < reaction_params.num_reactions & threshold >= cumm_propensity[i]) {
+        i += 1u;
+    }
+    return i;
+}
+
+// Change of a species by a reaction, from its row in the stoichiometry matrix or in the surface reactions table
+fn species_change(i_change: i32, surface: bool, idx_species: i32) -> i32 {
+    if (surface) {
+        return surface_reactions[i_change + idx_species];
+    }
+    return stoichiometry[i_change + idx_species];
+}
+
+// Whether a voxel can take `times` times a change: no count becomes negative and the particles fit in the site.
+// The particles of a reservoir are not consumed
+fn change_fits(idx_occupancy: i32, i_change: i32, surface: bool, times: i32) -> bool {
+    let idx_concentration = idx_occupancy * i32(reaction_params.num_species + 1u);
+    let reservoir = reservoirs[idx_occupancy];
+    var occupancy: i32 = 0;
+    for (var idx_species = 1; idx_species <= i32(reaction_params.num_species); idx_species += 1) {
+        var count: i32 = concentrations[idx_concentration + idx_species];
+        if (reservoir != u32(idx_species)) {
+            count += times * species_change(i_change, surface, idx_species);
+        }
+        if (count < 0) {
+            return false;
+        }
+        occupancy += count;
+    }
+    // As before, the last slot of a site stays empty
+    return u32(occupancy) < params.max_particles_site;
+}
 
-    // Renormalize propensities 
-    for (var i: u32 = 0u; i < reaction_params.num_reactions; i += 1u) {
-        cumm_propensity[i] = cumm_propensity[i] / total_propensity; 
+// Writes the particles of a voxel to the lattice from its concentrations
+fn write_voxel(idx_occupancy: i32) {
+    let idx_concentration = idx_occupancy * i32(reaction_params.num_species + 1u);
+    let idx_lattice = u32(idx_occupancy) * params.max_particles_site;
+    var j_lattice = 0u;
+    for (var idx_species = 1; idx_species <= i32(reaction_params.num_species); idx_species += 1) {
+        var cc: i32 = concentrations[idx_concentration + idx_species];
+        while (cc > 0) {
+            latticeDest.lattice[idx_lattice + j_lattice] = u32(idx_species);
+            cc -= 1;
+            j_lattice += 1u;
+        }
+    }
+    // Write the new occupancy:
+    atomicStore(&occupancyDest[idx_occupancy], j_lattice);
+    // Fill the rest with zeros
+    while (j_lattice < params.max_particles_site) {
+        latticeDest.lattice[idx_lattice + j_lattice] = 0u;
+        j_lattice += 1u;
     }
+}
 
-    let idx_lattice = u32(get_index_lattice(global_id, params));
-    // Copy the contents of the cell just in case it overflows:
-    var cell_copy: array<u32, 16>;  // It should be length params.max_particles_site, but that's not allowed. TODO: Check this
-    for (var i_particle: u32 = 0u; i_particle < params.max_particles_site; i_particle += 1u) {
-        cell_copy[i_particle] = latticeDest.lattice[idx_lattice + i_particle];
+// Applies `times` times a change to the concentrations of a voxel and writes the voxel back to the lattice
+fn apply_change(idx_occupancy: i32, i_change: i32, surface: bool, times: i32) {
+    let idx_concentration = idx_occupancy * i32(reaction_params.num_species + 1u);
+    let reservoir = reservoirs[idx_occupancy];
+    for (var idx_species = 1; idx_species <= i32(reaction_params.num_species); idx_species += 1) {
+        if (reservoir != u32(idx_species)) {
+            let change = times * species_change(i_change, surface, idx_species);
+            concentrations[idx_concentration + idx_species] += change;
+            let original = atomicAdd(&concentrations_stat[idx_species], change);
+        }
     }
+    write_voxel(idx_occupancy);
+}
 
-    // 1. Generate random
-    // 2. Find next reaction with the propensity vector
-    // 3. Perform reaction: I need to know where the different species are. That means another loop or save them in the previous one
-    var state: u32 = PCG(unif.itime + global_id.x * global_id.y + global_id.z);
-    var rand_number: f32 = UniformFloat(state);
-    if (rand_number <= 1. - exp(-total_propensity * params.tau)) {
-        // One reaction can happen in this time
-        rand_number = UniformFloat(state + 1u);
 
-        var i: u32 = 0u;  // Index of the reaction
-        while (rand_number > cumm_propensity[i] & i < reaction_params.num_reactions) {
-            i += 1u;
+// Whether a voxel is next to another region or to a face of the lattice. Keep in sync with boundary::at_boundary
+fn at_boundary(id_volume: vec3<u32>, region: u32) -> bool {
+    for (var direction: u32 = 0u; direction < 6u; direction += 1u) {
+        let voxel = get_neighbour(id_volume, direction, params);
+        if (voxel.x < 0 || regions[get_index_occupancy(vec3<u32>(voxel), params)] != region) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Clamps and fluxes of the voxel, see boundary.rs. A clamped species is redrawn around its concentration and a
+// flux adds its particles if the voxel is at the boundary of its region. The site keeps its last slot empty
+fn apply_boundaries(id_volume: vec3<u32>, idx_occupancy: i32, region: u32) {
+    let idx_concentration = idx_occupancy * i32(reaction_params.num_species + 1u);
+    let num_rows = arrayLength(&boundary_conditions) / 4u;
+    var changed = false;
+    for (var i_row: u32 = 1u; i_row < num_rows; i_row += 1u) {
+        let kind = u32(boundary_conditions[4u * i_row]);
+        let species = i32(boundary_conditions[4u * i_row + 1u]);
+        let mean = boundary_conditions[4u * i_row + 3u];
+        if (u32(boundary_conditions[4u * i_row + 2u]) != region) {
+            continue;
+        }
+        if (kind == FLUX && !at_boundary(id_volume, region)) {
+            continue;
         }
 
-        // Loop the stoichiometry matrix row and apply it to the concentrations vector. Update the lattice at the same time
-        var j_lattice = 0u;
-        let idx_reaction = i32(i * (reaction_params.num_species + 1u));
-        var new_occupancy = i32(occupancyDest[idx_occupancy]);
+        var occupancy: i32 = 0;
         for (var idx_species = 1; idx_species <= i32(reaction_params.num_species); idx_species += 1) {
-            if reservoirs[idx_lattice] == u32(idx_species) {
-                // We don't update the concentrations because the particle is part of a reservoir
-                continue;
+            occupancy += concentrations[idx_concentration + idx_species];
+        }
+        let count = concentrations[idx_concentration + species];
+        var wanted: i32 = count + poisson(mean);
+        if (kind == CLAMP) {
+            wanted = poisson(mean);
+        }
+        let change = min(wanted - count, max(i32(params.max_particles_site) - 1 - occupancy, 0));
+        if (change != 0) {
+            concentrations[idx_concentration + species] += change;
+            atomicAdd(&concentrations_stat[species], change);
+            changed = true;
+        }
+    }
+    if (changed) {
+        write_voxel(idx_occupancy);
+    }
+}
+
+fn count_firings(i_reaction: u32, times: i32, region: u32) {
+    let idx_counter = i_reaction * (params.n_regions + 1u);
+    atomicAdd(&reactions_stat[idx_counter], times);
+    atomicAdd(&reactions_stat[idx_counter + 1u + region], times);
+}
+
+// Fires a reaction `times` times and writes the voxel back to the lattice. Nothing changes, and it returns false,
+// if a count would become negative or the particles would not fit in the site
+fn fire(i_reaction: u32, times: i32, idx_occupancy: i32) -> bool {
+    // Check first, so that nothing has to be rolled back
+    let i_change = i32(i_reaction * (reaction_params.num_species + 1u));
+    if (!change_fits(idx_occupancy, i_change, false, times)) {
+        return false;
+    }
+    apply_change(idx_occupancy, i_change, false, times);
+    count_firings(i_reaction, times, regions[idx_occupancy]);
+    return true;
+}
+
+@compute @workgroup_size(2, 1, 1)  // Why repeat it? Is it necessary to add it here?
+fn cme(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // Solve CME with Gillespie algorithm
+    let idx_occupancy: i32 = get_index_occupancy(global_id, params);
+    let idx_concentration: i32 = idx_occupancy * i32(reaction_params.num_species + 1u);
+    let region: u32 = regions[idx_occupancy];
+    rng_init(params.seed, u32(idx_occupancy), unif.frame_num, CME_STREAM);
+    apply_boundaries(global_id, idx_occupancy, region);
+
+    if (reaction_params.solver == SINGLE_EVENT) {
+        // One reaction at most, with the probability that at least one happens in this time
+        let total_propensity = compute_propensities(idx_concentration, region);
+        if (total_propensity > 0. && next_float() <= 1. - exp(-total_propensity * params.tau)) {
+            fire(select_reaction(total_propensity), 1, idx_occupancy);
+        }
+        return;
+    }
+
+    // Gillespie steps until the time step is over
+    var t: f32 = 0.;
+    for (var i_event: u32 = 0u; i_event < MAX_EVENTS; i_event += 1u) {
+        let total_propensity = compute_propensities(idx_concentration, region);
+        if (total_propensity <= 0.) {
+            return;
+        }
+
+        let remaining = params.tau - t;
+        if (reaction_params.solver == TAU_LEAPING & total_propensity * remaining > reaction_params.leap_threshold) {
+            // Too many events to take one by one: leap over the rest of the step with the current propensities.
+            // A leap that does not fit is halved until it does
+            for (var i_reaction: u32 = 1u; i_reaction <= reaction_params.num_reactions; i_reaction += 1u) {
+                var times = poisson((cumm_propensity[i_reaction] - cumm_propensity[i_reaction - 1u]) * remaining);
+                while (times > 0 && !fire(i_reaction, times, idx_occupancy)) {
+                    times /= 2;
+                }
             }
-            
-            concentrations[idx_concentration + idx_species] += stoichiometry[idx_reaction + idx_species];
-            new_occupancy += stoichiometry[idx_reaction + idx_species];
-            let original = atomicAdd(&concentrations_stat[idx_species], stoichiometry[idx_reaction + idx_species]); 
-            // Now update the lattice:
-            // Look at the concentration of the species in the site and write 
-            var cc: i32 = concentrations[idx_concentration + idx_species];
-            while (cc > 0) {
-                latticeDest.lattice[idx_lattice + j_lattice] = u32(idx_species);
-                cc -= 1;
-                j_lattice += 1u;
-                if (j_lattice >= params.max_particles_site) {
-                    // It doesn't fit. We have to step back and not perform the reaction
-                    // Reset the lattice
-                    for (var i_particle: u32 = 0u; i_particle < params.max_particles_site; i_particle += 1u) {
-                        latticeDest.lattice[idx_lattice + i_particle] = cell_copy[i_particle];
-                    }
-                    // Reset the concentrations (only up to idx_species)
-                    for (var idx_species_reset = 1; idx_species_reset <= idx_species; idx_species_reset += 1) {
-                        //atomicAdd(&concentrations[idx_concentration + idx_species_reset], -stoichiometry[idx_reaction + idx_species_reset]);
-                        concentrations[idx_concentration + idx_species_reset] -= stoichiometry[idx_reaction + idx_species_reset];
-                        atomicAdd(&concentrations_stat[idx_species_reset], -stoichiometry[idx_reaction + idx_species_reset]); 
-                    }
-                    // Exit the program
-                    return;
-                    //break;
+            return;
+        }
+
+        t += -log(1. - next_float()) / total_propensity;
+        if (t > params.tau) {
+            return;
+        }
+        // An event that does not fit is rejected and the others go on, e.g. a degradation that frees room. The time
+        // has advanced, so the loop still ends
+        fire(select_reaction(total_propensity), 1, idx_occupancy);
+    }
+}
+
+// Surface reactions between the voxel and its neighbours in another region, one event at most per reaction and
+// step. Both voxels are locked while they change; if either is taken, the event is lost and counted in row 0 of
+// reactions_stat, which no reaction uses
+@compute @workgroup_size(2, 1, 1)
+fn cme_surface(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx_occupancy: i32 = get_index_occupancy(global_id, params);
+    let idx_concentration: i32 = idx_occupancy * i32(reaction_params.num_species + 1u);
+    let region: u32 = regions[idx_occupancy];
+    let species_stride = i32(reaction_params.num_species + 1u);
+    // Not the stream of the main pass
+    rng_init(params.seed, u32(idx_occupancy), unif.frame_num, SURFACE_STREAM);
+
+    for (var i_reaction: u32 = 1u; i_reaction <= reaction_params.num_reactions; i_reaction += 1u) {
+        let i_row = i_reaction * reaction_params.surface_slots;
+        let neighbour_region = surface_reactions[i_row];
+        if (neighbour_region == 0 || u32(surface_reactions[i_row + 1u]) != region) {
+            continue;
+        }
+        let host = reaction_rates[i_reaction] * reactant_sets(idx_concentration, i_reaction);
+        if (host <= 0.) {
+            continue;
+        }
+
+        // Each neighbour in the other region weighs its sets of reactants, 1 if it gives none.
+        // Keep in sync with rates::surface_propensity
+        var weights: array<f32, 6>;
+        var total: f32 = 0.;
+        for (var direction: u32 = 0u; direction < 6u; direction += 1u) {
+            weights[direction] = 0.;
+            let voxel = get_neighbour(global_id, direction, params);
+            if (voxel.x >= 0) {
+                let idx_neighbour = get_index_occupancy(vec3<u32>(voxel), params);
+                if (i32(regions[idx_neighbour]) + 1 == neighbour_region) {
+                    weights[direction] = neighbour_sets(idx_neighbour * species_stride, i_row);
+                    total += weights[direction];
                 }
             }
         }
-        // Write the new occupancy:
-        atomicStore(&occupancyDest[idx_occupancy], u32(new_occupancy));
-        // Fill the rest with zeros
-        while (j_lattice < params.max_particles_site) {
-            latticeDest.lattice[idx_lattice + j_lattice] = 0u;
-            j_lattice += 1u;
+        if (total <= 0.) {
+            continue;
         }
-    } 
-}
\ No newline at end of file
+        var propensity: f32 = host * total;
+        if (surface_reactions[i_row + 2u] == 0) {
+            // Nothing is taken from the neighbours: the rate does not depend on how many there are
+            propensity = host;
+        }
+        // Once per step at most, Simulation::validate rejects a large propensity * tau unless it is allowed
+        if (next_float() > 1. - exp(-propensity * params.tau)) {
+            continue;
+        }
+
+        // The neighbour, with probability proportional to its weight
+        let threshold = next_float() * total;
+        var direction: u32 = 0u;
+        var cumulative: f32 = weights[0];
+        while (direction < 5u & (threshold >= cumulative | weights[direction] <= 0.)) {
+            direction += 1u;
+            cumulative += weights[direction];
+        }
+        if (weights[direction] <= 0.) {
+            continue;
+        }
+        let idx_neighbour = get_index_occupancy(vec3<u32>(get_neighbour(global_id, direction, params)), params);
+
+        if (atomicExchange(&lock[idx_occupancy], 1u) != 0u) {
+            count_firings(0u, 1, region);
+            continue;
+        }
+        if (atomicExchange(&lock[idx_neighbour], 1u) != 0u) {
+            atomicStore(&lock[idx_occupancy], 0u);
+            count_firings(0u, 1, region);
+            continue;
+        }
+        let i_change = i32(i_reaction) * species_stride;
+        let i_neighbour_change = i32(i_row + 2u + reaction_params.surface_reactant_slots);
+        if (change_fits(idx_occupancy, i_change, false, 1) & change_fits(idx_neighbour, i_neighbour_change, true, 1)) {
+            apply_change(idx_occupancy, i_change, false, 1);
+            apply_change(idx_neighbour, i_neighbour_change, true, 1);
+            count_firings(i_reaction, 1, region);
+        }
+        atomicStore(&lock[idx_neighbour], 0u);
+        atomicStore(&lock[idx_occupancy], 0u);
+    }
+}
@@ -1,5 +1,3 @@
-let MAX_REACTIONS: u32 = 100u;
-
 // struct LatticeParams {
 //     x: f32,
 //     y: f32,
@@ -20,15 +18,25 @@ struct LatticeParams {
     tau: f32,
     dims: vec3<f32>,
     res: vec3<u32>,
+    boundaries: vec3<u32>,  // 0 reflecting, 1 periodic, 2 absorbing, per axis
+    seed: vec2<u32>,  // Key of the random streams, see random.wgsl
 };
 
+let PERIODIC: u32 = 1u;
+
 struct ReactionParams {
     num_species: u32,
-    num_reactions: u32
+    num_reactions: u32,
+    reactant_slots: u32,  // Width of a row of reactions_idx
+    solver: u32,  // 0 single event, 1 SSA, 2 tau-leaping
+    leap_threshold: f32,
+    law_slots: u32,  // Width of a row of rate_laws
+    surface_slots: u32,  // Width of a row of surface_reactions
+    surface_reactant_slots: u32,  // Reactants in the neighbour voxel, after the regions of the row
 }
 
 struct Uniforms {
-    itime: u32,
+    itime: u32,  // Wall clock for the renderers, the kernels draw from params.seed and frame_num
     frame_num: u32,
     slice: u32,
     slice_axis: u32,
@@ -50,3 +58,18 @@ fn get_index_occupancy(id_volume: vec3<u32>, params: LatticeParams) -> i32 {
     //return i32(id_volume.x + id_volume.y * params.res.x + id_volume.z * params.res.x * params.res.y);
 }
 
+// Voxel next to `id_volume` in one of 6 directions: -x, +x, -y, +y, -z, +z. Its x is -1 outside of the lattice.
+// Periodic axes wrap around. Keep in sync with boundary::neighbour
+fn get_neighbour(id_volume: vec3<u32>, direction: u32, params: LatticeParams) -> vec3<i32> {
+    let axis = direction / 2u;
+    var offset = vec3<i32>(0, 0, 0);
+    offset[axis] = select(-1, 1, direction % 2u == 1u);
+    var voxel = vec3<i32>(id_volume) + offset;
+    if (params.boundaries[axis] == PERIODIC) {
+        voxel = (voxel + vec3<i32>(params.res)) % vec3<i32>(params.res);
+    }
+    if (any(voxel < vec3<i32>(0, 0, 0)) || any(voxel >= vec3<i32>(params.res))) {
+        return vec3<i32>(-1, 0, 0);
+    }
+    return voxel;
+}
@@ -1,22 +1,39 @@
 //-------------------------------------------------------------------------
-// Hash
+// Philox4x32-10
 //-------------------------------------------------------------------------
 
-fn Hash_Wang(_key: u32) -> u32 {
-	var key: u32 = (_key ^ 61u) ^ (_key >> 16u);
-	key = key + (key << 3u);
-	key = key ^ (key >> 4u);
-	key = key * 0x27D4EB2Du;
-	key = key ^ (key >> 15u);
-	return key;
+// Streams of the kernels, the last word of the counter. RDME::new and CME::new set them from random.rs
+//!define RDME_STREAM 0u
+//!define CME_STREAM 1u
+//!define SURFACE_STREAM 2u
+
+// High and low words of a * b, from 16-bit halves as there are no 64-bit integers
+fn mulhilo(a: u32, b: u32) -> vec2<u32> {
+	let a_lo = a & 0xffffu;
+	let a_hi = a >> 16u;
+	let b_lo = b & 0xffffu;
+	let b_hi = b >> 16u;
+	let lo_lo = a_lo * b_lo;
+	let hi_lo = a_hi * b_lo;
+	let lo_hi = a_lo * b_hi;
+	let cross = (lo_lo >> 16u) + (hi_lo & 0xffffu) + (lo_hi & 0xffffu);
+	let hi = a_hi * b_hi + (hi_lo >> 16u) + (lo_hi >> 16u) + (cross >> 16u);
+	return vec2<u32>(hi, a * b);
 }
 
-fn PCG(state: u32) -> u32 {
-	// According to this: https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
-	// Better performance and _much_ better distribution than the Wang hash
-	var rng_state = state * 747796405u + 2891336453u;
-	var word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
-	return (word >> 22u) ^ word;
+// 4 random words for a counter and a key. Same as random::philox4x32 on the CPU
+fn philox4x32(counter: vec4<u32>, key: vec2<u32>) -> vec4<u32> {
+	var c = counter;
+	var k = key;
+	for (var round: u32 = 0u; round < 10u; round += 1u) {
+		if (round > 0u) {
+			k += vec2<u32>(0x9E3779B9u, 0xBB67AE85u);
+		}
+		let p0 = mulhilo(0xD2511F53u, c.x);
+		let p1 = mulhilo(0xCD9E8D57u, c.z);
+		c = vec4<u32>(p1.x ^ c.y ^ k.x, p1.y, p0.x ^ c.w ^ k.y, p0.y);
+	}
+	return c;
 }
 
 
@@ -24,30 +41,34 @@ fn PCG(state: u32) -> u32 {
 // RNG
 //-------------------------------------------------------------------------
 
-fn UniformUintToFloat(u: u32) -> f32 {
-    return f32(u) * bitcast<f32>(0x2F800000u);
-}
+// Stream of the invocation: the counter is (voxel or particle slot, step, block of 4 draws, kernel)
+var<private> rng_key: vec2<u32>;
+var<private> rng_counter: vec4<u32>;
+var<private> rng_block: vec4<u32>;
+var<private> rng_used: u32;
 
+fn rng_init(seed: vec2<u32>, index: u32, step: u32, stream: u32) {
+	rng_key = seed;
+	rng_counter = vec4<u32>(index, step, 0u, stream);
+	rng_used = 4u;
+}
 
-fn UniformUint(_state: u32) -> u32{
-    // Xorshift: slower than LCG better distribution for long sequences
-	var state: u32 = _state ^ (_state << 13u);
-	state ^= (state >> 17u);
-	state ^= (state << 5u);
-    
-    // LCG: faster than Xorshift, but poorer distribution for long sequences
-    //const uint multiplier = 1664525u;
-	//const uint increment  = 1013904223u;
-	//state *= multiplier;
-    //state += increment;
-    
-    return state;
+fn next_random() -> u32 {
+	if (rng_used >= 4u) {
+		rng_block = philox4x32(rng_counter, rng_key);
+		rng_counter.z += 1u;
+		rng_used = 0u;
+	}
+	let word = rng_block[rng_used];
+	rng_used += 1u;
+	return word;
 }
 
-fn UniformFloat(state: u32) -> f32 {
-    return UniformUintToFloat(UniformUint(state));
+// Uniform in [0, 1), from the top 24 bits so that it is exact
+fn next_float() -> f32 {
+	return f32(next_random() >> 8u) / 16777216.0;
 }
 
 fn TestInclude() -> f32 {
 	return 0.5f;
-}
\ No newline at end of file
+}
@@ -11,6 +11,8 @@ struct Lattice {
 @group(0) @binding(2) var<uniform> unif: Uniforms;
 @group(0) @binding(3) var<storage> regions: array<u32>;
 @group(0) @binding(4) var<storage> diffusion_matrix: array<f32>;
+// Diffusion tensors and drift, see Simulation::build_transport_table
+@group(0) @binding(5) var<storage> transport: array<f32>;
 
 @group(1) @binding(0) var<storage> latticeSrc: Lattice;
 @group(1) @binding(1) var<storage, read_write> latticeDest: Lattice;
@@ -20,6 +22,8 @@ struct Lattice {
 
 @group(2) @binding(0) var <storage, read_write> concentrations: array<atomic<i32>>;
 @group(3) @binding(0) var <storage, read_write> concentrations_stat: array<atomic<i32>>;
+// Faces of the lattice that absorb each species, one bit per face: -x, +x, -y, +y, -z, +z
+@group(4) @binding(2) var<storage> absorbing: array<u32>;
 
 
 fn writeLatticeSite(idx_lattice: i32, value: u32) -> bool {
@@ -88,44 +92,29 @@ fn move_particle_site(particle: u32, volume_src: vec3<u32>, volume_dest: vec3<u3
 }
 
 
+// Removes a particle that diffuses out of the lattice through an absorbing face. Other faces reflect it
+fn leave_lattice(particle: u32, id_volume: vec3<u32>, idx_particle: u32, face: u32) -> f32 {
+    if ((absorbing[particle] & (1u << face)) == 0u) {
+        return 0.;
+    }
+    let idx_occupancy: i32 = get_index_occupancy(id_volume, params);
+    atomicSub(&occupancyDest[idx_occupancy], 1u);
+    atomicStore(&latticeDest.lattice[idx_particle], 0u);
+    atomicSub(&concentrations[idx_occupancy * i32(reaction_params.num_species + 1u) + i32(particle)], 1);
+    atomicSub(&concentrations_stat[particle], 1);
+    return 1.;
+}
+
 fn move_particle(particle: u32, i_movement: i32, id_volume: vec3<u32>, initial_index: u32) -> f32 {
-    var volume_dest: vec3<u32> = id_volume;
-    switch i_movement {
-        case 0: {
-            // - x
-            if (id_volume.x == 0u) { return 0.; }
-            volume_dest.x -= 1u;
-        }
-        case 1: {
-            // + x
-            if (id_volume.x == (params.res.x - 1u)) { return 0.; }
-            volume_dest.x += 1u;
-        }
-        case 2: {
-            // - y
-            if (id_volume.y == 0u) { return 0.; }
-            volume_dest.y -= 1u;
-        }
-        case 3: {
-            // + y
-            if (id_volume.y == (params.res.y - 1u)) { return 0.; }
-            volume_dest.y += 1u;
-        }
-        case 4: {
-            // - z
-            if (id_volume.z == 0u) { return 0.; }
-            volume_dest.z -= 1u;
-        }
-        case 5: {
-            // + z
-            if (id_volume.z == (params.res.z - 1u)) { return 0.; }
-            volume_dest.z += 1u;
-        }
-        default: {
-            return 1.;
-        }
+    // 6 is staying in the voxel
+    if (i_movement >= 6) {
+        return 1.;
+    }
+    let volume_dest = get_neighbour(id_volume, u32(i_movement), params);
+    if (volume_dest.x < 0) {
+        return leave_lattice(particle, id_volume, initial_index, u32(i_movement));
     }
-    move_particle_site(particle, id_volume, volume_dest, initial_index);
+    move_particle_site(particle, id_volume, vec3<u32>(volume_dest), initial_index);
     return 1.;
 }
 
@@ -135,58 +124,60 @@ fn probability_value(src_region: u32, dest_region: u32, particle: u32) -> f32 {
     return val * params.tau / (params.lambda * params.lambda);
 }
 
-fn create_probability_vector(volume_id: vec3<u32>, particle: u32, cumulative_probability: ptr<function, array<f32, 7>>) {
-    // Fix: probabilities should be equal in all directions
-    // var probability_vector: array<f32, 7>;
-    let src_region = regions[get_index_occupancy(volume_id, params)];
-    // -x
-    if (volume_id.x > 0u) {
-        let dest_region = regions[get_index_occupancy(vec3<u32>(volume_id.x - 1u, volume_id.y, volume_id.z), params)];
-        (*cumulative_probability)[0] = probability_value(src_region, dest_region, particle);
-    } else {
-        (*cumulative_probability)[0] = 0.;
-    }
-    
-    // +x
-    if (volume_id.x < (params.res.x - 1u)) {
-        let dest_region = regions[get_index_occupancy(vec3<u32>(volume_id.x + 1u, volume_id.y, volume_id.z), params)];
-        (*cumulative_probability)[1] = (*cumulative_probability)[0] + probability_value(src_region, dest_region, particle);
-    } else {
-        (*cumulative_probability)[1] = (*cumulative_probability)[0];
+// Drift velocity along an axis: the one of the region plus the drift field of the particle, if it has one
+fn transport_velocity(volume_id: vec3<u32>, region: u32, particle: u32, axis: u32) -> f32 {
+    let num_species = reaction_params.num_species + 1u;
+    let start_drift = 3u * params.n_regions * num_species;
+    var velocity = transport[start_drift + (region * num_species + particle) * 3u + axis];
+    let field = transport[2u * start_drift + particle];
+    if (field >= 0.) {
+        let voxels = params.res.x * params.res.y * params.res.z;
+        let idx_voxel = u32(get_index_occupancy(volume_id, params));
+        velocity += transport[2u * start_drift + num_species + (u32(field) * voxels + idx_voxel) * 3u + axis];
     }
+    return velocity;
+}
 
-    // -y
-    if (volume_id.y > 0u) {
-        let dest_region = regions[get_index_occupancy(vec3<u32>(volume_id.x, volume_id.y - 1u, volume_id.z), params)];
-        (*cumulative_probability)[2] = (*cumulative_probability)[1] + probability_value(src_region, dest_region, particle);
-    } else {
-        (*cumulative_probability)[2] = (*cumulative_probability)[1];
+// Probability to jump in a direction. Within a region, diffusion along the axis (its tensor if the particle has one)
+// plus drift along the direction. Keep in sync with transport::jump_probability
+fn jump_probability(volume_id: vec3<u32>, src_region: u32, dest_region: u32, particle: u32, direction: u32) -> f32 {
+    if (src_region != dest_region) {
+        return probability_value(src_region, dest_region, particle);
     }
-
-    // +y
-    if (volume_id.y < (params.res.y - 1u)) {
-        let dest_region = regions[get_index_occupancy(vec3<u32>(volume_id.x, volume_id.y + 1u, volume_id.z), params)];
-        (*cumulative_probability)[3] = (*cumulative_probability)[2] + probability_value(src_region, dest_region, particle);
-    } else {
-        (*cumulative_probability)[3] = (*cumulative_probability)[2];
+    let axis = direction / 2u;
+    var probability = probability_value(src_region, src_region, particle);
+    let tensor = transport[(src_region * (reaction_params.num_species + 1u) + particle) * 3u + axis];
+    if (tensor >= 0.) {
+        probability = tensor * params.tau / (params.lambda * params.lambda);
     }
+    let velocity = select(-1., 1., direction % 2u == 1u) * transport_velocity(volume_id, src_region, particle, axis);
+    return probability + max(velocity, 0.) * params.tau / params.lambda;
+}
 
-    // -z
-    if (volume_id.z > 0u) {
-        let dest_region = regions[get_index_occupancy(vec3<u32>(volume_id.x, volume_id.y, volume_id.z - 1u), params)];
-        (*cumulative_probability)[4] = (*cumulative_probability)[3] + probability_value(src_region, dest_region, particle);
-    } else {
-        (*cumulative_probability)[4] = (*cumulative_probability)[3];
+// Probability to diffuse out through a face of the lattice: 0 unless the face absorbs the particle, then as if the
+// region went on
+fn face_probability(volume_id: vec3<u32>, region: u32, particle: u32, face: u32) -> f32 {
+    if ((absorbing[particle] & (1u << face)) == 0u) {
+        return 0.;
     }
+    return jump_probability(volume_id, region, region, particle, face);
+}
 
-    // +z
-    if (volume_id.z < (params.res.z - 1u)) {
-        let dest_region = regions[get_index_occupancy(vec3<u32>(volume_id.x, volume_id.y, volume_id.z + 1u), params)];
-        (*cumulative_probability)[5] = (*cumulative_probability)[4] + probability_value(src_region, dest_region, particle);
-    } else {
-        (*cumulative_probability)[5] = (*cumulative_probability)[4];
+fn create_probability_vector(volume_id: vec3<u32>, particle: u32, cumulative_probability: ptr<function, array<f32, 7>>) {
+    // Fix: probabilities should be equal in all directions
+    let src_region = regions[get_index_occupancy(volume_id, params)];
+    var cumulative: f32 = 0.;
+    // -x, +x, -y, +y, -z, +z. Periodic axes wrap around
+    for (var direction: u32 = 0u; direction < 6u; direction += 1u) {
+        let volume_dest = get_neighbour(volume_id, direction, params);
+        if (volume_dest.x < 0) {
+            cumulative += face_probability(volume_id, src_region, particle, direction);
+        } else {
+            let dest_region = regions[get_index_occupancy(vec3<u32>(volume_dest), params)];
+            cumulative += jump_probability(volume_id, src_region, dest_region, particle, direction);
+        }
+        (*cumulative_probability)[direction] = cumulative;
     }
-    
     (*cumulative_probability)[6] = 1.;
 }
 
@@ -206,7 +197,6 @@ fn rdme(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let idx_occupancy = get_index_occupancy(global_id, params);
 
     let occupancy: u32 = occupancySrc[idx_occupancy];
-    var state: u32;
     var rand_number: f32;
 
     for (var i_part: u32 = idx_lattice; i_part < idx_lattice + occupancy; i_part += 1u) {
@@ -217,11 +207,13 @@ fn rdme(@builtin(global_invocation_id) global_id: vec3<u32>) {
         var p: array<f32, 7>;
         create_probability_vector(global_id, latticeSrc.lattice[i_part], &p);
 
-        state = PCG(unif.itime + X % Y + Z + u32(i_part));
-        rand_number = UniformFloat(state);
+        rng_init(params.seed, i_part, unif.frame_num, RDME_STREAM);
+        rand_number = next_float();
 
+        // First direction past the number: next_float gives exactly 0 for 1 word in 2^24, and with `>` it would take
+        // the first direction even without probability. Keep in sync with transport::select_jump
         var i: i32 = 0;
-        while (rand_number > p[i]) {
+        while (rand_number >= p[i]) {
             i += 1;
         }
                         
@@ -0,0 +1,148 @@
+//! Boundary conditions beyond reservoirs: clamped concentrations, constant fluxes into a region and
+//! absorbing faces of the lattice.
+//!
+//! Clamps and fluxes are applied by `cme.wgsl` to each voxel at the start of its step, before its reactions.
+//! Sinks act in `rdme.wgsl`: a particle next to an absorbing face diffuses out of it as if the region went on,
+//! and leaves the simulation. Faces without a sink reflect the particles, unless their axis is periodic or
+//! absorbing (see `AxisBoundary`).
+
+use serde::{Deserialize, Serialize};
+
+/// Kinds of rows of the boundary table of `cme.wgsl`. 0 is the empty first row.
+pub const CLAMP: u32 = 1;
+pub const FLUX: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum BoundaryCondition {
+    /// Every voxel of `region` holds `concentration` particles of `species` on average, redrawn each step
+    Clamp { species: String, region: String, concentration: f32 },
+    /// `rate` particles of `species` per second enter `region` through its voxels next to other regions or to
+    /// the faces of the lattice
+    Flux { species: String, region: String, rate: f32 },
+    /// Particles that diffuse out of the lattice through `faces` are removed. Without `species`, all of them
+    Sink {
+        faces: Vec<Face>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        species: Vec<String>,
+    },
+}
+
+/// What happens at the two faces of an axis of the lattice, set with `LatticeParams::set_boundaries`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AxisBoundary {
+    /// Particles bounce off the faces
+    #[default]
+    Reflecting,
+    /// Particles leaving through a face come back through the other, and voxels at both faces are neighbours
+    Periodic,
+    /// Particles leaving through either face are removed, like a sink of every species
+    Absorbing,
+}
+
+impl AxisBoundary {
+    /// Value in the lattice params of the shaders.
+    pub fn to_raw(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => AxisBoundary::Periodic,
+            2 => AxisBoundary::Absorbing,
+            _ => AxisBoundary::Reflecting,
+        }
+    }
+}
+
+/// A face of the lattice, `"-x"` or `"+x"` and so on in model files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Face {
+    #[serde(rename = "-x")]
+    XMin,
+    #[serde(rename = "+x")]
+    XMax,
+    #[serde(rename = "-y")]
+    YMin,
+    #[serde(rename = "+y")]
+    YMax,
+    #[serde(rename = "-z")]
+    ZMin,
+    #[serde(rename = "+z")]
+    ZMax,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [Face::XMin, Face::XMax, Face::YMin, Face::YMax, Face::ZMin, Face::ZMax];
+
+    /// Position of the face in the directions of the shaders: -x, +x, -y, +y, -z, +z.
+    pub fn index(&self) -> usize {
+        Face::ALL.iter().position(|face| face == self).unwrap()
+    }
+
+    pub fn axis(&self) -> usize {
+        self.index() / 2
+    }
+}
+
+impl BoundaryCondition {
+    /// Name of the condition in model files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BoundaryCondition::Clamp { .. } => "clamp",
+            BoundaryCondition::Flux { .. } => "flux",
+            BoundaryCondition::Sink { .. } => "sink",
+        }
+    }
+
+    /// Species the condition applies to. Empty for sinks that take every species.
+    pub fn species(&self) -> Vec<&str> {
+        match self {
+            BoundaryCondition::Clamp { species, .. } | BoundaryCondition::Flux { species, .. } => vec![species.as_str()],
+            BoundaryCondition::Sink { species, .. } => species.iter().map(String::as_str).collect(),
+        }
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        match self {
+            BoundaryCondition::Clamp { region, .. } | BoundaryCondition::Flux { region, .. } => Some(region),
+            BoundaryCondition::Sink { .. } => None,
+        }
+    }
+
+    /// Field of the condition and what is wrong with it, if anything. Names are checked by the simulation.
+    pub fn validate(&self) -> Result<(), (&'static str, &'static str)> {
+        match self {
+            BoundaryCondition::Clamp { concentration, .. } if *concentration < 0. => Err(("concentration", "must not be negative")),
+            BoundaryCondition::Flux { rate, .. } if *rate < 0. => Err(("rate", "must not be negative")),
+            BoundaryCondition::Sink { faces, .. } if faces.is_empty() => Err(("faces", "needs at least one face")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Neighbour of a voxel across a face, as `get_neighbour` in `functions.wgsl` finds it. `None` outside of the lattice;
+/// periodic axes wrap around.
+pub fn neighbour(voxel: [usize; 3], face: Face, res: [usize; 3], periodic: [bool; 3]) -> Option<[usize; 3]> {
+    let axis = face.axis();
+    let mut neighbour = voxel;
+    match face.index() % 2 {
+        0 if voxel[axis] > 0 => neighbour[axis] -= 1,
+        0 if periodic[axis] => neighbour[axis] = res[axis] - 1,
+        1 if voxel[axis] + 1 < res[axis] => neighbour[axis] += 1,
+        1 if periodic[axis] => neighbour[axis] = 0,
+        _ => return None,
+    }
+    Some(neighbour)
+}
+
+/// Whether a voxel of a region is at its boundary: next to a voxel of another region or to a face of the
+/// lattice that does not wrap around. `region_at` gives the region of a voxel. Keep in sync with `cme.wgsl`.
+pub fn at_boundary(voxel: [usize; 3], res: [usize; 3], periodic: [bool; 3], region_at: impl Fn([usize; 3]) -> u32) -> bool {
+    let region = region_at(voxel);
+    Face::ALL.iter().any(|face| match neighbour(voxel, *face, res, periodic) {
+        Some(neighbour) => region_at(neighbour) != region,
+        None => true,
+    })
+}
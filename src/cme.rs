@@ -1,14 +1,18 @@
-use crate::{lattice_params::Params,preprocessor::ShaderBuilder, CME_WORKGROUP_SIZE, statistics::StatisticsGroup};
+use crate::{lattice_params::Params,preprocessor::ShaderBuilder, random, solver::MAX_EVENTS, CME_WORKGROUP_SIZE, statistics::StatisticsGroup};
 
 
 pub struct CME {
     compute_pipeline: wgpu::ComputePipeline,
+    // Only built when there are surface reactions
+    surface_pipeline: Option<wgpu::ComputePipeline>,
 }
 
 impl CME {
     pub fn new( // Receive bind groups, build compute pipeline. Simulation is responsible for everything else
         bind_group_layouts: &Vec<wgpu::BindGroupLayout>,
         statistics: &StatisticsGroup,
+        max_reactions: usize,
+        surface_reactions: bool,
         device: &wgpu::Device,
     ) -> Self {
         let data_bind_group_layout = &bind_group_layouts[0];
@@ -16,7 +20,10 @@ impl CME {
         let reaction_bind_group_layout = &bind_group_layouts[2];
         let boundary_bind_group_layout = &bind_group_layouts[3];
 
-        let binding = ShaderBuilder::new("cme.wgsl").unwrap();
+        // Reactions start at index 1 of the propensity table
+        let mut definitions = vec![("MAX_REACTIONS", format!("{}u", max_reactions + 1)), ("MAX_EVENTS", format!("{}u", MAX_EVENTS))];
+        definitions.extend(random::stream_definitions());
+        let binding = ShaderBuilder::with_definitions("cme.wgsl", &definitions).unwrap();
         let shader_builder = binding.build();
         let compute_shader = device.create_shader_module(shader_builder);
 
@@ -37,8 +44,18 @@ impl CME {
             }
         );
 
+        let surface_pipeline = surface_reactions.then(|| device.create_compute_pipeline(
+            &wgpu::ComputePipelineDescriptor {
+                label: Some("CME surface reactions pipeline"),
+                layout: Some(&compute_pipeline_layout),
+                module: &compute_shader,
+                entry_point: "cme_surface",
+            }
+        ));
+
         CME { 
             compute_pipeline: compute_pipeline,
+            surface_pipeline,
         }
     }
 
@@ -75,6 +92,18 @@ impl CME {
             cpass.set_bind_group(3, statistics_bind_group, &[]);
             cpass.set_bind_group(4, boundaries_bind_group, &[]);
             cpass.dispatch_workgroups(xgroups, ygroups, zgroups);
-        }        
+        }
+
+        // Surface reactions, once the voxels are done with their own
+        if let Some(surface_pipeline) = &self.surface_pipeline {
+            let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("CME surface reactions") });
+            cpass.set_pipeline(surface_pipeline);
+            cpass.set_bind_group(0, data_bind_group, &[]);
+            cpass.set_bind_group(1, lattice_bind_group, &[]);
+            cpass.set_bind_group(2, simulation_bind_group, &[]);
+            cpass.set_bind_group(3, statistics_bind_group, &[]);
+            cpass.set_bind_group(4, boundaries_bind_group, &[]);
+            cpass.dispatch_workgroups(xgroups, ygroups, zgroups);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,248 @@
+//! Grammar of the reaction equations in model files.
+//!
+//! ```text
+//! equation := side ("->" | "<->") side [comment]
+//! side     := "0" | "∅" | term ("+" term)*
+//! term     := [coefficient] species ["@" region]
+//! comment  := "#" anything
+//! ```
+//!
+//! `0` and `∅` are the empty side of sources (`0 -> A`) and sinks (`A -> 0`). Coefficients are
+//! positive integers, `2 A` is the same as `A + A`. Species names start with a letter or `_` and
+//! go on with letters, digits, `_` or `'`. Region names follow the same rules: `L@background + R@membrane -> C@membrane`
+//! is a surface reaction between neighbouring voxels of the two regions (see `Simulation::add_reaction`).
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Term {
+    pub coefficient: u32,
+    pub species: String,
+    /// Column of the species name, starting at 1
+    pub column: usize,
+    /// Region after `@`, for surface reactions
+    pub region: Option<String>,
+}
+
+impl Term {
+    /// Species with its region, `L@membrane`, as `add_reaction` takes it.
+    pub fn name(&self) -> String {
+        match &self.region {
+            Some(region) => format!("{}@{}", self.species, region),
+            None => self.species.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equation {
+    pub reactants: Vec<Term>,
+    pub products: Vec<Term>,
+    /// `<->`: the reaction also happens from products to reactants
+    pub reversible: bool,
+}
+
+impl Equation {
+    /// Reactant names repeated by their coefficient, as `add_reaction` takes them.
+    pub fn reactant_names(&self) -> Vec<String> {
+        expand(&self.reactants)
+    }
+
+    pub fn product_names(&self) -> Vec<String> {
+        expand(&self.products)
+    }
+}
+
+fn expand(terms: &[Term]) -> Vec<String> {
+    terms.iter().flat_map(|term| std::iter::repeat_n(term.name(), term.coefficient as usize)).collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Column where the problem was found, starting at 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses one equation. Blank text or a comment alone is `None`.
+pub fn parse(text: &str) -> Result<Option<Equation>, ParseError> {
+    let mut chars = text.chars().collect::<Vec<char>>();
+    if let Some(comment) = chars.iter().position(|c| *c == '#') {
+        chars.truncate(comment);
+    }
+    let mut parser = Parser { chars, position: 0 };
+
+    parser.skip_whitespace();
+    if parser.peek().is_none() {
+        return Ok(None);
+    }
+    let reactants = parser.side()?;
+    let reversible = parser.arrow()?;
+    let products = parser.side()?;
+    if let Some(c) = parser.peek() {
+        return Err(parser.error(format!("unexpected `{}` after the products", c)));
+    }
+    Ok(Some(Equation { reactants, products, reversible }))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn column(&self) -> usize {
+        self.position + 1
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError { column: self.column(), message: message.into() }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn starts_with(&self, token: &str) -> bool {
+        token.chars().enumerate().all(|(i, c)| self.chars.get(self.position + i) == Some(&c))
+    }
+
+    fn side(&mut self) -> Result<Vec<Term>, ParseError> {
+        self.skip_whitespace();
+        let mut terms = Vec::new();
+        loop {
+            let column = self.column();
+            match self.term()? {
+                Some(term) => terms.push(term),
+                None if terms.is_empty() && self.peek() != Some('+') => return Ok(terms),
+                None => return Err(ParseError { column, message: "`0` and `∅` must be alone on their side".to_string() }),
+            }
+            self.skip_whitespace();
+            if self.peek() != Some('+') {
+                return Ok(terms);
+            }
+            self.position += 1;
+            self.skip_whitespace();
+        }
+    }
+
+    /// A term, or `None` for `0` and `∅`.
+    fn term(&mut self) -> Result<Option<Term>, ParseError> {
+        if self.peek() == Some('∅') {
+            self.position += 1;
+            self.skip_whitespace();
+            return Ok(None);
+        }
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        let digits = self.chars[start..self.position].iter().collect::<String>();
+        self.skip_whitespace();
+
+        let name_start = self.position;
+        while self.peek().is_some_and(|c| is_name_char(c, self.position == name_start)) {
+            self.position += 1;
+        }
+        let species = self.chars[name_start..self.position].iter().collect::<String>();
+        let region = if !species.is_empty() && self.peek() == Some('@') {
+            self.position += 1;
+            let region_start = self.position;
+            while self.peek().is_some_and(|c| is_name_char(c, self.position == region_start)) {
+                self.position += 1;
+            }
+            if self.position == region_start {
+                return Err(self.error("expected a region after `@`"));
+            }
+            Some(self.chars[region_start..self.position].iter().collect::<String>())
+        } else {
+            None
+        };
+
+        match (digits.as_str(), species.is_empty()) {
+            ("0", true) => {
+                self.skip_whitespace();
+                Ok(None)
+            },
+            ("", true) => Err(match self.peek() {
+                Some(c) => self.error(format!("expected a species, `0` or `∅`, found `{}`", c)),
+                None => self.error("expected a species, `0` or `∅`"),
+            }),
+            (_, true) => Err(self.error("expected a species after the coefficient")),
+            (_, false) => {
+                let coefficient = match digits.as_str() {
+                    "" => 1,
+                    digits => match digits.parse::<u32>() {
+                        Ok(coefficient) if coefficient > 0 => coefficient,
+                        _ => return Err(ParseError { column: start + 1, message: format!("`{}` is not a valid coefficient", digits) }),
+                    },
+                };
+                Ok(Some(Term { coefficient, species, column: name_start + 1, region }))
+            },
+        }
+    }
+
+    fn arrow(&mut self) -> Result<bool, ParseError> {
+        self.skip_whitespace();
+        for (arrow, reversible) in [("<->", true), ("->", false)] {
+            if self.starts_with(arrow) {
+                self.position += arrow.chars().count();
+                return Ok(reversible);
+            }
+        }
+        Err(match self.peek() {
+            Some(c) => self.error(format!("expected `+`, `->` or `<->`, found `{}`", c)),
+            None => self.error("expected `->` or `<->`"),
+        })
+    }
+}
+
+fn is_name_char(c: char, first: bool) -> bool {
+    c.is_alphabetic() || c == '_' || (!first && (c.is_numeric() || c == '\''))
+}
+
+/// Species and region of a name as `add_reaction` takes it, `L@membrane`.
+pub fn split_region(name: &str) -> (&str, Option<&str>) {
+    match name.split_once('@') {
+        Some((species, region)) => (species, Some(region)),
+        None => (name, None),
+    }
+}
+
+/// Regions of a surface reaction, whose terms name their region: the host region of the first reactant, and
+/// the neighbour region of the other terms if they are not all in the host. `None` if no term names a region.
+pub fn surface_regions<'a>(reactants: &[&'a str], products: &[&'a str]) -> Result<Option<(&'a str, Option<&'a str>)>, &'static str> {
+    let regions = reactants.iter().chain(products).map(|name| split_region(name).1).collect::<Vec<Option<&str>>>();
+    if regions.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+    if regions.iter().any(Option::is_none) {
+        return Err("either every term or none names a region");
+    }
+    let host = match reactants.first() {
+        Some(reactant) => split_region(reactant).1.unwrap(),
+        None => return Err("a surface reaction needs a reactant in its host region"),
+    };
+    let mut other = None;
+    for region in regions.into_iter().flatten().filter(|region| *region != host) {
+        match other {
+            Some(other) if other != region => return Err("a surface reaction spans two regions at most"),
+            _ => other = Some(region),
+        }
+    }
+    Ok(Some((host, other)))
+}
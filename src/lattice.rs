@@ -1,11 +1,11 @@
 use core::fmt;
 use std::collections::VecDeque;
 use rand::Rng;
+use rand_chacha::ChaCha8Rng;
 use tensor_wgpu::{Tensor4, Tensor3};
 use ndarray::prelude::*;
 use log::debug;
 
-use crate::{MAX_PARTICLES_SITE};
 use crate::lattice_params::Params;
 use crate::region::Regions;
 use crate::types::Particle;
@@ -27,7 +27,7 @@ impl Lattice {
         params: &Params,
     ) -> Self {
         let shape_3d = (params.res[0] as usize, params.res[1] as usize, params.res[2] as usize).f();
-        let shape_lattice = (params.res[0] as usize, params.res[1] as usize, params.res[2] as usize, MAX_PARTICLES_SITE as usize).f();
+        let shape_lattice = (params.res[0] as usize, params.res[1] as usize, params.res[2] as usize, params.max_particles_site as usize).f();
         let shape_concentrations = (params.res[0] as usize, params.res[1] as usize, params.res[2] as usize, 1).f();
 
         let particle_names: Vec<String> = vec![String::from("void")];
@@ -60,10 +60,14 @@ impl Lattice {
     }
 
 
-    pub fn init_random_particles_region(&mut self, particle: Particle, num_particles: u32, regions_idx_buffer: &Vec<u32>, is_reservoir: bool) {
+    /// Places `num_particles` at random sites of the region. Returns how many were placed, fewer when the region
+    /// runs out of room.
+    pub fn init_random_particles_region(&mut self, particle: Particle, num_particles: u32, regions_idx_buffer: &Vec<u32>, is_reservoir: bool, rng: &mut ChaCha8Rng) -> u32 {
         self.concentrations.enlarge_dimension(3, 0);
-        let mut rng = rand::thread_rng();
-        for _ in 0..num_particles {
+        if regions_idx_buffer.is_empty() {
+            return 0;
+        }
+        for placed in 0..num_particles {
             let mut i_ret = 0;
             while i_ret < 100 {
                 let position_idx = rng.gen_range(0..regions_idx_buffer.len());
@@ -91,26 +95,37 @@ impl Lattice {
 
             }
             if i_ret == 100 {
-                panic!("Could not add particle to region");
+                // Nearly full: any site with room, from a random one on
+                let start = rng.gen_range(0..regions_idx_buffer.len());
+                let found = (0..regions_idx_buffer.len()).any(|i| {
+                    let site = self.idx_to_site_usize(regions_idx_buffer[(start + i) % regions_idx_buffer.len()]);
+                    if is_reservoir { self.add_reservoir_site(site, particle).is_ok() } else { self.add_particle_site(site, particle).is_ok() }
+                });
+                if !found {
+                    return placed;
+                }
             }
         }
+        num_particles
     }
 
-    pub fn fill_region_particles(&mut self, particle: Particle, regions_idx_buffer: &Vec<u32>) {
+    /// One particle in each site of the region that has room. Returns how many were placed.
+    pub fn fill_region_particles(&mut self, particle: Particle, regions_idx_buffer: &Vec<u32>) -> u32 {
         self.concentrations.enlarge_dimension(3, 0);
+        let mut placed = 0;
         for position_idx in 0..regions_idx_buffer.len() {
             let position = regions_idx_buffer[position_idx];
 
             // Transform the position idx to the 3D coordinates
             let site = self.idx_to_site_usize(position);
-            match self.add_particle_site(site, particle) {
-                Ok(_) => continue,
-                Err(_) => panic!("Could not add particle to region"),
+            if self.add_particle_site(site, particle).is_ok() {
+                placed += 1;
             }
         }
+        placed
     }
 
-    pub fn init_random_walk_particles(&mut self, particle: Particle, total_length: f32, block_length: f32, radius: f32, region_idx: usize, regions: &Regions) -> Result<(), String> {
+    pub fn init_random_walk_particles(&mut self, particle: Particle, total_length: f32, block_length: f32, radius: f32, region_idx: usize, regions: &Regions, rng: &mut ChaCha8Rng) -> Result<(), String> {
         // The particles are added as a cylinder, in blocks of length block_length. Each block has a random direction with at most 75 degrees to the previous
         // If the outside of the region is reached, we step back a few steps (step_backwards) and try again
         self.concentrations.enlarge_dimension(3, 0);
@@ -121,10 +136,9 @@ impl Lattice {
         let voxel_size = self.lattice_params.get_voxel_size();
         let safety_radius = block_length * (step_backwards as f32) * 0.4;  // 0.4 is a safety factor
         let safety_radius_voxels = voxel_size.iter().map(|&x| (safety_radius / x) as usize).collect::<Vec<usize>>();
-        let site_us = regions.generate_boundary_aware(region_idx, voxel_size, &safety_radius_voxels);
+        let site_us = regions.generate_boundary_aware(region_idx, voxel_size, &safety_radius_voxels, rng);
         let mut site = [site_us[0] as u32, site_us[1] as u32, site_us[2] as u32];
-        let mut rng = rand::thread_rng();
-        let mut direction = random_direction_sphere(&mut rng);
+        let mut direction = random_direction_sphere(rng);
 
         // VecDeque to store the last steps_backwards sites
         let mut queue_sites: VecDeque<[u32; 3]> = VecDeque::new();
@@ -168,7 +182,7 @@ impl Lattice {
                     s.0
                 }
             };
-            direction = random_direction_sphere(&mut rng);
+            direction = random_direction_sphere(rng);
         }
         Ok(())
     }
@@ -278,7 +292,7 @@ impl Lattice {
     }
 
     fn add_particle_site(&mut self, site: [usize; 3], particle: Particle) -> Result<String, String> {
-        if self.occupancy[[site[0], site[1], site[2]]] == MAX_PARTICLES_SITE as u32 {
+        if self.occupancy[[site[0], site[1], site[2]]] == self.lattice_params.max_particles_site {
             return Err(String::from("Lattice site is full"));
         }
         let lattice_index = (site[0], site[1], site[2], self.occupancy[[site[0], site[1], site[2]]] as usize);
@@ -337,7 +351,7 @@ impl Lattice {
         [i, j, k]
     }
 
-    fn idx_to_site_usize(&self, position: u32) -> [usize; 3] {
+    pub fn idx_to_site_usize(&self, position: u32) -> [usize; 3] {
         let pos_3d = self.idx_to_site(position);
         pos_3d.iter().map(|&x| x as usize).collect::<Vec<usize>>().try_into().unwrap()
     }
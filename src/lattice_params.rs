@@ -5,7 +5,11 @@ use std::io::{BufReader, prelude::*};
 use serde_json::Value;
 use std::error::Error;
 
-use crate::{lattice::Lattice, MAX_PARTICLES_SITE, utils::json_to_array};
+use crate::{lattice::Lattice, DEFAULT_MAX_PARTICLES_SITE};
+use crate::model::{self, ModelError, ParametersModel};
+use crate::reactions_params::ReactionSolver;
+use crate::boundary::AxisBoundary;
+use crate::random;
 
 // ---------------------------------------------------------------------------
 // Structures that are shared between Rust and the compute/fragment shaders.
@@ -14,7 +18,7 @@ use crate::{lattice::Lattice, MAX_PARTICLES_SITE, utils::json_to_array};
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub struct Params {
     // The ordering here is very important. Vectors at the end of the struct because of padding issues
-    max_particles_site: u32,
+    pub max_particles_site: u32,
     pub n_regions: u32,
     lambda: f32,
     pub tau: f32,
@@ -22,6 +26,12 @@ pub struct Params {
     _padding: u32,
     pub res: [u32; 3],
     _padding2: u32,
+    // AxisBoundary of x, y and z
+    pub boundaries: [u32; 3],
+    _padding3: u32,
+    // Key of the random streams of the shaders, see `random` and `Simulation::set_seed`
+    pub seed: [u32; 2],
+    _padding4: [u32; 2],
 }
 
 impl Params {
@@ -66,14 +76,16 @@ impl LatticeParams {
             _padding: 0,
             res: resolution,
             _padding2: 0,
-            max_particles_site: MAX_PARTICLES_SITE as u32,
+            boundaries: [AxisBoundary::Reflecting.to_raw(); 3],
+            _padding3: 0,
+            seed: [0; 2],
+            _padding4: [0; 2],
+            max_particles_site: DEFAULT_MAX_PARTICLES_SITE,
             n_regions: 1,
             lambda: lambda,
             tau: tau
         };
 
-        println!("Lattice parameters: {}", MAX_PARTICLES_SITE as u32);
-
         LatticeParams {
             raw: lattice_params,
             param_buf: None,
@@ -113,6 +125,35 @@ impl LatticeParams {
         self.raw.n_regions -= 1;
     }
 
+    /// Particles that fit in a lattice site. The lattices of a `Simulation` are allocated with it, so it is set before.
+    pub fn set_max_particles_site(&mut self, max_particles_site: u32) {
+        assert!(max_particles_site > 0, "A lattice site needs room for at least one particle");
+        self.raw.max_particles_site = max_particles_site;
+    }
+
+    pub fn max_particles_site(&self) -> u32 {
+        self.raw.max_particles_site
+    }
+
+    /// What happens to particles at the faces of each axis. Every axis is reflecting by default.
+    pub fn set_boundaries(&mut self, boundaries: [AxisBoundary; 3]) {
+        self.raw.boundaries = boundaries.map(|boundary| boundary.to_raw());
+    }
+
+    pub fn boundaries(&self) -> [AxisBoundary; 3] {
+        self.raw.boundaries.map(AxisBoundary::from_raw)
+    }
+
+    /// Seed of the shaders. Their random numbers only depend on it, the step and the voxel or particle slot.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.raw.seed = random::seed_key(seed);
+    }
+
+    /// Axes that wrap around.
+    pub fn periodic(&self) -> [bool; 3] {
+        self.boundaries().map(|boundary| boundary == AxisBoundary::Periodic)
+    }
+
     pub fn res(&self) -> [u32; 3] {
         self.raw.res
     }
@@ -132,6 +173,15 @@ impl LatticeParams {
     pub fn get_voxel_size(&self) -> [f32; 3] {
         self.raw.get_voxel_size()
     }
+
+    pub fn lambda(&self) -> f32 {
+        self.raw.lambda
+    }
+
+    /// Volume of a voxel in m^3. `lambda` is the lattice spacing used for diffusion.
+    pub fn voxel_volume(&self) -> f64 {
+        (self.raw.lambda as f64).powi(3)
+    }
 }
 
 impl Params {
@@ -141,28 +191,35 @@ impl Params {
 }
 
 impl LatticeParams {
-    pub fn from_json_value(parameters: &Value) -> Self {
-        let dimensions = json_to_array::<f32>(&parameters["dimensions"]).unwrap();
-        let resolution = json_to_array::<u32>(&parameters["lattice_resolution"]).unwrap();
-        let lambda = parameters["lambda"].as_f64().unwrap() as f32;
-        let tau = parameters["tau"].as_f64().unwrap() as f32;
-
-
-        let lattice_params = Params {
-            dims: dimensions,
-            _padding: 0,
-            res: resolution,
-            _padding2: 0,
-            max_particles_site: MAX_PARTICLES_SITE as u32,
-            n_regions: 1,
-            lambda: lambda,
-            tau: tau
-        };
-        
-        LatticeParams {
-            raw: lattice_params,
-            param_buf: None,
+    pub fn from_model(parameters: &ParametersModel) -> Self {
+        let mut lattice_params = LatticeParams::new(parameters.dimensions, parameters.lattice_resolution, parameters.tau, parameters.lambda);
+        if let Some(max_particles_site) = parameters.max_particles_site {
+            lattice_params.set_max_particles_site(max_particles_site);
+        }
+        lattice_params.set_boundaries(parameters.axis_boundaries);
+        lattice_params
+    }
+
+    pub fn to_model(&self) -> ParametersModel {
+        ParametersModel {
+            lattice_resolution: self.raw.res,
+            dimensions: self.raw.dims,
+            tau: self.raw.tau,
+            lambda: self.raw.lambda,
+            max_particles_site: Some(self.raw.max_particles_site).filter(|max| *max != DEFAULT_MAX_PARTICLES_SITE),
+            axis_boundaries: self.boundaries(),
+            // Kept with the reactions, the random generator and the validation, `Simulation::to_model` fills them in
+            max_reactions: None,
+            solver: ReactionSolver::default(),
+            seed: None,
+            allow_fast_surface_reactions: false,
         }
     }
 
-}
\ No newline at end of file
+    pub fn from_json_value(parameters: &Value) -> Result<Self, ModelError> {
+        let mut parameters = parameters.clone();
+        model::resolve_parameters(&mut parameters)?;
+        let parameters: ParametersModel = model::from_value(parameters, "parameters")?;
+        Ok(LatticeParams::from_model(&parameters))
+    }
+}
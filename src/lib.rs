@@ -8,14 +8,19 @@ pub use texture::Texture;
 pub use renderer_3d::Render3D;
 pub use renderer_2d::Render2D;
 pub use region::{Cube, Sphere, RegionType};
+pub use model::{Model, ModelError, RateModel, ReactionModel};
+pub use rates::{ReactionRate, RateUnits, RateLaw};
+pub use schedule::RateSignal;
+pub use boundary::{AxisBoundary, BoundaryCondition, Face};
+pub use reactions_params::ReactionSolver;
 // pub use statistics::StatisticContainer;
 
 // type Result<T> = std::result::Result<T, Error>;
 
-const MAX_PARTICLES_SITE: usize = 8;  // Never larger than 16
+const DEFAULT_MAX_PARTICLES_SITE: u32 = 8;
 const RDME_WORKGROUP_SIZE: (u32, u32, u32) = (1, 1, 1);
 const CME_WORKGROUP_SIZE: (u32, u32, u32) = (2, 1, 1);
-const MAX_REACTIONS: usize = 100;
+const DEFAULT_MAX_REACTIONS: usize = 99;
 
 mod simulation;
 mod lattice;
@@ -34,4 +39,15 @@ mod renderer_3d;
 mod renderer_2d;
 mod region;
 mod macros;
-pub mod statistics;
\ No newline at end of file
+pub mod statistics;
+pub mod model;
+pub mod sbml;
+pub mod rates;
+pub mod schedule;
+pub mod boundary;
+pub mod transport;
+pub mod random;
+pub mod solver;
+pub mod validation;
+pub mod equation;
+pub mod sweep;
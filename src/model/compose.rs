@@ -0,0 +1,263 @@
+//! Includes and templates: building one model file out of several.
+//!
+//! `"include"` takes a path or a list of paths, relative to the including file. The included files are
+//! merged in order, then the including file itself:
+//!
+//! - `regions`, `particles`, `transitions` and `boundaries` are appended. A region or particle name can only be
+//!   defined once in the whole model.
+//! - `constants`, `parameters`, `rendering`, `reactions` and `templates` are merged key by key. Two
+//!   includes cannot give different values to the same key, but the including file can override them.
+//!
+//! A template is a set of regions, particles, transitions, boundaries and reactions with `${argument}`
+//! placeholders. Each entry of `"instances"` fills the placeholders and adds the result to the model:
+//!
+//! ```json
+//! "templates": {"cell": {"arguments": ["name", "r"], "regions": [
+//!     {"type": "sphere", "name": "${name}", "center": [0.4, 0.4, 0.4], "radius": "${r}", "base_diffusion_rate": 1e-14}]}},
+//! "instances": [{"template": "cell", "name": "small", "r": 0.1}, {"template": "cell", "name": "large", "r": "R - 0.1"}]
+//! ```
+//!
+//! A string that is just a placeholder takes the value of the argument, numbers included. Elsewhere the
+//! argument is pasted as text, so expressions given as arguments may need parentheses.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use super::ModelError;
+
+/// Sections merged key by key. Any other section is replaced.
+const MAP_SECTIONS: &[&str] = &["constants", "parameters", "rendering", "reactions", "templates"];
+const LIST_SECTIONS: &[&str] = &["regions", "particles", "transitions", "boundaries"];
+/// What a template can define.
+const TEMPLATE_SECTIONS: &[&str] = &["regions", "particles", "transitions", "boundaries", "reactions"];
+
+pub(crate) fn read_file(path: &Path) -> Result<Value, ModelError> {
+    let mut buff = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut buff))
+        .map_err(|source| ModelError::Io { file: path.to_path_buf(), source })?;
+    serde_json::from_str(&buff).map_err(ModelError::Syntax)
+}
+
+/// Expands the includes and instances of a model. `file` is where it was read from, if anywhere.
+pub(crate) fn compose(model: Value, file: Option<&Path>) -> Result<Value, ModelError> {
+    let model = match model {
+        Value::Object(model) => model,
+        // Not a model, the schema reports it
+        other => return Ok(other),
+    };
+    let mut files = Vec::new();
+    if let Some(file) = file {
+        files.push(canonical(file)?);
+    }
+    let mut composition = compose_file(model, file, &mut files)?;
+    composition.model.remove("templates");
+    Ok(Value::Object(composition.model))
+}
+
+fn canonical(file: &Path) -> Result<PathBuf, ModelError> {
+    file.canonicalize().map_err(|source| ModelError::Io { file: file.to_path_buf(), source })
+}
+
+/// `files` are the files being included, to catch files that include themselves.
+fn compose_file(mut model: Map<String, Value>, file: Option<&Path>, files: &mut Vec<PathBuf>) -> Result<Composition, ModelError> {
+    let directory = file.and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+    let here = file.map_or("this file".to_string(), |file| format!("`{}`", file.display()));
+    let mut composition = Composition::default();
+
+    let includes = match model.remove("include") {
+        None => Vec::new(),
+        Some(Value::String(include)) => vec![("include".to_string(), include)],
+        Some(Value::Array(includes)) => includes.into_iter().enumerate().map(|(i, include)| match include {
+            Value::String(include) => Ok((format!("include[{}]", i), include)),
+            _ => Err(ModelError::invalid(format!("include[{}]", i), "expected the path of a model file")),
+        }).collect::<Result<_, _>>()?,
+        Some(_) => return Err(ModelError::invalid("include", "expected a path or a list of paths")),
+    };
+    for (path, include) in includes {
+        let included = directory.join(&include);
+        let canonical = canonical(&included)?;
+        if files.contains(&canonical) {
+            return Err(ModelError::invalid(path, format!("`{}` includes itself", included.display())));
+        }
+        let value = match read_file(&included) {
+            Ok(Value::Object(value)) => value,
+            Ok(_) => return Err(ModelError::invalid(path, format!("`{}` is not a model", included.display()))),
+            Err(ModelError::Syntax(e)) => return Err(ModelError::invalid(path, format!("`{}` is not valid JSON: {}", included.display(), e))),
+            Err(e) => return Err(e),
+        };
+
+        files.push(canonical);
+        let included_composition = compose_file(value, Some(&included), files)?;
+        files.pop();
+        composition.merge(included_composition.model, &format!("`{}`", included.display()), &path, false)?;
+    }
+
+    let instances = model.remove("instances");
+    composition.merge(model, &here, "", true)?;
+
+    match instances {
+        None => (),
+        Some(Value::Array(instances)) => {
+            for (i, instance) in instances.iter().enumerate() {
+                let path = format!("instances[{}]", i);
+                let sections = composition.instantiate(instance, &path)?;
+                composition.merge(sections, &path, &path, false)?;
+            }
+        },
+        Some(_) => return Err(ModelError::invalid("instances", "expected a list of instances")),
+    }
+    Ok(composition)
+}
+
+#[derive(Default)]
+struct Composition {
+    model: Map<String, Value>,
+    /// Where each region, particle and key of the merged sections was defined, e.g. "region `interior`"
+    origins: HashMap<String, String>,
+}
+
+impl Composition {
+    /// Adds the sections of `origin`. Conflicts are reported at `path`; with `overrides`, the keys of merged
+    /// sections replace the ones already there instead of conflicting.
+    fn merge(&mut self, sections: Map<String, Value>, origin: &str, path: &str, overrides: bool) -> Result<(), ModelError> {
+        for (section, value) in sections {
+            let section_path = if path.is_empty() { section.clone() } else { path.to_string() };
+
+            if MAP_SECTIONS.contains(&section.as_str()) {
+                let entries = match value {
+                    Value::Object(entries) => entries,
+                    _ => return Err(ModelError::invalid(section_path, format!("`{}` must be an object", section))),
+                };
+                let merged = self.model.entry(section.clone()).or_insert_with(|| Value::Object(Map::new()));
+                let merged = merged.as_object_mut().expect("merged sections are objects");
+                for (key, value) in entries {
+                    let label = format!("`{}.{}`", section, key);
+                    if let Some(existing) = merged.get(&key) {
+                        if !overrides && *existing != value {
+                            return Err(ModelError::invalid(section_path, format!("{} is defined differently in {} and {}", label, self.origins[&label], origin)));
+                        }
+                    }
+                    self.origins.insert(label, origin.to_string());
+                    merged.insert(key, value);
+                }
+            } else if LIST_SECTIONS.contains(&section.as_str()) {
+                let entries = match value {
+                    Value::Array(entries) => entries,
+                    _ => return Err(ModelError::invalid(section_path, format!("`{}` must be a list", section))),
+                };
+                let merged = self.model.entry(section.clone()).or_insert_with(|| Value::Array(Vec::new()));
+                let merged = merged.as_array_mut().expect("merged sections are lists");
+                for (i, entry) in entries.into_iter().enumerate() {
+                    for label in defined_names(&section, &entry) {
+                        if let Some(first) = self.origins.insert(label.clone(), origin.to_string()) {
+                            let path = if path.is_empty() { format!("{}[{}]", section, i) } else { path.to_string() };
+                            return Err(ModelError::invalid(path, format!("{} is defined in {} and {}", label, first, origin)));
+                        }
+                    }
+                    merged.push(entry);
+                }
+            } else {
+                self.model.insert(section, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Regions, particles, transitions and reactions of one entry of `instances`.
+    fn instantiate(&self, instance: &Value, path: &str) -> Result<Map<String, Value>, ModelError> {
+        let mut arguments = match instance {
+            Value::Object(arguments) => arguments.clone(),
+            _ => return Err(ModelError::invalid(path, "expected an object with a `template` and its arguments")),
+        };
+        let name = match arguments.remove("template") {
+            Some(Value::String(name)) => name,
+            _ => return Err(ModelError::invalid(format!("{}.template", path), "expected the name of a template")),
+        };
+        let template = match self.model.get("templates").and_then(|templates| templates.get(&name)) {
+            Some(Value::Object(template)) => template,
+            Some(_) => return Err(ModelError::invalid(format!("{}.template", path), format!("template `{}` is not an object", name))),
+            None => return Err(ModelError::invalid(format!("{}.template", path), format!("unknown template `{}`", name))),
+        };
+
+        let declared = match template.get("arguments") {
+            None => Vec::new(),
+            Some(Value::Array(declared)) => declared.iter().filter_map(Value::as_str).collect::<Vec<&str>>(),
+            Some(_) => return Err(ModelError::invalid(path, format!("the arguments of template `{}` must be a list of names", name))),
+        };
+        for argument in &declared {
+            if !arguments.contains_key(*argument) {
+                return Err(ModelError::invalid(path, format!("missing argument `{}` of template `{}`", argument, name)));
+            }
+        }
+        if let Some(unknown) = arguments.keys().find(|argument| !declared.contains(&argument.as_str())) {
+            return Err(ModelError::invalid(format!("{}.{}", path, unknown), format!("template `{}` has no argument `{}`", name, unknown)));
+        }
+
+        let mut sections = Map::new();
+        for (section, value) in template.iter().filter(|(section, _)| *section != "arguments") {
+            if !TEMPLATE_SECTIONS.contains(&section.as_str()) {
+                return Err(ModelError::invalid(path, format!("template `{}` defines `{}`, templates can only define {}", name, section, TEMPLATE_SECTIONS.join(", "))));
+            }
+            let value = substitute(value, &arguments)
+                .map_err(|message| ModelError::invalid(path, format!("template `{}`: {}", name, message)))?;
+            sections.insert(section.clone(), value);
+        }
+        Ok(sections)
+    }
+}
+
+/// Names that an entry of a list section defines, labelled as in `Composition::origins`.
+fn defined_names(section: &str, entry: &Value) -> Vec<String> {
+    let (kind, keys): (&str, &[&str]) = match section {
+        "regions" => ("region", &["name", "shell_name", "interior_name"]),
+        "particles" => ("particle", &["name"]),
+        _ => return Vec::new(),
+    };
+    keys.iter()
+        .filter_map(|key| entry.get(key).and_then(Value::as_str))
+        .map(|name| format!("{} `{}`", kind, name))
+        .collect()
+}
+
+/// Fills the `${argument}` placeholders of a template, in values and in keys.
+fn substitute(value: &Value, arguments: &Map<String, Value>) -> Result<Value, String> {
+    Ok(match value {
+        Value::String(text) => {
+            let whole = text.strip_prefix("${").and_then(|text| text.strip_suffix('}'));
+            match whole.and_then(|argument| arguments.get(argument)) {
+                Some(argument) => argument.clone(),
+                None => Value::String(substitute_text(text, arguments)?),
+            }
+        },
+        Value::Array(values) => Value::Array(values.iter().map(|value| substitute(value, arguments)).collect::<Result<_, _>>()?),
+        Value::Object(fields) => Value::Object(fields.iter()
+            .map(|(key, value)| Ok((substitute_text(key, arguments)?, substitute(value, arguments)?)))
+            .collect::<Result<_, String>>()?),
+        other => other.clone(),
+    })
+}
+
+fn substitute_text(text: &str, arguments: &Map<String, Value>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..].find('}').ok_or_else(|| format!("unclosed `${{` in `{}`", text))? + start;
+        let argument = &rest[start + 2..end];
+        let value = match arguments.get(argument) {
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::Number(value)) => value.to_string(),
+            Some(_) => return Err(format!("argument `{}` is pasted in `{}`, it must be a string or a number", argument, text)),
+            None => return Err(format!("`{}` uses `${{{}}}`, which is not an argument", text, argument)),
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
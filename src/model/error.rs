@@ -0,0 +1,79 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Separator used to smuggle the path of a nested deserialization through a `serde` custom error.
+/// See `model::nested`.
+pub(crate) const NESTED_PATH_SEPARATOR: char = '\u{1f}';
+
+#[derive(Debug)]
+pub enum ModelError {
+    /// The model file could not be read or written.
+    Io { file: PathBuf, source: std::io::Error },
+    /// The file is not valid JSON.
+    Syntax(serde_json::Error),
+    /// A value does not have the type or shape the schema expects.
+    Schema { path: String, message: String },
+    /// The value is well formed but does not make sense for the model (unknown region, missing count...).
+    Invalid { path: String, message: String },
+    /// The model builds, but `Simulation::validate` found errors.
+    Validation(crate::validation::Validation),
+}
+
+impl ModelError {
+    pub fn invalid(path: impl Into<String>, message: impl Into<String>) -> Self {
+        ModelError::Invalid { path: path.into(), message: message.into() }
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            ModelError::Schema { path, .. } | ModelError::Invalid { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_path_error(error: serde_path_to_error::Error<serde_json::Error>, prefix: &str) -> Self {
+        // Nested deserializers encode their own path in front of the message. Unpack it.
+        let message = error.inner().to_string();
+        let mut parts = message.split(NESTED_PATH_SEPARATOR).collect::<Vec<&str>>();
+        let message = parts.pop().unwrap_or_default().to_string();
+
+        let mut path = prefix.to_string();
+        for segment in std::iter::once(error.path().to_string()).chain(parts.into_iter().map(String::from)) {
+            path = join_path(&path, &segment);
+        }
+        ModelError::Schema { path, message }
+    }
+}
+
+/// Joins two paths as printed by `serde_path_to_error` (`"."` is the root).
+pub(crate) fn join_path(base: &str, child: &str) -> String {
+    match (base, child) {
+        ("" | ".", _) => child.to_string(),
+        (_, "" | ".") => base.to_string(),
+        (_, _) if child.starts_with('[') => format!("{}{}", base, child),
+        (_, _) => format!("{}.{}", base, child),
+    }
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io { file, source } => write!(f, "could not access model file {}: {}", file.display(), source),
+            ModelError::Syntax(e) => write!(f, "model file is not valid JSON: {}", e),
+            ModelError::Schema { path, message } => write!(f, "{}: {}", path, message),
+            ModelError::Invalid { path, message } => write!(f, "{}: {}", path, message),
+            ModelError::Validation(validation) => write!(f, "{}", validation),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ModelError::Io { source, .. } => Some(source),
+            ModelError::Syntax(e) => Some(e),
+            ModelError::Validation(validation) => Some(validation),
+            _ => None,
+        }
+    }
+}
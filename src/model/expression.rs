@@ -0,0 +1,363 @@
+//! Constants and arithmetic expressions in model files.
+//!
+//! Wherever `parameters`, `regions` or `particles` expect a number, a string with an expression can
+//! be used instead. Expressions use the `constants` section and the lattice parameters:
+//!
+//! ```json
+//! "constants": {"D_cyto": 1.358e-14, "R": "dimensions[0] / 2 - 0.05"},
+//! "regions": [{"type": "sphere", "name": "cell", "center": ["R + 0.05", "R + 0.05", "R + 0.05"], "radius": "R",
+//!              "base_diffusion_rate": "D_cyto * 0.5"}]
+//! ```
+//!
+//! ```text
+//! sum     := product (("+" | "-") product)*
+//! product := unary (("*" | "/") unary)*
+//! unary   := "-" unary | power
+//! power   := atom ["^" unary]
+//! atom    := number | name ["[" index "]"] | "(" sum ")"
+//! ```
+//!
+//! Names can be used before they are defined, so constants can use parameters and the other way round.
+//! Everything is evaluated when the file is read: a `Model` only holds numbers.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+use crate::equation::ParseError;
+use super::ModelError;
+use super::error::join_path;
+
+/// Keys that hold names instead of numbers.
+const NAME_FIELDS: &[&str] = &["type", "name", "shell_name", "interior_name", "to_region", "solver", "axis_boundaries"];
+
+/// Replaces the expressions of a model file by their values, `constants` included.
+pub(crate) fn resolve(model: &mut Value) -> Result<(), ModelError> {
+    let object = match model.as_object_mut() {
+        Some(object) => object,
+        None => return Ok(()),
+    };
+    let mut scope = Scope::new(section(object, "constants"), section(object, "parameters"));
+
+    for key in ["constants", "parameters"] {
+        if let Some(Value::Object(fields)) = object.get_mut(key) {
+            for (name, value) in fields.iter_mut() {
+                // `parameters.solver` and `axis_boundaries` are names, constants can be called anything
+                if key == "parameters" && NAME_FIELDS.contains(&name.as_str()) {
+                    continue;
+                }
+                scope.resolve(value, &format!("{}.{}", key, name))?;
+            }
+        }
+    }
+    if let Some(Value::Array(regions)) = object.get_mut("regions") {
+        for (i, region) in regions.iter_mut().enumerate() {
+            scope.resolve(region, &format!("regions[{}]", i))?;
+        }
+    }
+    if let Some(Value::Array(particles)) = object.get_mut("particles") {
+        for (i, particle) in particles.iter_mut().enumerate() {
+            let path = format!("particles[{}]", i);
+            let fields = match particle.as_object_mut() {
+                Some(fields) => fields,
+                None => continue,
+            };
+            for key in ["count", "concentration"] {
+                if let Some(value) = fields.get_mut(key) {
+                    scope.resolve(value, &join_path(&path, key))?;
+                }
+            }
+            // The keys are region names, which could be anything
+            for key in ["diffusion_rate", "diffusion_tensor", "drift"] {
+                if let Some(Value::Object(rates)) = fields.get_mut(key) {
+                    for (region, value) in rates.iter_mut() {
+                        scope.resolve(value, &format!("{}.{}.{}", path, key, region))?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the expressions of a `parameters` section read on its own, without constants.
+pub(crate) fn resolve_parameters(parameters: &mut Value) -> Result<(), ModelError> {
+    let mut scope = Scope::new(Map::new(), parameters.as_object().cloned().unwrap_or_default());
+    if let Some(fields) = parameters.as_object_mut() {
+        for (name, value) in fields.iter_mut().filter(|(name, _)| !NAME_FIELDS.contains(&name.as_str())) {
+            scope.resolve(value, &format!("parameters.{}", name))?;
+        }
+    }
+    Ok(())
+}
+
+fn section(model: &Map<String, Value>, key: &str) -> Map<String, Value> {
+    match model.get(key) {
+        Some(Value::Object(fields)) => fields.clone(),
+        _ => Map::new(),
+    }
+}
+
+/// Integers are written as integers so that they can still be read as counts or resolutions.
+pub(crate) fn number(value: f64) -> Value {
+    if value.fract() == 0. && value.abs() < 2f64.powi(53) {
+        Value::from(value as i64)
+    } else {
+        Value::from(value)
+    }
+}
+
+struct Scope {
+    constants: Map<String, Value>,
+    parameters: Map<String, Value>,
+    /// Values already computed, by path
+    values: HashMap<String, f64>,
+    /// Paths being computed, to catch definitions that depend on themselves
+    pending: Vec<String>,
+}
+
+impl Scope {
+    fn new(constants: Map<String, Value>, parameters: Map<String, Value>) -> Self {
+        Scope { constants, parameters, values: HashMap::new(), pending: Vec::new() }
+    }
+
+    /// Evaluates the expressions in `value`, which can be a list or an object of them.
+    fn resolve(&mut self, value: &mut Value, path: &str) -> Result<(), ModelError> {
+        match value {
+            Value::String(_) => {
+                let result = self.evaluate(value, path)?;
+                *value = number(result);
+            },
+            Value::Array(values) => {
+                for (i, value) in values.iter_mut().enumerate() {
+                    self.resolve(value, &format!("{}[{}]", path, i))?;
+                }
+            },
+            Value::Object(fields) => {
+                for (key, value) in fields.iter_mut().filter(|(key, _)| !NAME_FIELDS.contains(&key.as_str())) {
+                    self.resolve(value, &join_path(path, key))?;
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Value of the number or expression found at `path`.
+    fn evaluate(&mut self, value: &Value, path: &str) -> Result<f64, ModelError> {
+        let text = match value {
+            Value::Number(number) => return Ok(number.as_f64().unwrap_or_default()),
+            Value::String(text) => text,
+            _ => return Err(ModelError::invalid(path, "expected a number or an expression")),
+        };
+        if let Some(value) = self.values.get(path) {
+            return Ok(*value);
+        }
+        if self.pending.iter().any(|pending| pending == path) {
+            return Err(ModelError::invalid(path, format!("circular definition: {} -> {}", self.pending.join(" -> "), path)));
+        }
+
+        let expression = Parser::parse(text).map_err(|e| ModelError::invalid(path, e.to_string()))?;
+        self.pending.push(path.to_string());
+        let result = self.compute(&expression, path);
+        self.pending.pop();
+
+        let result = result?;
+        if !result.is_finite() {
+            return Err(ModelError::invalid(path, format!("`{}` is {}", text, result)));
+        }
+        self.values.insert(path.to_string(), result);
+        Ok(result)
+    }
+
+    fn compute(&mut self, expression: &Expression, path: &str) -> Result<f64, ModelError> {
+        Ok(match expression {
+            Expression::Number(value) => *value,
+            Expression::Negate(expression) => -self.compute(expression, path)?,
+            Expression::Binary(operator, left, right) => {
+                let (left, right) = (self.compute(left, path)?, self.compute(right, path)?);
+                match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    '/' => left / right,
+                    _ => left.powf(right),
+                }
+            },
+            Expression::Variable { name, index, column } => {
+                let (value, definition) = self.definition(name, *index)
+                    .map_err(|message| ModelError::invalid(path, format!("column {}: {}", column, message)))?;
+                self.evaluate(&value, &definition)?
+            },
+        })
+    }
+
+    /// Value that `name` (or `name[index]`) refers to, and its path.
+    fn definition(&self, name: &str, index: Option<usize>) -> Result<(Value, String), String> {
+        let (value, path) = if let Some(value) = self.constants.get(name) {
+            (value, format!("constants.{}", name))
+        } else if let Some(value) = self.parameters.get(name) {
+            (value, format!("parameters.{}", name))
+        } else {
+            return Err(format!("unknown constant `{}`", name));
+        };
+        match (value, index) {
+            (Value::Array(values), Some(index)) => match values.get(index) {
+                Some(value) => Ok((value.clone(), format!("{}[{}]", path, index))),
+                None => Err(format!("`{}` has {} elements", name, values.len())),
+            },
+            (Value::Array(_), None) => Err(format!("`{}` is a list, use `{}[i]`", name, name)),
+            (_, Some(_)) => Err(format!("`{}` is not a list", name)),
+            (value, None) => Ok((value.clone(), path)),
+        }
+    }
+}
+
+enum Expression {
+    Number(f64),
+    Variable { name: String, index: Option<usize>, column: usize },
+    Negate(Box<Expression>),
+    Binary(char, Box<Expression>, Box<Expression>),
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn parse(text: &str) -> Result<Expression, ParseError> {
+        let mut parser = Parser { chars: text.chars().collect(), position: 0 };
+        let expression = parser.sum()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            Some(c) => Err(parser.error(format!("unexpected `{}`", c))),
+            None => Ok(expression),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError { column: self.position + 1, message: message.into() }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Takes the next character if it is one of `operators`.
+    fn operator(&mut self, operators: &[char]) -> Option<char> {
+        self.skip_whitespace();
+        let operator = self.peek().filter(|c| operators.contains(c))?;
+        self.position += 1;
+        Some(operator)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.error(format!("expected `{}`", c)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn sum(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.product()?;
+        while let Some(operator) = self.operator(&['+', '-']) {
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.product()?));
+        }
+        Ok(left)
+    }
+
+    fn product(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.unary()?;
+        while let Some(operator) = self.operator(&['*', '/']) {
+            left = Expression::Binary(operator, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ParseError> {
+        match self.operator(&['-']) {
+            Some(_) => Ok(Expression::Negate(Box::new(self.unary()?))),
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Expression, ParseError> {
+        let base = self.atom()?;
+        match self.operator(&['^']) {
+            Some(operator) => Ok(Expression::Binary(operator, Box::new(base), Box::new(self.unary()?))),
+            None => Ok(base),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expression, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let expression = self.sum()?;
+                self.expect(')')?;
+                Ok(expression)
+            },
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_alphabetic() || c == '_' => self.variable(),
+            Some(c) => Err(self.error(format!("expected a number or a name, found `{}`", c))),
+            None => Err(self.error("expected a number or a name")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Expression, ParseError> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.position += 1;
+        }
+        // Exponent, as in 1.358E-14
+        if self.peek().is_some_and(|c| c == 'e' || c == 'E') {
+            let sign = matches!(self.chars.get(self.position + 1), Some('+' | '-')) as usize;
+            if self.chars.get(self.position + 1 + sign).is_some_and(char::is_ascii_digit) {
+                self.position += 1 + sign;
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.position += 1;
+                }
+            }
+        }
+        let text = self.chars[start..self.position].iter().collect::<String>();
+        text.parse().map(Expression::Number)
+            .map_err(|_| ParseError { column: start + 1, message: format!("`{}` is not a number", text) })
+    }
+
+    fn variable(&mut self) -> Result<Expression, ParseError> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.position += 1;
+        }
+        let name = self.chars[start..self.position].iter().collect::<String>();
+
+        self.skip_whitespace();
+        let index = if self.peek() == Some('[') {
+            self.position += 1;
+            self.skip_whitespace();
+            let digits = self.position;
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.position += 1;
+            }
+            let index = self.chars[digits..self.position].iter().collect::<String>();
+            if index.is_empty() {
+                return Err(self.error("expected an index"));
+            }
+            self.expect(']')?;
+            Some(index.parse().map_err(|_| ParseError { column: digits + 1, message: format!("`{}` is not an index", index) })?)
+        } else {
+            None
+        };
+        Ok(Expression::Variable { name, index, column: start + 1 })
+    }
+}
@@ -0,0 +1,460 @@
+//! Typed schema of the model files in `saved_models/`.
+//!
+//! A file is first read into a [`Model`] and then applied to a `Simulation` with `Simulation::from_model`.
+//! Reading expands its includes and templates (see `compose`) and evaluates its expressions (see `expression`).
+//! Every error carries the JSON path of the offending value, e.g. `regions[0].internal_radius`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::{self, DeserializeOwned}, ser::SerializeMap};
+use serde_json::Value;
+
+use crate::rates::{RateLaw, RateUnits, ReactionRate};
+use crate::schedule::RateSignal;
+use crate::boundary::{AxisBoundary, BoundaryCondition};
+use crate::reactions_params::ReactionSolver;
+use crate::region::{RegionType, Cube, Sphere, SemiSphere, Cylinder, SphericalShell, CylindricalShell, Capsid, Sparse};
+
+mod compose;
+mod error;
+mod expression;
+
+pub use error::ModelError;
+pub(crate) use compose::{compose, read_file};
+pub(crate) use expression::{number, resolve_parameters};
+use error::NESTED_PATH_SEPARATOR;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Model {
+    /// Named values that the numbers of `parameters`, `regions` and `particles` can use, see `expression`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "entries")]
+    pub constants: Vec<(String, f64)>,
+    pub parameters: ParametersModel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rendering: Option<RenderingModel>,
+    #[serde(default)]
+    pub regions: Vec<RegionModel>,
+    #[serde(default)]
+    pub particles: Vec<ParticleModel>,
+    /// Transition rates between regions: the off-diagonal entries of the diffusion matrix.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transitions: Vec<TransitionModel>,
+    /// Clamped concentrations, fluxes into regions and absorbing faces, e.g. `{"sink": {"faces": ["-x", "+x"]}}`.
+    /// See `BoundaryCondition`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub boundaries: Vec<BoundaryCondition>,
+    /// Reaction equations (`"2 A + B -> C"`, see `equation`) and their rates, in the order of the file.
+    /// A rate is a per-voxel number or has units, e.g. `{"value": 1e6, "units": "molar"}`. Reactions follow
+    /// mass action and happen in every region unless they say otherwise, see `ReactionModel`.
+    #[serde(default, with = "entries")]
+    pub reactions: Vec<(String, ReactionModel)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParametersModel {
+    pub lattice_resolution: [u32; 3],
+    pub dimensions: [f32; 3],
+    pub tau: f32,
+    pub lambda: f32,
+    /// Particles that fit in a lattice site, 8 if it is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_particles_site: Option<u32>,
+    /// Reactions the CME kernel can hold, 99 if it is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reactions: Option<u32>,
+    /// `"reflecting"`, `"periodic"` or `"absorbing"` for the x, y and z axes. Reflecting if it is not given
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub axis_boundaries: [AxisBoundary; 3],
+    /// `"single_event"`, `"ssa"` or `{"tau_leaping": {"threshold": 10}}`, see `ReactionSolver`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub solver: ReactionSolver,
+    /// Seed of the placement of particles and of the shaders. Runs with the same seed start from the same lattice, but
+    /// their trajectories can differ, see `Simulation::set_seed`; without it, one is drawn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Run surface reactions that would fire more than once per voxel and step, see
+    /// `Simulation::set_allow_fast_surface_reactions`
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub allow_fast_surface_reactions: bool,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderingModel {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for RenderingModel {
+    /// The window of the examples
+    fn default() -> Self {
+        RenderingModel { width: 1280, height: 720 }
+    }
+}
+
+/// A region of the model file: its shape (tagged by `"type"`) and the diffusion rate inside it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RegionModel {
+    #[serde(flatten)]
+    pub shape: RegionType,
+    pub base_diffusion_rate: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParticleModel {
+    pub name: String,
+    pub to_region: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concentration: Option<f32>,
+    #[serde(default)]
+    pub logging: bool,
+    #[serde(default)]
+    pub is_reservoir: bool,
+    /// Diffusion rate of this particle per region. Regions not listed keep their base rate.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub diffusion_rate: BTreeMap<String, f32>,
+    /// Diffusion rates along x, y and z per region, in place of its `diffusion_rate` there
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub diffusion_tensor: BTreeMap<String, [f32; 3]>,
+    /// Drift velocity in m/s per region
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub drift: BTreeMap<String, [f32; 3]>,
+    /// Drift velocity of each voxel, z fastest, then y and x. Added to the drift of its region
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drift_field: Vec<[f32; 3]>,
+}
+
+/// Rate at which particles move from one region into another. Without `species` it applies to every particle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransitionModel {
+    pub from: String,
+    pub to: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub species: Option<String>,
+    pub rate: f32,
+}
+
+impl Model {
+    /// Reads a model file. Its includes are found relative to it.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ModelError> {
+        let path = path.as_ref();
+        Model::from_json(compose::read_file(path)?, Some(path))
+    }
+
+    /// Builds a model from parsed JSON. Its includes are found relative to the working directory.
+    pub fn from_value(value: Value) -> Result<Self, ModelError> {
+        Model::from_json(value, None)
+    }
+
+    fn from_json(value: Value, file: Option<&Path>) -> Result<Self, ModelError> {
+        let mut value = compose::compose(value, file)?;
+        expression::resolve(&mut value)?;
+        from_value(value, "")
+    }
+
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ModelError> {
+        let path = path.as_ref();
+        serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|data| std::fs::write(path, data))
+            .map_err(|source| ModelError::Io { file: path.to_path_buf(), source })
+    }
+}
+
+impl FromStr for Model {
+    type Err = ModelError;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let value: Value = serde_json::from_str(data).map_err(ModelError::Syntax)?;
+        Model::from_value(value)
+    }
+}
+
+/// Deserializes `value` as `T`. `path` is the location of `value` inside the model file.
+pub(crate) fn from_value<T: DeserializeOwned>(value: Value, path: &str) -> Result<T, ModelError> {
+    serde_path_to_error::deserialize(value).map_err(|e| ModelError::from_path_error(e, path))
+}
+
+/// Deserializes a value that a hand-written `Deserialize` impl had to buffer. The buffering hides
+/// the inner path from `serde_path_to_error`, so it travels inside the error message instead and
+/// `ModelError::from_path_error` puts it back together. `field` is the key `value` was read from, if any.
+fn nested<T: DeserializeOwned, E: de::Error>(value: Value, field: &str) -> Result<T, E> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        E::custom(format!("{}{}{}{}{}", field, NESTED_PATH_SEPARATOR, e.path(), NESTED_PATH_SEPARATOR, e.inner()))
+    })
+}
+
+const REGION_TYPES: &[&str] = &["cube", "sphere", "semi_sphere", "cylinder", "spherical_shell", "cylindrical_shell", "capsid", "sparse"];
+
+impl<'de> Deserialize<'de> for RegionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Each shape is read through its own struct in `region.rs` so that errors point at the exact field
+        let mut fields = serde_json::Map::<String, Value>::deserialize(deserializer)?;
+        let kind: String = match fields.remove("type") {
+            Some(kind) => nested(kind, "type")?,
+            None => return Err(de::Error::missing_field("type")),
+        };
+        let fields = Value::Object(fields);
+
+        let region = match kind.as_str() {
+            "cube" => {
+                let Cube { name, p0, pf } = nested(fields, "")?;
+                RegionType::Cube { name, p0, pf }
+            },
+            "sphere" => {
+                let Sphere { name, center, radius } = nested(fields, "")?;
+                RegionType::Sphere { name, center, radius }
+            },
+            "semi_sphere" => {
+                let SemiSphere { name, center, radius, direction } = nested(fields, "")?;
+                RegionType::SemiSphere { name, center, radius, direction }
+            },
+            "cylinder" => {
+                let Cylinder { name, p0, pf, radius } = nested(fields, "")?;
+                RegionType::Cylinder { name, p0, pf, radius }
+            },
+            "spherical_shell" => {
+                let SphericalShell { shell_name, interior_name, center, internal_radius, external_radius } = nested(fields, "")?;
+                RegionType::SphericalShell { shell_name, interior_name, center, internal_radius, external_radius }
+            },
+            "cylindrical_shell" => {
+                let CylindricalShell { shell_name, interior_name, p0, pf, internal_radius, external_radius } = nested(fields, "")?;
+                RegionType::CylindricalShell { shell_name, interior_name, p0, pf, internal_radius, external_radius }
+            },
+            "capsid" => {
+                let Capsid { shell_name, interior_name, center, dir, internal_radius, external_radius, total_length } = nested(fields, "")?;
+                RegionType::Capsid { shell_name, interior_name, center, dir, internal_radius, external_radius, total_length }
+            },
+            "sparse" => {
+                let Sparse { name, to_region, max_volume, base_region } = nested(fields, "")?;
+                RegionType::Sparse { name, to_region, max_volume, base_region }
+            },
+            _ => return Err(de::Error::unknown_variant(&kind, REGION_TYPES)),
+        };
+        Ok(region)
+    }
+}
+
+impl<'de> Deserialize<'de> for RegionModel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = serde_json::Map::<String, Value>::deserialize(deserializer)?;
+        let base_diffusion_rate = match fields.remove("base_diffusion_rate") {
+            Some(rate) => nested(rate, "base_diffusion_rate")?,
+            None => return Err(de::Error::missing_field("base_diffusion_rate")),
+        };
+        let shape = RegionType::deserialize(Value::Object(fields)).map_err(de::Error::custom)?;
+        Ok(RegionModel { shape, base_diffusion_rate })
+    }
+}
+
+/// Rates of a reaction: `k`, or `[k_forward, k_reverse]` for reversible (`<->`) reactions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateModel {
+    Forward(ReactionRate),
+    Reversible(ReactionRate, ReactionRate),
+}
+
+impl From<ReactionRate> for RateModel {
+    fn from(rate: ReactionRate) -> Self {
+        RateModel::Forward(rate)
+    }
+}
+
+impl Serialize for RateModel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            RateModel::Forward(rate) => rate.serialize(serializer),
+            RateModel::Reversible(forward, reverse) => [forward, reverse].serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RateModel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        if value.is_array() {
+            let [forward, reverse]: [ReactionRate; 2] = nested(value, "")?;
+            Ok(RateModel::Reversible(forward, reverse))
+        } else {
+            Ok(RateModel::Forward(nested(value, "")?))
+        }
+    }
+}
+
+/// A reaction of the model file: its rates alone, or an object with them as `rate`, its rate law, the
+/// regions where it happens, whether its firings are logged and how its rate changes with time, e.g.
+/// `{"rate": 10, "law": {"michaelis_menten": {"km": 50}}, "regions": ["interior"], "logging": true}` or
+/// `{"rate": 0, "schedule": [{"step": {"time": 30, "value": 2}}]}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReactionModel {
+    pub rate: RateModel,
+    pub law: RateLaw,
+    /// Regions where the reaction happens. Empty for all of them.
+    pub regions: Vec<String>,
+    /// Log the firings of the reaction as `reaction:<equation>` statistics, in total and per region, see
+    /// `Simulation::logged_statistics`
+    pub logging: bool,
+    /// Changes of the rate with the simulated time, in the units of `rate`. Only for `->` reactions
+    pub schedule: Vec<RateSignal>,
+}
+
+impl From<RateModel> for ReactionModel {
+    fn from(rate: RateModel) -> Self {
+        ReactionModel { rate, law: RateLaw::MassAction, regions: Vec::new(), logging: false, schedule: Vec::new() }
+    }
+}
+
+impl From<ReactionRate> for ReactionModel {
+    fn from(rate: ReactionRate) -> Self {
+        RateModel::from(rate).into()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReactionObject {
+    rate: RateModel,
+    #[serde(default, skip_serializing_if = "is_default")]
+    law: RateLaw,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    regions: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    logging: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    schedule: Vec<RateSignal>,
+}
+
+impl Serialize for ReactionModel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.law == RateLaw::MassAction && self.regions.is_empty() && !self.logging && self.schedule.is_empty() {
+            return self.rate.serialize(serializer);
+        }
+        let ReactionModel { rate, law, regions, logging, schedule } = self.clone();
+        ReactionObject { rate, law, regions, logging, schedule }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ReactionModel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        // A rate with units is an object too, but it has no `rate`
+        if value.get("rate").is_some() {
+            let ReactionObject { rate, law, regions, logging, schedule } = nested(value, "")?;
+            Ok(ReactionModel { rate, law, regions, logging, schedule })
+        } else {
+            let rate: RateModel = nested(value, "")?;
+            Ok(rate.into())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateWithUnits {
+    value: f32,
+    units: RateUnits,
+}
+
+impl Serialize for ReactionRate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.units {
+            RateUnits::PerVoxel => self.value.serialize(serializer),
+            units => RateWithUnits { value: self.value, units }.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ReactionRate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        if value.is_object() {
+            let RateWithUnits { value, units } = nested(value, "")?;
+            Ok(ReactionRate { value, units })
+        } else {
+            Ok(ReactionRate::per_voxel(nested(value, "")?))
+        }
+    }
+}
+
+/// The base shape of a sparse region only carries its size, e.g. `{"type": "sphere", "radius": 0.02}`.
+/// Copies of it are scattered inside `to_region`, so it has no name or center of its own.
+pub(crate) mod sparse_base {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+    enum BaseShape {
+        Sphere { radius: f32 },
+    }
+
+    pub fn serialize<S: Serializer>(sphere: &Sphere, serializer: S) -> Result<S::Ok, S::Error> {
+        BaseShape::Sphere { radius: sphere.radius }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Sphere, D::Error> {
+        let BaseShape::Sphere { radius } = BaseShape::deserialize(deserializer)?;
+        Ok(Sphere { name: String::new(), center: [0.; 3], radius })
+    }
+}
+
+/// (De)serializes a JSON object as a list of `(key, value)` entries, keeping the order of the file.
+/// A missing or `null` object is an empty list.
+mod entries {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: Serialize>(entries: &[(String, T)], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+        for (key, value) in entries {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Vec<(String, T)>, D::Error> {
+        deserializer.deserialize_option(EntriesVisitor(PhantomData))
+    }
+
+    struct EntriesVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> de::Visitor<'de> for EntriesVisitor<T> {
+        type Value = Vec<(String, T)>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an object")
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(Vec::new())
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(Vec::new())
+        }
+
+        fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+            deserializer.deserialize_map(self)
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut entries = Vec::new();
+            while let Some((key, value)) = map.next_entry()? {
+                entries.push((key, value));
+            }
+            Ok(entries)
+        }
+    }
+}
@@ -5,6 +5,7 @@ use std::path::PathBuf;
 use ex;
 
 const INCLUDE_INSTRUCTION: &str = "//!include";
+const DEFINE_INSTRUCTION: &str = "//!define";
 
 pub struct ShaderBuilder {
 	/// String with the current WGSL source.
@@ -22,11 +23,19 @@ impl ShaderBuilder {
 	/// 	Code is generated recursively with attention to `include` and `define` statements.
 	/// 	See "Examples" for more details on include and macro functionality.
 	pub fn new(source_path: &str) -> Result<Self, ex::io::Error> {
+		Self::with_definitions(source_path, &[])
+	}
+
+	/// Creates a new [`ShaderBuilder`] where `definitions` replace the values of the `//!define NAME VALUE`
+	/// lines with the same name. Names without a `define` line are not replaced.
+	pub fn with_definitions(source_path: &str, definitions: &[(&str, String)]) -> Result<Self, ex::io::Error> {
 		let module_path = PathBuf::from(&source_path);
 		let base_path = PathBuf::from("res").join("shader");
+		let overrides = definitions.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
 		let source_string = Self::load_shader_module(
 			&base_path,
 			&module_path,
+			&overrides,
 		)?
 		.0;
 		Ok(Self {
@@ -53,6 +62,7 @@ impl ShaderBuilder {
 	fn load_shader_module(
 		base_path: &PathBuf,
 		module_path: &PathBuf,
+		overrides: &HashMap<String, String>,
 	) -> Result<(String, HashMap<String, String>), ex::io::Error> {
 		let module_source = ex::fs::read_to_string(base_path.join(module_path))?;
 		let mut module_string = String::new();
@@ -61,10 +71,16 @@ impl ShaderBuilder {
 			if line.starts_with(INCLUDE_INSTRUCTION) {
 				for include in line.split_whitespace().skip(1) {
 					let (included_module_string, included_definitions) =
-						Self::load_shader_module(base_path, &PathBuf::from(include))?;
+						Self::load_shader_module(base_path, &PathBuf::from(include), overrides)?;
 					module_string.push_str(&included_module_string);
 					definitions.extend(included_definitions);
 				}
+			} else if line.starts_with(DEFINE_INSTRUCTION) {
+				let mut tokens = line.split_whitespace().skip(1);
+				if let (Some(name), Some(value)) = (tokens.next(), tokens.next()) {
+					let value = overrides.get(name).map_or(value, |value| value.as_str());
+					definitions.insert(name.to_string(), value.to_string());
+				}
 			} else {
 				module_string.push_str(line);
 				module_string.push('\n');
@@ -0,0 +1,89 @@
+//! Counter-based random numbers shared by the shaders and the CPU.
+//!
+//! Philox4x32-10 (Salmon et al., "Parallel random numbers: as easy as 1, 2, 3", 2011) turns a 128-bit counter and a
+//! 64-bit key into 4 random words. Every kernel keys it with the seed of the simulation and counts with the voxel or
+//! particle slot, the step, the draw and the kernel, so no state is kept between steps and no two invocations share a
+//! stream. `random.wgsl` holds the same generator; `Stream` here draws the same numbers as a shader invocation.
+
+const PHILOX_M0: u32 = 0xD251_1F53;
+const PHILOX_M1: u32 = 0xCD9E_8D57;
+const PHILOX_W0: u32 = 0x9E37_79B9;
+const PHILOX_W1: u32 = 0xBB67_AE85;
+
+/// Streams of the kernels, the last word of the counter.
+pub const RDME_STREAM: u32 = 0;
+pub const CME_STREAM: u32 = 1;
+pub const SURFACE_STREAM: u32 = 2;
+
+/// The streams as `ShaderBuilder::with_definitions` takes them, for the kernels that include `random.wgsl`.
+pub fn stream_definitions() -> [(&'static str, String); 3] {
+    [("RDME_STREAM", format!("{}u", RDME_STREAM)), ("CME_STREAM", format!("{}u", CME_STREAM)), ("SURFACE_STREAM", format!("{}u", SURFACE_STREAM))]
+}
+
+/// High and low words of `a * b`, computed as `mulhilo` in `random.wgsl` does from 16-bit halves.
+pub fn mulhilo(a: u32, b: u32) -> (u32, u32) {
+    let (a_lo, a_hi) = (a & 0xffff, a >> 16);
+    let (b_lo, b_hi) = (b & 0xffff, b >> 16);
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let cross = (lo_lo >> 16) + (hi_lo & 0xffff) + (lo_hi & 0xffff);
+    let hi = a_hi * b_hi + (hi_lo >> 16) + (lo_hi >> 16) + (cross >> 16);
+    (hi, a.wrapping_mul(b))
+}
+
+/// The 4 words of Philox4x32 with 10 rounds for a counter and a key.
+pub fn philox4x32(counter: [u32; 4], key: [u32; 2]) -> [u32; 4] {
+    let mut counter = counter;
+    let mut key = key;
+    for round in 0..10 {
+        if round > 0 {
+            key = [key[0].wrapping_add(PHILOX_W0), key[1].wrapping_add(PHILOX_W1)];
+        }
+        let (hi0, lo0) = mulhilo(PHILOX_M0, counter[0]);
+        let (hi1, lo1) = mulhilo(PHILOX_M1, counter[2]);
+        counter = [hi1 ^ counter[1] ^ key[0], lo1, hi0 ^ counter[3] ^ key[1], lo0];
+    }
+    counter
+}
+
+/// Key of a seed, as the lattice params hold it.
+pub fn seed_key(seed: u64) -> [u32; 2] {
+    [seed as u32, (seed >> 32) as u32]
+}
+
+/// Uniform number in [0, 1) from the top 24 bits of a word, exact in an f32.
+pub fn to_unit_float(word: u32) -> f32 {
+    (word >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Numbers drawn by one invocation of a kernel in a step: blocks of 4 words, the third word of the counter numbering
+/// the blocks. Mirrors `rng_init` and `next_random` in `random.wgsl`.
+#[derive(Debug, Clone)]
+pub struct Stream {
+    key: [u32; 2],
+    counter: [u32; 4],
+    block: [u32; 4],
+    used: usize,
+}
+
+impl Stream {
+    /// `index` is the voxel or particle slot of the invocation and `stream` its kernel, e.g. `CME_STREAM`.
+    pub fn new(seed: u64, index: u32, step: u32, stream: u32) -> Self {
+        Stream { key: seed_key(seed), counter: [index, step, 0, stream], block: [0; 4], used: 4 }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        if self.used == 4 {
+            self.block = philox4x32(self.counter, self.key);
+            self.counter[2] = self.counter[2].wrapping_add(1);
+            self.used = 0;
+        }
+        self.used += 1;
+        self.block[self.used - 1]
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        to_unit_float(self.next_u32())
+    }
+}
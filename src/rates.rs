@@ -0,0 +1,246 @@
+//! Reaction rate constants and their conversion to the per-voxel coefficients used by `cme.wgsl`.
+//!
+//! The shader computes the propensity of a reaction in a voxel as `k` times the number of distinct
+//! sets of reactants among the particles of the voxel (see [`propensity`]), so `k` depends on the
+//! reaction order and on the voxel volume. Reactions with another [`RateLaw`] take `k` times the law
+//! instead, as long as their reactants are in the voxel (see [`law_propensity`]).
+
+use serde::{Deserialize, Serialize};
+
+pub const AVOGADRO: f64 = 6.022_140_76e23;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateUnits {
+    /// Used as it is by the shader. Plain numbers in model files are per-voxel rates.
+    #[default]
+    PerVoxel,
+    /// Macroscopic constant in M^(1-n) s^-1 for a reaction with n reactants (M^-1 s^-1 for bimolecular)
+    Molar,
+    /// Constant in (molecules / m^3)^(1-n) s^-1, i.e. m^3 s^-1 for bimolecular reactions
+    PerMolecule,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReactionRate {
+    pub value: f32,
+    pub units: RateUnits,
+}
+
+impl ReactionRate {
+    pub fn per_voxel(value: f32) -> Self {
+        ReactionRate { value, units: RateUnits::PerVoxel }
+    }
+
+    pub fn molar(value: f32) -> Self {
+        ReactionRate { value, units: RateUnits::Molar }
+    }
+
+    pub fn per_molecule(value: f32) -> Self {
+        ReactionRate { value, units: RateUnits::PerMolecule }
+    }
+
+    /// Per-voxel rate of a reaction with `order` reactants, in voxels of `voxel_volume` m^3.
+    pub fn to_lattice(&self, order: usize, voxel_volume: f64) -> f32 {
+        // Molecules in one voxel for one unit of concentration
+        let molecules = match self.units {
+            RateUnits::PerVoxel => return self.value,
+            RateUnits::Molar => AVOGADRO * voxel_volume * 1e3,
+            RateUnits::PerMolecule => voxel_volume,
+        };
+        (self.value as f64 / molecules.powi(order as i32 - 1)) as f32
+    }
+
+    /// Per-voxel rate of a reaction with these reactants, given as species indices with repetitions.
+    /// Rates with units describe `k * [A]^2` for `A + A`, while the shader counts `nA * (nA - 1) / 2`
+    /// pairs, so they also take the `symmetry_factor`. Per-voxel rates are used as they are.
+    pub fn stochastic_rate(&self, reactants: &[usize], voxel_volume: f64) -> f32 {
+        let k = self.to_lattice(reactants.len(), voxel_volume);
+        match self.units {
+            RateUnits::PerVoxel => k,
+            _ => (k as f64 * symmetry_factor(reactants)) as f32,
+        }
+    }
+}
+
+/// Product of `m!` over the species that appear `m` times among the reactants: 2 for `A + A`, 1 for `A + B`.
+pub fn symmetry_factor<T: PartialEq>(reactants: &[T]) -> f64 {
+    let mut factor = 1.;
+    for (i, species) in reactants.iter().enumerate() {
+        // The i-th copy of a species multiplies by i
+        factor *= reactants[..=i].iter().filter(|other| *other == species).count() as f64;
+    }
+    factor
+}
+
+/// How the propensity of a reaction depends on the particles of the voxel. Counts and thresholds are
+/// numbers of particles in the voxel, and the rate of the reaction multiplies the law.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum RateLaw {
+    /// `k` times the number of distinct sets of reactants
+    #[default]
+    MassAction,
+    /// `Vmax * S / (km + S)`, with the rate as `Vmax`. The substrate is the first reactant unless it is given.
+    MichaelisMenten {
+        km: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        substrate: Option<String>,
+    },
+    /// `k * x^n / (K^n + x^n)` for an activator `species`, `k * K^n / (K^n + x^n)` for a repressor
+    Hill {
+        species: String,
+        n: f32,
+        #[serde(rename = "K")]
+        half_saturation: f32,
+        #[serde(default)]
+        repressor: bool,
+    },
+    /// `k * values[i]`, where `i` is the number of `thresholds` that the count of `species` reaches
+    Piecewise {
+        species: String,
+        thresholds: Vec<f32>,
+        values: Vec<f32>,
+    },
+}
+
+/// Kinds of rate law in the first column of the rate law table of `cme.wgsl`
+pub const MASS_ACTION: u32 = 0;
+pub const MICHAELIS_MENTEN: u32 = 1;
+pub const HILL_ACTIVATOR: u32 = 2;
+pub const HILL_REPRESSOR: u32 = 3;
+pub const PIECEWISE: u32 = 4;
+
+impl RateLaw {
+    /// Name of the law in model files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RateLaw::MassAction => "mass_action",
+            RateLaw::MichaelisMenten { .. } => "michaelis_menten",
+            RateLaw::Hill { .. } => "hill",
+            RateLaw::Piecewise { .. } => "piecewise",
+        }
+    }
+
+    /// Species the law depends on, if it names one.
+    pub fn species(&self) -> Option<&str> {
+        match self {
+            RateLaw::MassAction => None,
+            RateLaw::MichaelisMenten { substrate, .. } => substrate.as_deref(),
+            RateLaw::Hill { species, .. } | RateLaw::Piecewise { species, .. } => Some(species),
+        }
+    }
+
+    /// Field of the law and what is wrong with it, if anything.
+    pub fn validate(&self) -> Result<(), (&'static str, &'static str)> {
+        match self {
+            RateLaw::MassAction => Ok(()),
+            RateLaw::MichaelisMenten { km, .. } if *km <= 0. => Err(("km", "must be positive")),
+            RateLaw::MichaelisMenten { .. } => Ok(()),
+            RateLaw::Hill { n, .. } if *n <= 0. => Err(("n", "must be positive")),
+            RateLaw::Hill { half_saturation, .. } if *half_saturation <= 0. => Err(("K", "must be positive")),
+            RateLaw::Hill { .. } => Ok(()),
+            RateLaw::Piecewise { thresholds, values, .. } => {
+                if values.len() != thresholds.len() + 1 {
+                    Err(("values", "needs one value more than `thresholds`"))
+                } else if thresholds.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    Err(("thresholds", "must be increasing"))
+                } else {
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// Row of the rate law table, `[kind, species, parameters...]`, for the law that depends on `species`.
+    /// Piecewise laws store the number of thresholds first, then `values[0]` and each threshold with its value.
+    pub fn to_lattice(&self, species: usize) -> Vec<f32> {
+        let mut row = vec![0., species as f32];
+        match self {
+            RateLaw::MassAction => (),
+            RateLaw::MichaelisMenten { km, .. } => {
+                row[0] = MICHAELIS_MENTEN as f32;
+                row.push(*km);
+            },
+            RateLaw::Hill { n, half_saturation, repressor, .. } => {
+                row[0] = (if *repressor { HILL_REPRESSOR } else { HILL_ACTIVATOR }) as f32;
+                row.extend([*n, *half_saturation]);
+            },
+            RateLaw::Piecewise { thresholds, values, .. } => {
+                row[0] = PIECEWISE as f32;
+                row.extend([thresholds.len() as f32, values[0]]);
+                for (threshold, value) in thresholds.iter().zip(&values[1..]) {
+                    row.extend([*threshold, *value]);
+                }
+            },
+        }
+        row
+    }
+}
+
+/// Propensity of a reaction in a voxel, as `cme.wgsl` computes it: `k` times the product over the reactant
+/// species of `C(n, m)`, with `n` particles of a species in the voxel and `m` copies among the reactants.
+/// `reactants` is a row of the reactions index, padded with species 0, and `counts` the particles per species.
+pub fn propensity(k: f32, reactants: &[u32], counts: &[i32]) -> f32 {
+    let mut propensity = k;
+    for (slot, species) in reactants.iter().enumerate() {
+        if *species == 0 {
+            break;
+        }
+        let taken = reactants[..slot].iter().filter(|previous| *previous == species).count() as i32;
+        propensity *= (counts[*species as usize] - taken).max(0) as f32 / (taken + 1) as f32;
+    }
+    propensity
+}
+
+/// Propensity of a reaction with a rate law, as `cme.wgsl` computes it. `law` is a row of the rate law table
+/// (see `RateLaw::to_lattice`), padded with zeros. Other laws than mass action replace the number of sets of
+/// reactants, but the reaction still needs its reactants.
+pub fn law_propensity(k: f32, reactants: &[u32], law: &[f32], counts: &[i32]) -> f32 {
+    let propensity = propensity(k, reactants, counts);
+    let kind = law[0] as u32;
+    if kind == MASS_ACTION || propensity <= 0. {
+        return propensity;
+    }
+    let x = counts[law[1] as usize] as f32;
+    let params = &law[2..];
+    k * match kind {
+        MICHAELIS_MENTEN => x / (params[0] + x),
+        HILL_ACTIVATOR | HILL_REPRESSOR => {
+            let ratio = (x / params[1]).powf(params[0]);
+            if kind == HILL_ACTIVATOR { ratio / (1. + ratio) } else { 1. / (1. + ratio) }
+        },
+        PIECEWISE => {
+            let steps = &params[2..2 + 2 * params[0] as usize];
+            steps.chunks(2).rev().find(|step| x >= step[0]).map_or(params[1], |step| step[1])
+        },
+        _ => 1.,
+    }
+}
+
+/// Propensity of a surface reaction in a voxel of its host region, as `cme.wgsl` computes it: `propensity` of the
+/// reactants in the voxel times the sum over `neighbours` of the sets of `neighbour_reactants` each of them has.
+/// `neighbours` are the counts of the adjacent voxels in the other region. If the reaction takes nothing from them,
+/// it only needs one of them to be there.
+pub fn surface_propensity(k: f32, reactants: &[u32], neighbour_reactants: &[u32], counts: &[i32], neighbours: &[&[i32]]) -> f32 {
+    if neighbours.is_empty() {
+        return 0.;
+    }
+    let host = propensity(k, reactants, counts);
+    if neighbour_reactants.iter().all(|species| *species == 0) {
+        return host;
+    }
+    host * neighbours.iter().map(|neighbour| propensity(1., neighbour_reactants, neighbour)).sum::<f32>()
+}
+
+impl From<f32> for ReactionRate {
+    fn from(value: f32) -> Self {
+        ReactionRate::per_voxel(value)
+    }
+}
+
+impl From<f64> for ReactionRate {
+    fn from(value: f64) -> Self {
+        ReactionRate::per_voxel(value as f32)
+    }
+}
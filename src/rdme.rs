@@ -1,4 +1,4 @@
-use crate::{lattice_params::Params,preprocessor::ShaderBuilder, RDME_WORKGROUP_SIZE, statistics::StatisticsGroup};
+use crate::{lattice_params::Params,preprocessor::ShaderBuilder, random, RDME_WORKGROUP_SIZE, statistics::StatisticsGroup};
 
 
 pub struct RDME {
@@ -14,15 +14,16 @@ impl RDME {
         let data_bind_group_layout = &bind_group_layouts[0];
         let lattice_bind_group_layout = &bind_group_layouts[1];
         let reaction_bind_group_layout = &bind_group_layouts[2];
+        let boundary_bind_group_layout = &bind_group_layouts[3];
 
-        let binding = ShaderBuilder::new("rdme.wgsl").unwrap();
+        let binding = ShaderBuilder::with_definitions("rdme.wgsl", &random::stream_definitions()).unwrap();
         let shader_builder = binding.build();
         let compute_shader = device.create_shader_module(shader_builder);
 
         let compute_pipeline_layout = device.create_pipeline_layout(
             &wgpu::PipelineLayoutDescriptor {
                 label: Some("RDME compute"),
-                bind_group_layouts: &[data_bind_group_layout, lattice_bind_group_layout, reaction_bind_group_layout, &statistics.bind_group_layout],
+                bind_group_layouts: &[data_bind_group_layout, lattice_bind_group_layout, reaction_bind_group_layout, &statistics.bind_group_layout, boundary_bind_group_layout],
                 push_constant_ranges: &[],
             }
         );
@@ -49,6 +50,7 @@ impl RDME {
         lattice_bind_group: &wgpu::BindGroup,
         simulation_bind_group: &wgpu::BindGroup,
         statistics_bind_group: &wgpu::BindGroup,
+        boundaries_bind_group: &wgpu::BindGroup,
         command_encoder: &mut wgpu::CommandEncoder,
         params: &Params,
     ) {
@@ -71,6 +73,7 @@ impl RDME {
             cpass.set_bind_group(1, lattice_bind_group, &[]);
             cpass.set_bind_group(2, simulation_bind_group, &[]);
             cpass.set_bind_group(3, statistics_bind_group, &[]);
+            cpass.set_bind_group(4, boundaries_bind_group, &[]);
             cpass.dispatch_workgroups(xgroups, ygroups, zgroups);
         }        
     }
@@ -1,7 +1,33 @@
 use wgpu::util::DeviceExt;
 use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
 use std::mem;
 
+/// How `cme.wgsl` advances the reactions of a voxel during one time step.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum ReactionSolver {
+    /// At most one reaction per voxel and step, with probability `1 - exp(-a0 * tau)`. Reactions faster
+    /// than `1 / tau` are under-counted.
+    #[default]
+    SingleEvent,
+    /// Exact Gillespie steps until the time step is over, `solver::MAX_EVENTS` at most.
+    Ssa,
+    /// Gillespie steps, but the rest of the time step is leaped over with Poisson numbers of events once
+    /// a voxel expects more than `threshold` of them.
+    TauLeaping { threshold: f32 },
+}
+
+impl ReactionSolver {
+    /// Field of the solver and what is wrong with it, if anything.
+    pub fn validate(&self) -> Result<(), (&'static str, &'static str)> {
+        match self {
+            ReactionSolver::TauLeaping { threshold } if threshold.is_nan() || *threshold <= 0. => Err(("threshold", "must be positive")),
+            _ => Ok(()),
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Structures that are shared between Rust and the compute/fragment shaders.
 
@@ -9,7 +35,17 @@ use std::mem;
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub struct Params {
     pub num_species: u32,
-    pub num_reactions: u32
+    pub num_reactions: u32,
+    /// Width of a row of the reactions index, the largest number of reactants of a reaction
+    pub reactant_slots: u32,
+    /// `ReactionSolver` as a number: 0 single event, 1 SSA, 2 tau-leaping
+    solver: u32,
+    leap_threshold: f32,
+    /// Width of a row of the rate law table
+    pub law_slots: u32,
+    /// Width of a row of the surface reaction table, and how many of its columns are reactants
+    pub surface_slots: u32,
+    pub surface_reactant_slots: u32,
 }
 
 // ---------------------------------------------------------------------------
@@ -23,12 +59,38 @@ impl ReactionParams {
     pub fn new(num_species: u32, num_reactions: u32) -> Self {
         let reaction_params = Params {
             num_species,
-            num_reactions
+            num_reactions,
+            reactant_slots: 3,
+            solver: 0,
+            leap_threshold: 0.,
+            law_slots: 2,
+            surface_slots: 3,
+            surface_reactant_slots: 1,
         };
 
-        ReactionParams {
+        let mut reaction_params = ReactionParams {
             raw_params: reaction_params,
             param_buf: None,
+        };
+        reaction_params.set_solver(ReactionSolver::default());
+        reaction_params
+    }
+
+    pub fn set_solver(&mut self, solver: ReactionSolver) {
+        let (code, threshold) = match solver {
+            ReactionSolver::SingleEvent => (0, 0.),
+            ReactionSolver::Ssa => (1, 0.),
+            ReactionSolver::TauLeaping { threshold } => (2, threshold),
+        };
+        self.raw_params.solver = code;
+        self.raw_params.leap_threshold = threshold;
+    }
+
+    pub fn solver(&self) -> ReactionSolver {
+        match self.raw_params.solver {
+            0 => ReactionSolver::SingleEvent,
+            2 => ReactionSolver::TauLeaping { threshold: self.raw_params.leap_threshold },
+            _ => ReactionSolver::Ssa,
         }
     }
 
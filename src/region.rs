@@ -2,11 +2,15 @@ use std::collections::HashMap;
 
 use tensor_wgpu::Tensor3;
 use rand::Rng;
+use rand_chacha::ChaCha8Rng;
 use ndarray::{prelude::*, StrideShape};
+use serde::{Deserialize, Serialize};
 
 use crate::{types::Region, debug_println};
 
-#[derive(Debug)]
+// Deserialization is implemented in `model` so that errors point at the offending field of each shape
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum RegionType {
     // Cube, Sphere and Cylinder are primitives, the rest are compositions
     Cube { name: String, p0: [f32; 3], pf: [f32; 3] },
@@ -16,16 +20,39 @@ pub enum RegionType {
     SphericalShell { shell_name: String, interior_name: String, center: [f32; 3], internal_radius: f32, external_radius: f32 },
     CylindricalShell { shell_name: String, interior_name: String, p0: [f32; 3], pf: [f32; 3], internal_radius: f32, external_radius: f32 },
     Capsid { shell_name: String, interior_name: String, center: [f32; 3], dir: [f32; 3], internal_radius: f32, external_radius: f32, total_length: f32 },
-    Sparse { name: String, base_region: Sphere },
+    Sparse {
+        name: String,
+        to_region: String,
+        max_volume: u32,
+        #[serde(with = "crate::model::sparse_base")]
+        base_region: Sphere,
+    },
 }
 
+impl RegionType {
+    /// Name of a primitive region. Compositions have none: they are split into primitives when added.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            RegionType::Cube { name, .. } |
+            RegionType::Sphere { name, .. } |
+            RegionType::SemiSphere { name, .. } |
+            RegionType::Cylinder { name, .. } |
+            RegionType::Sparse { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Cube {
     pub name: String,
     pub p0: [f32; 3],
     pub pf: [f32; 3],
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Sphere {
     pub name: String,
     pub center: [f32; 3],
@@ -33,6 +60,8 @@ pub struct Sphere {
 }
 
 #[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SemiSphere {
     pub name: String,
     pub center: [f32; 3],
@@ -41,6 +70,8 @@ pub struct SemiSphere {
 }
 
 #[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Cylinder {
     pub name: String,
     pub p0: [f32; 3],
@@ -49,6 +80,8 @@ pub struct Cylinder {
 }
 
 #[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SphericalShell {
     pub shell_name: String,
     pub interior_name: String,
@@ -58,6 +91,8 @@ pub struct SphericalShell {
 }
 
 #[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CylindricalShell {
     pub shell_name: String,
     pub interior_name: String,
@@ -68,6 +103,8 @@ pub struct CylindricalShell {
 }
 
 #[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Capsid {
     pub shell_name: String,
     pub interior_name: String,
@@ -79,8 +116,13 @@ pub struct Capsid {
 }
 
 #[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Sparse {
     pub name: String,
+    pub to_region: String,
+    pub max_volume: u32,
+    #[serde(with = "crate::model::sparse_base")]
     pub base_region: Sphere,
 }
 
@@ -147,7 +189,7 @@ impl Regions {
         self.index_buffer = Some(index_buffer);
     }
 
-    pub fn generate_boundary_aware(&self, region_idx: usize, voxel_size: [f32; 3], radius_voxels: &Vec<usize>) -> [usize; 3] {
+    pub fn generate_boundary_aware(&self, region_idx: usize, voxel_size: [f32; 3], radius_voxels: &Vec<usize>, rng: &mut ChaCha8Rng) -> [usize; 3] {
         // Generate a point inside the region. It is the center of a sphere. With this function, we make sure that the whole sphere fits inside the region.
         // Steps:
         // 1. Generate a random point inside the region.
@@ -157,7 +199,7 @@ impl Regions {
         let mut point: [usize; 3] = [0, 0, 0];
         let mut found = false;
         while retries < 10 && !found {
-            point = self.types[region_idx].generate_lattice(voxel_size);
+            point = self.types[region_idx].generate_lattice(voxel_size, rng);
             debug_println!("generate_boundary_aware: Point: {:?}, radius: {:?}\n", point, radius_voxels);
             if self.get_value_position(point) as usize != region_idx {
                 retries += 1;
@@ -197,23 +239,21 @@ impl Regions {
 }
 
 pub trait Random {
-    fn generate(&self) -> [f32; 3];
-    fn generate_lattice(&self, voxel_size: [f32; 3]) -> [usize; 3];
+    fn generate(&self, rng: &mut ChaCha8Rng) -> [f32; 3];
+    fn generate_lattice(&self, voxel_size: [f32; 3], rng: &mut ChaCha8Rng) -> [usize; 3];
 }
 
 impl Random for RegionType {
-    fn generate(&self) -> [f32; 3] {
+    fn generate(&self, rng: &mut ChaCha8Rng) -> [f32; 3] {
         use RegionType::*;
         match *&self {
             Cube { name: _, p0, pf } => {
-                let mut rng = rand::thread_rng();
                 let x = rng.gen_range(p0[0]..pf[0]); // Does this work? They are both &f32
                 let y = rng.gen_range(p0[1]..pf[1]);
                 let z = rng.gen_range(p0[2]..pf[2]);
                 [x, y, z]
             },
             Sphere { name: _, center, radius } => {
-                let mut rng = rand::thread_rng();
                 let theta = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
                 let phi = rng.gen_range(0.0..std::f32::consts::PI);
                 let r = rng.gen_range(0.0..*radius);
@@ -223,7 +263,6 @@ impl Random for RegionType {
                 [x, y, z]
             },
             SemiSphere { name: _, center, radius, direction: _ } => {
-                let mut rng = rand::thread_rng();
                 let theta = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
                 let phi = rng.gen_range(0.0..std::f32::consts::PI);
                 let r = rng.gen_range(0.0..*radius);
@@ -234,7 +273,6 @@ impl Random for RegionType {
             },
             Cylinder { name: _, p0, pf, radius } => {
                 //println!("Cylinder: {:?}", self);
-                let mut rng = rand::thread_rng();
                 let theta = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
                 let r = rng.gen_range(0.0..*radius);
                 let x = r * theta.cos() + p0[0];
@@ -246,8 +284,8 @@ impl Random for RegionType {
         }
     }
 
-    fn generate_lattice(&self, voxel_size: [f32; 3]) -> [usize; 3] {
-        let point = self.generate();
+    fn generate_lattice(&self, voxel_size: [f32; 3], rng: &mut ChaCha8Rng) -> [usize; 3] {
+        let point = self.generate(rng);
         [(point[0] / voxel_size[0]) as usize, (point[1] / voxel_size[1]) as usize, (point[2] / voxel_size[2]) as usize]
     }
 }
\ No newline at end of file
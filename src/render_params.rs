@@ -1,9 +1,8 @@
 use wgpu::util::DeviceExt;
 use bytemuck::{Pod, Zeroable};
 use std::mem;
-use serde_json::Value;
-use std::fs::File;
-use std::io::{prelude::*};
+
+use crate::model::{Model, ModelError};
 
 
 // ---------------------------------------------------------------------------
@@ -59,16 +58,14 @@ impl RenderParams {
     pub fn from_file<P: AsRef<std::path::Path>>(
         path: P,
         device: &wgpu::Device,
-    ) -> Result<Self, String> {
-        let mut file = File::open(path).unwrap();
-        let mut buff = String::new();
-        file.read_to_string(&mut buff).unwrap();
-     
-        let data: Value = serde_json::from_str(&buff).unwrap();
+    ) -> Result<Self, ModelError> {
+        let rendering = Model::from_file(path)?
+            .rendering
+            .ok_or_else(|| ModelError::invalid("rendering", "the model has no rendering section"))?;
 
         let dimensions = vec![
-            data["rendering"]["height"].as_u64().unwrap() as usize,
-            data["rendering"]["width"].as_u64().unwrap() as usize,
+            rendering.height as usize,
+            rendering.width as usize,
         ];
         println!("Dimensions: {:?}", dimensions);
         Ok(RenderParams::new(device, &dimensions))
@@ -0,0 +1,551 @@
+//! Reader for SBML Level 3 reaction networks.
+//!
+//! Only what the simulation can represent is read: compartments, species with an initial amount or
+//! concentration, and reactions with mass-action kinetic laws. Anything else (rules, events, other rate
+//! laws...) is listed in [`SbmlError::Unsupported`] instead of being dropped silently.
+//! Values are converted from the units of the document to molecules, litres and seconds, so the substance, extent,
+//! volume and time units must be given, as base units or definitions of one unit.
+//! The result is applied to a `Simulation` with `Simulation::add_sbml`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use roxmltree::Node;
+
+use crate::rates::AVOGADRO;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbmlModel {
+    pub compartments: Vec<SbmlCompartment>,
+    pub species: Vec<SbmlSpecies>,
+    pub reactions: Vec<SbmlReaction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbmlCompartment {
+    pub id: String,
+    pub name: Option<String>,
+    /// In litres
+    pub size: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbmlSpecies {
+    pub id: String,
+    pub compartment: String,
+    pub initial: InitialValue,
+    /// `boundaryCondition` or `constant` species. Reactions do not change them, like reservoirs.
+    pub is_reservoir: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InitialValue {
+    /// Number of molecules
+    Amount(f64),
+    /// In mol/L, placed as the number of molecules in the size of the compartment
+    Concentration(f64),
+}
+
+/// A mass-action reaction. Species appear once per unit of stoichiometry.
+/// Reversible SBML reactions are split in two, the reverse one named `<id>_reverse`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbmlReaction {
+    pub id: String,
+    pub reactants: Vec<String>,
+    pub products: Vec<String>,
+    /// Macroscopic constant in M^(1-n) s^-1 for n reactants, as `ReactionRate::molar`
+    pub rate: f64,
+}
+
+#[derive(Debug)]
+pub enum SbmlError {
+    Io { file: PathBuf, source: std::io::Error },
+    Xml(roxmltree::Error),
+    /// The document is not the SBML the reader expects (missing attribute, unknown species...).
+    Invalid { element: String, message: String },
+    /// Constructs the simulation cannot represent. All of them are listed, not only the first one.
+    Unsupported(Vec<String>),
+}
+
+impl SbmlError {
+    pub fn invalid(element: impl Into<String>, message: impl Into<String>) -> Self {
+        SbmlError::Invalid { element: element.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for SbmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SbmlError::Io { file, source } => write!(f, "could not read SBML file {}: {}", file.display(), source),
+            SbmlError::Xml(e) => write!(f, "SBML file is not valid XML: {}", e),
+            SbmlError::Invalid { element, message } => write!(f, "{}: {}", element, message),
+            SbmlError::Unsupported(constructs) => {
+                write!(f, "the SBML model uses constructs the simulation cannot represent:")?;
+                for construct in constructs {
+                    write!(f, "\n  - {}", construct)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::error::Error for SbmlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SbmlError::Io { source, .. } => Some(source),
+            SbmlError::Xml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl SbmlModel {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SbmlError> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(|source| SbmlError::Io { file: path.to_path_buf(), source })?
+            .parse()
+    }
+}
+
+impl FromStr for SbmlModel {
+    type Err = SbmlError;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let document = roxmltree::Document::parse(data).map_err(SbmlError::Xml)?;
+        let sbml = document.root_element();
+        if sbml.tag_name().name() != "sbml" {
+            return Err(SbmlError::invalid(sbml.tag_name().name(), "the root element must be <sbml>"));
+        }
+        let model = child(sbml, "model").ok_or_else(|| SbmlError::invalid("sbml", "there is no <model>"))?;
+
+        let mut reader = Reader { unsupported: Vec::new(), scales: Scales::default() };
+        let level = sbml.attribute("level").unwrap_or_default();
+        if level != "3" {
+            reader.unsupported.push(format!("SBML level {} (only level 3 is read)", level));
+        }
+        for list in ["listOfFunctionDefinitions", "listOfInitialAssignments", "listOfRules", "listOfConstraints", "listOfEvents"] {
+            for node in elements(model, list, None) {
+                let subject = ["id", "variable", "symbol"].iter().find_map(|key| node.attribute(*key));
+                reader.unsupported.push(match subject {
+                    Some(subject) => format!("{} `{}`", node.tag_name().name(), subject),
+                    None => node.tag_name().name().to_string(),
+                });
+            }
+        }
+
+        let units = Units::read(model)?;
+        let compartments = elements(model, "listOfCompartments", Some("compartment"))
+            .map(|node| reader.compartment(node, &units))
+            .collect::<Result<Vec<_>, SbmlError>>()?;
+        let species = elements(model, "listOfSpecies", Some("species"))
+            .map(|node| reader.species(node, &units, &compartments))
+            .collect::<Result<Vec<_>, SbmlError>>()?;
+        reader.scales.extent_per_time = units.size(model.attribute("extentUnits"), Dimension::Substance)
+            .zip(units.size(model.attribute("timeUnits"), Dimension::Time))
+            .map(|(extent, time)| extent / time);
+        let parameters = reader.parameters(elements(model, "listOfParameters", Some("parameter")))?;
+
+        let mut reactions = Vec::new();
+        for node in elements(model, "listOfReactions", Some("reaction")) {
+            reactions.extend(reader.reaction(node, &species, &compartments, &parameters)?);
+        }
+
+        if reader.unsupported.is_empty() {
+            Ok(SbmlModel { compartments, species, reactions })
+        } else {
+            Err(SbmlError::Unsupported(reader.unsupported))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dimension {
+    Substance,
+    Volume,
+    Time,
+}
+
+/// Unit definitions of the document that the reader understands: one base unit, scaled.
+struct Units {
+    /// Molecules, litres or seconds in one unit
+    definitions: HashMap<String, (Dimension, f64)>,
+    substance: Option<String>,
+    volume: Option<String>,
+}
+
+impl Units {
+    fn read(model: Node) -> Result<Units, SbmlError> {
+        let mut definitions = HashMap::new();
+        for definition in elements(model, "listOfUnitDefinitions", Some("unitDefinition")) {
+            let units = elements(definition, "listOfUnits", Some("unit")).collect::<Vec<Node>>();
+            if let [unit] = units.as_slice() {
+                let exponent = number(*unit, "exponent")?.unwrap_or(1.);
+                let factor = number(*unit, "multiplier")?.unwrap_or(1.) * 10f64.powf(number(*unit, "scale")?.unwrap_or(0.));
+                if let Some((dimension, size, base_exponent)) = base_unit(required(*unit, "kind")?) {
+                    if exponent == base_exponent {
+                        definitions.insert(required(definition, "id")?.to_string(), (dimension, size * factor.powf(exponent)));
+                    }
+                }
+            }
+        }
+        Ok(Units {
+            definitions,
+            substance: model.attribute("substanceUnits").map(String::from),
+            volume: model.attribute("volumeUnits").map(String::from),
+        })
+    }
+
+    /// Size of `unit` if it measures `dimension`.
+    fn size(&self, unit: Option<&str>, dimension: Dimension) -> Option<f64> {
+        let (found, size) = match base_unit(unit?) {
+            Some((found, size, 1.)) => (found, size),
+            _ => *self.definitions.get(unit?)?,
+        };
+        (found == dimension).then_some(size)
+    }
+}
+
+/// Dimension, size in molecules, litres or seconds and exponent of the SBML base units that the reader uses.
+fn base_unit(kind: &str) -> Option<(Dimension, f64, f64)> {
+    match kind {
+        "item" => Some((Dimension::Substance, 1., 1.)),
+        "mole" => Some((Dimension::Substance, AVOGADRO, 1.)),
+        "litre" => Some((Dimension::Volume, 1., 1.)),
+        "metre" => Some((Dimension::Volume, 1e3, 3.)),
+        "second" => Some((Dimension::Time, 1., 1.)),
+        _ => None,
+    }
+}
+
+/// What the units of the quantities in kinetic laws are worth, `None` where the document does not tell.
+#[derive(Default)]
+struct Scales {
+    /// Molecules per substance unit of each species, and whether the species is an amount rather than a
+    /// concentration in kinetic laws
+    species: HashMap<String, (Option<f64>, bool)>,
+    /// Litres per volume unit of each compartment
+    volumes: HashMap<String, Option<f64>>,
+    /// Molecules per extent unit over seconds per time unit, the units of kinetic laws
+    extent_per_time: Option<f64>,
+}
+
+/// Collects the unsupported constructs while the rest of the document is read.
+struct Reader {
+    unsupported: Vec<String>,
+    scales: Scales,
+}
+
+impl Reader {
+    fn compartment(&mut self, node: Node, units: &Units) -> Result<SbmlCompartment, SbmlError> {
+        let id = required(node, "id")?;
+        let volume = units.size(node.attribute("units").or(units.volume.as_deref()), Dimension::Volume);
+        self.scales.volumes.insert(id.to_string(), volume);
+        Ok(SbmlCompartment {
+            id: id.to_string(),
+            name: node.attribute("name").map(String::from),
+            size: number(node, "size")?.zip(volume).map(|(size, volume)| size * volume),
+        })
+    }
+
+    fn species(&mut self, node: Node, units: &Units, compartments: &[SbmlCompartment]) -> Result<SbmlSpecies, SbmlError> {
+        let id = required(node, "id")?;
+        let compartment = required(node, "compartment")?;
+        let element = format!("species `{}`", id);
+        let substance = units.size(node.attribute("substanceUnits").or(units.substance.as_deref()), Dimension::Substance);
+        let volume = self.scales.volumes.get(compartment).copied().flatten();
+        let size = compartments.iter().find(|c| c.id == compartment).and_then(|c| c.size);
+        self.scales.species.insert(id.to_string(), (substance, node.attribute("hasOnlySubstanceUnits") == Some("true")));
+
+        let initial = match (number(node, "initialAmount")?, number(node, "initialConcentration")?) {
+            (Some(amount), None) => match substance {
+                Some(substance) => InitialValue::Amount(amount * substance),
+                None => {
+                    self.unsupported.push(format!("{} has no substance units", element));
+                    InitialValue::Amount(amount)
+                },
+            },
+            (None, Some(concentration)) => match (substance.zip(volume), size) {
+                (Some((substance, volume)), Some(_)) => InitialValue::Concentration(concentration * (substance / AVOGADRO) / volume),
+                _ => {
+                    self.unsupported.push(format!("{} has a concentration, but no substance units or compartment size in litres", element));
+                    InitialValue::Concentration(concentration)
+                },
+            },
+            (Some(_), Some(_)) => return Err(SbmlError::invalid(element, "has both an initial amount and concentration")),
+            (None, None) => {
+                // Kept so that reactions still resolve it. The model is rejected anyway
+                self.unsupported.push(format!("species `{}` has no initial amount or concentration", id));
+                InitialValue::Amount(0.)
+            },
+        };
+        Ok(SbmlSpecies {
+            id: id.to_string(),
+            compartment: compartment.to_string(),
+            initial,
+            is_reservoir: node.attribute("boundaryCondition") == Some("true") || node.attribute("constant") == Some("true"),
+        })
+    }
+
+    fn parameters<'a, 'input: 'a>(&mut self, nodes: impl Iterator<Item = Node<'a, 'input>>) -> Result<HashMap<String, f64>, SbmlError> {
+        let mut parameters = HashMap::new();
+        for node in nodes {
+            let id = required(node, "id")?;
+            match number(node, "value")? {
+                Some(value) => { parameters.insert(id.to_string(), value); },
+                None => self.unsupported.push(format!("parameter `{}` has no value", id)),
+            }
+        }
+        Ok(parameters)
+    }
+
+    fn reaction(
+        &mut self,
+        node: Node,
+        species: &[SbmlSpecies],
+        compartments: &[SbmlCompartment],
+        parameters: &HashMap<String, f64>,
+    ) -> Result<Vec<SbmlReaction>, SbmlError> {
+        let id = required(node, "id")?;
+        let element = format!("reaction `{}`", id);
+        let unsupported = self.unsupported.len();
+        let reactants = self.side(node, "listOfReactants", species, &element)?;
+        let products = self.side(node, "listOfProducts", species, &element)?;
+
+        if node.attribute("fast") == Some("true") {
+            self.unsupported.push(format!("{} is fast", element));
+        }
+        let law = match child(node, "kineticLaw") {
+            Some(law) => law,
+            None => {
+                self.unsupported.push(format!("{} has no kinetic law", element));
+                return Ok(Vec::new());
+            },
+        };
+        let mut scope = parameters.clone();
+        for list in ["listOfLocalParameters", "listOfParameters"] {
+            scope.extend(self.parameters(elements(law, list, None))?);
+        }
+        let math = match child(law, "math").and_then(|math| math.children().find(Node::is_element)) {
+            Some(math) => Math::read(math),
+            None => Err("empty".to_string()),
+        };
+
+        let law = LawScope { species, compartments, parameters: &scope, scales: &self.scales };
+        let laws = match &math {
+            Ok(math) if node.attribute("reversible") == Some("true") => match math {
+                Math::Apply(op, args) if op == "minus" && args.len() == 2 => {
+                    law.mass_action(&args[0], &reactants).zip(law.mass_action(&args[1], &products))
+                        .map(|(forward, reverse)| vec![(forward, &reactants, &products), (reverse, &products, &reactants)])
+                },
+                _ => None,
+            },
+            Ok(math) => law.mass_action(math, &reactants).map(|forward| vec![(forward, &reactants, &products)]),
+            Err(construct) => {
+                self.unsupported.push(format!("{}: `{}` in the kinetic law", element, construct));
+                return Ok(Vec::new());
+            },
+        };
+        let rates = match laws {
+            Some(laws) => laws.into_iter()
+                .map(|((rate, volumes), reactants, products)| law.molar_rate(rate, &volumes, reactants, products))
+                .collect::<Result<Vec<f64>, &str>>(),
+            None => {
+                self.unsupported.push(format!("{} does not have a mass-action kinetic law", element));
+                return Ok(Vec::new());
+            },
+        };
+        let rates = match rates {
+            Ok(rates) => rates,
+            Err(message) => {
+                self.unsupported.push(format!("{}: {}", element, message));
+                return Ok(Vec::new());
+            },
+        };
+        if self.unsupported.len() > unsupported {
+            return Ok(Vec::new());
+        }
+
+        let mut reactions = vec![SbmlReaction { id: id.to_string(), reactants: reactants.clone(), products: products.clone(), rate: rates[0] }];
+        if let Some(rate) = rates.get(1) {
+            reactions.push(SbmlReaction { id: format!("{}_reverse", id), reactants: products, products: reactants, rate: *rate });
+        }
+        Ok(reactions)
+    }
+
+    /// Species of one side of a reaction, repeated as many times as their stoichiometry.
+    fn side(&mut self, node: Node, list: &str, species: &[SbmlSpecies], element: &str) -> Result<Vec<String>, SbmlError> {
+        let mut side = Vec::new();
+        for reference in elements(node, list, Some("speciesReference")) {
+            let name = required(reference, "species")?;
+            if !species.iter().any(|s| s.id == name) {
+                return Err(SbmlError::invalid(element, format!("unknown species `{}`", name)));
+            }
+            let stoichiometry = number(reference, "stoichiometry")?.unwrap_or(1.);
+            if stoichiometry < 1. || stoichiometry.fract() != 0. {
+                self.unsupported.push(format!("{} has stoichiometry {} for `{}`", element, stoichiometry, name));
+                continue;
+            }
+            side.extend(std::iter::repeat_n(name.to_string(), stoichiometry as usize));
+        }
+        Ok(side)
+    }
+}
+
+/// Subset of MathML found in kinetic laws.
+#[derive(Debug, Clone, PartialEq)]
+enum Math {
+    Number(f64),
+    Symbol(String),
+    Apply(String, Vec<Math>),
+}
+
+impl Math {
+    /// Reads a MathML element. The error is the name of the construct that could not be read.
+    fn read(node: Node) -> Result<Math, String> {
+        let text = || node.text().unwrap_or_default().trim();
+        match node.tag_name().name() {
+            "ci" => Ok(Math::Symbol(text().to_string())),
+            "cn" => {
+                let parts = node.children().filter(|n| n.is_text()).filter_map(|n| n.text()).map(str::trim).collect::<Vec<&str>>();
+                let value = match (node.attribute("type"), parts.as_slice()) {
+                    (Some("e-notation"), [mantissa, exponent]) => format!("{}e{}", mantissa, exponent).parse::<f64>().ok(),
+                    (Some("rational"), [numerator, denominator]) => numerator.parse::<f64>().ok().zip(denominator.parse::<f64>().ok()).map(|(n, d)| n / d),
+                    (_, [value]) => value.parse::<f64>().ok(),
+                    _ => None,
+                };
+                value.map(Math::Number).ok_or_else(|| format!("<cn>{}</cn>", parts.join(" ")))
+            },
+            "apply" => {
+                let mut children = node.children().filter(Node::is_element);
+                let op = children.next().ok_or("empty <apply>")?.tag_name().name().to_string();
+                let args = children.map(Math::read).collect::<Result<Vec<Math>, String>>()?;
+                Ok(Math::Apply(op, args))
+            },
+            other => Err(other.to_string()),
+        }
+    }
+
+    fn factors<'a>(&'a self, factors: &mut Vec<&'a Math>) {
+        match self {
+            Math::Apply(op, args) if op == "times" => args.iter().for_each(|arg| arg.factors(factors)),
+            _ => factors.push(self),
+        }
+    }
+}
+
+struct LawScope<'a> {
+    species: &'a [SbmlSpecies],
+    compartments: &'a [SbmlCompartment],
+    parameters: &'a HashMap<String, f64>,
+    scales: &'a Scales,
+}
+
+impl<'a> LawScope<'a> {
+    /// Rate constant of `math` if it is `k * S1 * S2 ...` over exactly the species in `reactants`, and the
+    /// compartments that are also factors, as SBML laws are written in amount per time.
+    fn mass_action<'m>(&self, math: &'m Math, reactants: &[String]) -> Option<(f64, Vec<&'m str>)> {
+        let mut factors = Vec::new();
+        math.factors(&mut factors);
+
+        let mut rate = 1.;
+        let mut volumes = Vec::new();
+        let mut orders = HashMap::<&str, usize>::new();
+        for factor in factors {
+            match factor {
+                Math::Number(value) => rate *= value,
+                Math::Symbol(name) if self.parameters.contains_key(name) => rate *= self.parameters[name],
+                Math::Symbol(name) if self.species.iter().any(|s| &s.id == name) => *orders.entry(name).or_default() += 1,
+                Math::Symbol(name) if self.compartments.iter().any(|c| &c.id == name) => volumes.push(name.as_str()),
+                Math::Apply(op, args) if op == "power" => match args.as_slice() {
+                    [Math::Symbol(name), Math::Number(exponent)]
+                        if self.species.iter().any(|s| &s.id == name) && *exponent >= 1. && exponent.fract() == 0. => {
+                        *orders.entry(name).or_default() += *exponent as usize;
+                    },
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+
+        let mut expected = HashMap::<&str, usize>::new();
+        for reactant in reactants {
+            *expected.entry(reactant).or_default() += 1;
+        }
+        (orders == expected).then_some((rate, volumes))
+    }
+
+    /// Constant in M^(1-n) s^-1 of the law `rate * V1 * ... * S1 * ... * Sn`, with `volumes` the compartments among
+    /// the factors. The law gives extent per time and each species is a concentration, or an amount if it has only
+    /// substance units. Dividing by the volume of the compartment of the reactants gives the change of concentration.
+    fn molar_rate(&self, rate: f64, volumes: &[&str], reactants: &[String], products: &[String]) -> Result<f64, &'static str> {
+        let compartment_of = |id: &String| self.species.iter().find(|s| &s.id == id).map(|s| s.compartment.as_str());
+        let side = if reactants.is_empty() { products } else { reactants };
+        let mut compartments = side.iter().filter_map(compartment_of);
+        let compartment = compartments.next().ok_or("the reaction has no species")?;
+        if compartments.any(|other| other != compartment) {
+            return Err("the reactants are in different compartments");
+        }
+
+        let units = "the units of the rate cannot be determined";
+        let volume = self.scales.volumes.get(compartment).copied().flatten().ok_or(units)?;
+        let size = self.compartments.iter().find(|c| c.id == compartment).and_then(|c| c.size);
+        let mut molar = rate * self.scales.extent_per_time.ok_or(units)? / AVOGADRO / volume;
+        // Powers of the size of the compartment, in its volume units
+        let mut power = -1;
+        for name in volumes {
+            if *name == compartment {
+                power += 1;
+            } else {
+                let other = self.compartments.iter().find(|c| c.id == *name).and_then(|c| c.size).ok_or(units)?;
+                molar *= other / self.scales.volumes.get(*name).copied().flatten().ok_or(units)?;
+            }
+        }
+        for reactant in reactants {
+            let (substance, is_amount) = self.scales.species[reactant];
+            // From a concentration in M to the units of the species in the law
+            molar *= AVOGADRO * volume / substance.ok_or(units)?;
+            if is_amount {
+                power += 1;
+            }
+        }
+        if power != 0 {
+            molar *= (size.ok_or(units)? / volume).powi(power);
+        }
+        Ok(molar)
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+/// Elements inside the list `list` of `node`, optionally only those called `name`.
+fn elements<'a, 'input: 'a>(node: Node<'a, 'input>, list: &'a str, name: Option<&'a str>) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    child(node, list)
+        .into_iter()
+        .flat_map(|list| list.children())
+        .filter(move |n| n.is_element() && name.is_none_or(|name| n.tag_name().name() == name))
+}
+
+fn required<'a>(node: Node<'a, '_>, attribute: &str) -> Result<&'a str, SbmlError> {
+    node.attribute(attribute).ok_or_else(|| {
+        let element = match node.attribute("id") {
+            Some(id) => format!("{} `{}`", node.tag_name().name(), id),
+            None => node.tag_name().name().to_string(),
+        };
+        SbmlError::invalid(element, format!("missing attribute `{}`", attribute))
+    })
+}
+
+fn number(node: Node, attribute: &str) -> Result<Option<f64>, SbmlError> {
+    match node.attribute(attribute) {
+        Some(value) => value.trim().parse::<f64>().map(Some).map_err(|_| {
+            SbmlError::invalid(node.tag_name().name(), format!("`{}` is not a number: {}", attribute, value))
+        }),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,113 @@
+//! Reaction rates that change with the simulated time.
+//!
+//! A reaction keeps its rate until the first of its signals starts. From then on its rate is the value of the
+//! signal that started last, so a step at 30 s followed by a ramp from 60 s switches the rate on and then ramps it.
+//! `Simulation::step` evaluates them at the start of each time step and uploads the rates that changed.
+
+use std::f32::consts::PI;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum RateSignal {
+    /// `value` from `time` on
+    Step { time: f32, value: f32 },
+    /// Linear from `from` at `start` to `to` at `end`, and `to` afterwards
+    Ramp { start: f32, end: f32, from: f32, to: f32 },
+    /// `high` during the first `duty` fraction of each `period` from `start`, and `low` the rest
+    Square {
+        #[serde(default)]
+        start: f32,
+        period: f32,
+        #[serde(default = "half")]
+        duty: f32,
+        low: f32,
+        high: f32,
+    },
+    /// `mean + amplitude * sin(2 pi (t - start) / period)`
+    Sine {
+        #[serde(default)]
+        start: f32,
+        period: f32,
+        mean: f32,
+        amplitude: f32,
+    },
+}
+
+fn half() -> f32 {
+    0.5
+}
+
+impl RateSignal {
+    /// Name of the signal in model files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RateSignal::Step { .. } => "step",
+            RateSignal::Ramp { .. } => "ramp",
+            RateSignal::Square { .. } => "square",
+            RateSignal::Sine { .. } => "sine",
+        }
+    }
+
+    /// Time at which the signal takes over the rate.
+    pub fn start(&self) -> f32 {
+        match self {
+            RateSignal::Step { time, .. } => *time,
+            RateSignal::Ramp { start, .. } | RateSignal::Square { start, .. } | RateSignal::Sine { start, .. } => *start,
+        }
+    }
+
+    /// Rate at `time`, once the signal has started.
+    pub fn value(&self, time: f32) -> f32 {
+        match self {
+            RateSignal::Step { value, .. } => *value,
+            RateSignal::Ramp { end, to, .. } if time >= *end => *to,
+            RateSignal::Ramp { start, end, from, to } => from + (to - from) * (time - start) / (end - start),
+            RateSignal::Square { start, period, duty, low, high } => {
+                let phase = ((time - start) / period).fract();
+                if phase < *duty { *high } else { *low }
+            },
+            RateSignal::Sine { start, period, mean, amplitude } => mean + amplitude * (2. * PI * (time - start) / period).sin(),
+        }
+    }
+
+    /// The same signal with its rates multiplied by `factor`, to convert them to per-voxel rates.
+    pub fn scaled(&self, factor: f32) -> Self {
+        match self.clone() {
+            RateSignal::Step { time, value } => RateSignal::Step { time, value: value * factor },
+            RateSignal::Ramp { start, end, from, to } => RateSignal::Ramp { start, end, from: from * factor, to: to * factor },
+            RateSignal::Square { start, period, duty, low, high } => {
+                RateSignal::Square { start, period, duty, low: low * factor, high: high * factor }
+            },
+            RateSignal::Sine { start, period, mean, amplitude } => {
+                RateSignal::Sine { start, period, mean: mean * factor, amplitude: amplitude * factor }
+            },
+        }
+    }
+
+    /// Field of the signal and what is wrong with it, if anything. Rates cannot become negative.
+    pub fn validate(&self) -> Result<(), (&'static str, &'static str)> {
+        match self {
+            RateSignal::Step { value, .. } if *value < 0. => Err(("value", "must not be negative")),
+            RateSignal::Ramp { start, end, .. } if end <= start => Err(("end", "must be after `start`")),
+            RateSignal::Ramp { from, to, .. } if *from < 0. || *to < 0. => Err(("from", "the rates must not be negative")),
+            RateSignal::Square { period, .. } | RateSignal::Sine { period, .. } if *period <= 0. => Err(("period", "must be positive")),
+            RateSignal::Square { duty, .. } if !(0. ..=1.).contains(duty) => Err(("duty", "must be between 0 and 1")),
+            RateSignal::Square { low, high, .. } if *low < 0. || *high < 0. => Err(("low", "the rates must not be negative")),
+            RateSignal::Sine { mean, amplitude, .. } if amplitude.abs() > *mean => Err(("amplitude", "must not be larger than `mean`")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rate at `time` of a reaction with rate `base` and these signals.
+pub fn rate_at(base: f32, signals: &[RateSignal], time: f32) -> f32 {
+    // On a tie, the signal listed last wins
+    signals.iter()
+        .filter(|signal| signal.start() <= time)
+        .fold(None, |last: Option<&RateSignal>, signal| match last {
+            Some(last) if last.start() > signal.start() => Some(last),
+            _ => Some(signal),
+        })
+        .map_or(base, |signal| signal.value(time))
+}
@@ -1,16 +1,16 @@
 use std::default;
 use std::fs::File;
-use std::collections::{VecDeque, HashMap};
+use std::collections::{VecDeque, HashMap, BTreeMap};
 use cgmath::num_traits::Pow;
 use std::io::{BufReader, prelude::*};
 use std::path::Path;
-use serde_json::{Result, Value};
 
-use log::{debug, info};
+use log::{debug, info, warn};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 use tensor_wgpu::{Tensor2, Tensor3, Tensor1};
-use wgpu::{util::DeviceExt};
 use ndarray::{prelude::*, StrideShape};
-use crate::MAX_PARTICLES_SITE;
+use crate::DEFAULT_MAX_REACTIONS;
 use crate::{
     rdme::RDME, 
     texture::Texture, 
@@ -19,10 +19,17 @@ use crate::{
     uniforms::UniformBuffer, types::{Region, Particle},
     preprocessor::ShaderBuilder,
     cme::CME,
-    reactions_params::ReactionParams,
+    reactions_params::{ReactionParams, ReactionSolver},
     statistics::{StatisticsGroup, SolverStatisticSample},
     region::{RegionType, Regions, Sphere},
-    utils::{json_to_array, split_whitespace}
+    model::{Model, ModelError, ParametersModel, RegionModel, ParticleModel, RenderingModel, TransitionModel, RateModel, ReactionModel},
+    sbml::{SbmlModel, SbmlError, InitialValue},
+    rates::{self, RateLaw, ReactionRate, AVOGADRO},
+    schedule::{self, RateSignal},
+    boundary::{self, AxisBoundary, BoundaryCondition, Face, CLAMP, FLUX},
+    transport,
+    validation::{Validation, Issue, MAX_REACTIONS_CAP, MAX_SURFACE_FIRINGS, MAX_TEXTURE_SPECIES},
+    equation,
 };
 
 
@@ -37,20 +44,74 @@ pub struct Simulation {
     statistics_groups: Option<StatisticsGroup>,
     
     regions: Regions,
+    // Regions and particles as they were declared, to write the simulation back as a model
+    region_declarations: Vec<RegionModel>,
+    particle_declarations: Vec<ParticleModel>,
+    // Particles left out because their region was full: name, region and how many, for `validate`
+    unplaced_particles: Vec<(String, String, u32)>,
+    // Not used by the simulation, kept so that a written model opens in the renderers
+    rendering: RenderingModel,
     diffusion_matrix: Tensor3<f32>,
     stoichiometry_matrix: Tensor2<i32>,
     reactions_idx: Tensor2<u32>,
     reaction_rates: Tensor2<f32>,
+    // Rows of [kind, species, parameters...], see rates::RateLaw::to_lattice
+    rate_laws: Tensor2<f32>,
+    // Rate law and regions of each reaction as they were declared, from reaction 1. No regions is all of them
+    reaction_laws: Vec<RateLaw>,
+    reaction_regions: Vec<Vec<String>>,
+    reaction_logging: Vec<bool>,
+    // Signals of each reaction, with per-voxel rates, and the rates on the GPU when some reaction has them
+    rate_schedules: Vec<Vec<RateSignal>>,
+    uploaded_rates: Vec<f32>,
+    // Neighbour side of each reaction between two regions, see `SurfaceReaction`
+    surface_reactions: Vec<Option<SurfaceReaction>>,
+    // Rows of [neighbour region + 1, host region, neighbour reactants..., change per species]. Built before the upload
+    surface_table: Tensor2<i32>,
+    // Clamps, fluxes and sinks as they were added. Before the upload they become rows of [kind, species, region,
+    // particles per voxel and step] and a mask of absorbing faces per species
+    boundary_conditions: Vec<BoundaryCondition>,
+    boundary_table: Tensor2<f32>,
+    absorbing_faces: Tensor1<u32>,
+    // Diffusion tensors and drift velocities per (particle, region), and drift fields per particle. Packed into one
+    // table for rdme.wgsl before the upload, see `build_transport_table`
+    diffusion_tensors: BTreeMap<(String, String), [f32; 3]>,
+    drifts: BTreeMap<(String, String), [f32; 3]>,
+    drift_fields: BTreeMap<String, Vec<[f32; 3]>>,
+    transport_table: Tensor1<f32>,
+    // 1 where a reaction (row) can happen in a region (column). Built from reaction_regions before the upload
+    reaction_mask: Tensor2<u32>,
     reaction_params: ReactionParams,
+    // Reactions that fit in the propensity table of cme.wgsl
+    max_reactions: usize,
     texture_compute_pipeline: Option<wgpu::ComputePipeline>,
+    validate_before_upload: bool,
+    allow_fast_surface_reactions: bool,
+    // Placement of particles and sparse regions draws from `rng`. The shaders get the seed through the lattice params.
+    // A drawn seed is not written back to models
+    seed: u64,
+    seed_given: bool,
+    rng: ChaCha8Rng,
+}
+
+/// Reaction between a voxel of its host region, the only region of the reaction tables, and a neighbour in another
+/// region. The terms of the neighbour are kept here as species indices, with repetitions.
+#[derive(Clone)]
+struct SurfaceReaction {
+    neighbour_region: String,
+    reactants: Vec<usize>,
+    products: Vec<usize>,
 }
 
 
 
 impl Simulation {
     pub fn new(
-        lattice_params: LatticeParams,
+        mut lattice_params: LatticeParams,
     ) -> Self {
+        let seed = rand::random::<u64>();
+        lattice_params.set_seed(seed);
+
         // A simulation has a lattice first
         let mut lattices = Vec::<Lattice>::new();
         for _ in 0..2 {
@@ -74,6 +135,8 @@ impl Simulation {
         let stoichiometry_matrix = Tensor2::<i32>::zeros((1, 1).f());
         let reactions_idx = Tensor2::<u32>::zeros((1, 3).f());
         let reaction_rates = Tensor2::<f32>::zeros((1, 1).f());
+        let rate_laws = Tensor2::<f32>::zeros((1, 2).f());
+        let reaction_mask = Tensor2::<u32>::zeros((1, 1).f());
 
         let bind_groups = Vec::<wgpu::BindGroup>::new();
 
@@ -84,25 +147,57 @@ impl Simulation {
             lattices,
             lattice_params,
             regions,
+            region_declarations: Vec::new(),
+            unplaced_particles: Vec::new(),
+            rendering: RenderingModel::default(),
+            particle_declarations: Vec::new(),
             diffusion_matrix,
             stoichiometry_matrix,
             reactions_idx,
             reaction_rates,
+            rate_laws,
+            reaction_laws: Vec::new(),
+            reaction_regions: Vec::new(),
+            reaction_logging: Vec::new(),
+            rate_schedules: Vec::new(),
+            uploaded_rates: Vec::new(),
+            surface_reactions: Vec::new(),
+            surface_table: Tensor2::<i32>::zeros((1, 3).f()),
+            boundary_conditions: Vec::new(),
+            boundary_table: Tensor2::<f32>::zeros((1, 4).f()),
+            absorbing_faces: Tensor1::<u32>::from_data(vec![0], StrideShape::from((1,))),
+            diffusion_tensors: BTreeMap::new(),
+            drifts: BTreeMap::new(),
+            drift_fields: BTreeMap::new(),
+            transport_table: Tensor1::<f32>::from_data(vec![0.], StrideShape::from((1,))),
+            reaction_mask,
             reaction_params,
+            max_reactions: DEFAULT_MAX_REACTIONS,
             texture_compute_pipeline: None,
+            validate_before_upload: true,
+            allow_fast_surface_reactions: false,
+            seed,
+            seed_given: false,
+            rng: ChaCha8Rng::seed_from_u64(seed),
             statistics_groups: None,
             stats: VecDeque::<SolverStatisticSample<i32>>::new()
         }
         
     }
 
+    /// Records a time step in `command_encoder`. Scheduled rates that change are written through `queue`, which
+    /// runs before the commands when they are submitted, so submit each step before recording the next one.
     pub fn step(
         &mut self,
         frame_num: u32,
         command_encoder: &mut wgpu::CommandEncoder,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         write_freq: u32
     ) {
+        // Scheduled rates change before the reactions of this step
+        self.upload_scheduled_rates(frame_num as f32 * self.lattice_params.raw.tau, queue);
+
         self.rdme.as_ref()
             .expect("RDME must be initialized first")
             .step(
@@ -110,6 +205,7 @@ impl Simulation {
                 &self.bind_groups[1 + (frame_num as usize % 2)],
                 &self.bind_groups[3],
                 &self.statistics_groups.as_ref().expect("").bind_group,
+                &self.bind_groups[4],
                 command_encoder, 
                 &self.lattice_params.raw
             );
@@ -153,7 +249,18 @@ impl Simulation {
         uniform_buffer: &UniformBuffer,
         texture: &Texture,
         device: &wgpu::Device,
-    ) {
+    ) -> Result<(), Validation> {
+        if self.validate_before_upload {
+            let mut validation = self.validate();
+            validation.extend(self.validate_limits(&device.limits()));
+            for warning in validation.warnings.iter() {
+                warn!("{}", warning);
+            }
+            if !validation.is_ok() {
+                return Err(validation);
+            }
+        }
+
         let usage = wgpu::BufferUsages::STORAGE;
 
         self.lattices[1].lattice = self.lattices[0].lattice.clone();
@@ -168,6 +275,19 @@ impl Simulation {
         // Regions
         self.regions.regions.create_buffer(device, usage, Some("Regions Buffer"));
 
+        // Clamps, fluxes and sinks
+        (self.boundary_table, self.absorbing_faces) = self.build_boundary_tables();
+        self.boundary_table.create_buffer(device, usage, Some("Boundary conditions buffer"));
+        self.absorbing_faces.create_buffer(device, usage, Some("Absorbing faces buffer"));
+
+        // Diffusion tensors and drift
+        self.transport_table = self.build_transport_table();
+        self.transport_table.create_buffer(device, usage, Some("Transport buffer"));
+
+        // Surface reactions. Sets the width of their rows in the reaction params
+        self.surface_table = self.build_surface_table();
+        self.surface_table.create_buffer(device, usage, Some("Surface reactions buffer"));
+
         self.lattice_params.create_buffer(device);
         self.reaction_params.create_buffer(device);
         
@@ -177,8 +297,17 @@ impl Simulation {
         // Reactions idx
         self.reactions_idx.create_buffer(device, usage, Some("Reactions idx buffer"));
 
-        // Reaction rates
-        self.reaction_rates.create_buffer(device, usage, Some("Reaction rates buffer"));
+        // Reaction rates. Scheduled rates are copied into it during the simulation
+        self.reaction_rates.create_buffer(device, usage | wgpu::BufferUsages::COPY_DST, Some("Reaction rates buffer"));
+        // It starts with the rates before any signal
+        self.uploaded_rates = self.rates_at(f32::NEG_INFINITY);
+
+        // Rate laws
+        self.rate_laws.create_buffer(device, usage, Some("Rate laws buffer"));
+
+        // Regions where each reaction happens
+        self.reaction_mask = self.build_reaction_mask();
+        self.reaction_mask.create_buffer(device, usage, Some("Reaction mask buffer"));
 
         let bind_group_layouts = self.build_bind_group_layouts(uniform_buffer, texture, device);
 
@@ -203,156 +332,737 @@ impl Simulation {
         self.rdme = Some(rdme);
 
         // CME
-        let cme = CME::new(&bind_group_layouts, &self.statistics_groups.as_ref().expect(""), &device);
+        let surface_reactions = self.surface_reactions.iter().any(|surface| surface.is_some());
+        let cme = CME::new(&bind_group_layouts, &self.statistics_groups.as_ref().expect(""), self.max_reactions, surface_reactions, &device);
         self.cme = Some(cme);
+        Ok(())
     }
 
-    pub fn from_file<P: AsRef<Path>>(path: P, uniform_buffer: &UniformBuffer, device: &wgpu::Device) -> Result<(Self, Texture)> {
-        let mut file = File::open(path).unwrap();
-        let mut buff = String::new();
-        file.read_to_string(&mut buff).unwrap();
-     
-        let data: Value = serde_json::from_str(&buff).unwrap();
-        let simulation_params = LatticeParams::from_json_value(&data["parameters"]);
+    /// Whether `prepare_for_gpu` runs `validate` first and refuses to upload a simulation with errors.
+    pub fn set_validation(&mut self, enabled: bool) {
+        self.validate_before_upload = enabled;
+    }
 
-        let mut simulation = Simulation::new(simulation_params);
-        simulation.json_regions(&data["regions"]);
-        simulation.prepare_regions();
-        simulation.json_particles(&data["particles"]);
-        simulation.json_reactions(&data["reactions"]);
+    /// Accepts surface reactions that would fire more than `validation::MAX_SURFACE_FIRINGS` times per voxel and step.
+    /// They fire once at most, so they run slower than their rate; `validate` reports them as an error otherwise.
+    pub fn set_allow_fast_surface_reactions(&mut self, allow: bool) {
+        self.allow_fast_surface_reactions = allow;
+    }
+
+    /// How reactions advance within a time step. Set it before `prepare_for_gpu`.
+    pub fn set_solver(&mut self, solver: ReactionSolver) {
+        if let Err((field, message)) = solver.validate() {
+            panic!("Solver: `{}` {}", field, message);
+        }
+        self.reaction_params.set_solver(solver);
+    }
+
+    pub fn solver(&self) -> ReactionSolver {
+        self.reaction_params.solver()
+    }
+
+    /// Seeds the placement of particles and sparse regions, and the random streams of the shaders, which depend on the
+    /// step counter (`Uniform::frame_num`) instead of the time. Set it before adding regions and particles. The same
+    /// seed gives the same lattice and the same draws for each particle slot, but not the same trajectory: the slot a
+    /// particle takes in `rdme.wgsl` depends on which thread wins an atomic, and surface reactions are lost to voxels
+    /// locked by other threads, so two runs drift apart.
+    pub fn set_seed(&mut self, seed: u64) {
+        assert!(self.regions.types.len() == 1, "Set the seed before adding regions, sparse regions are placed with it");
+        assert!(self.lattices[0].particle_names.len() == 1, "Set the seed before adding particles, they are placed with it");
+        self.seed = seed;
+        self.seed_given = true;
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.lattice_params.set_seed(seed);
+    }
+
+    /// Seed of the run, drawn when the simulation is created unless `set_seed` was called. It reproduces the lattice.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Reactions that the CME kernel can hold, from 1 to `MAX_REACTIONS_CAP`. It is compiled into the shader by
+    /// `prepare_for_gpu`, and `validate` checks that the simulation fits.
+    pub fn set_max_reactions(&mut self, max_reactions: usize) {
+        assert!((1..=MAX_REACTIONS_CAP).contains(&max_reactions), "max_reactions must be between 1 and {}", MAX_REACTIONS_CAP);
+        self.max_reactions = max_reactions;
+    }
+
+    pub fn max_reactions(&self) -> usize {
+        self.max_reactions
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P, uniform_buffer: &UniformBuffer, device: &wgpu::Device) -> Result<(Self, Texture), ModelError> {
+        let model = Model::from_file(path)?;
+        let mut simulation = Simulation::from_model(&model)?;
 
         // Create texture for GPU
         let lattice_resolution_usize = simulation.lattice_params.get_res_usize();
         let lattice_resolution: [u32; 3] = [lattice_resolution_usize[0] as u32, lattice_resolution_usize[1] as u32, lattice_resolution_usize[2] as u32];
         let texture = Texture::new(&lattice_resolution, wgpu::TextureFormat::R32Float, false, &device);
         
-        simulation.prepare_for_gpu(&uniform_buffer, &texture, device);
+        simulation.prepare_for_gpu(&uniform_buffer, &texture, device).map_err(ModelError::Validation)?;
 
         Ok((simulation, texture))
     }
 
+    /// Builds the simulation described by `model`. Nothing is uploaded to the GPU yet.
+    pub fn from_model(model: &Model) -> Result<Self, ModelError> {
+        if model.parameters.lattice_resolution.contains(&0) {
+            return Err(ModelError::invalid("parameters.lattice_resolution", "every axis needs at least one voxel"));
+        }
+        if model.parameters.max_particles_site == Some(0) {
+            return Err(ModelError::invalid("parameters.max_particles_site", "must be at least 1"));
+        }
+        if let Some(max_reactions) = model.parameters.max_reactions {
+            if max_reactions == 0 || max_reactions as usize > MAX_REACTIONS_CAP {
+                return Err(ModelError::invalid("parameters.max_reactions", format!("must be between 1 and {}", MAX_REACTIONS_CAP)));
+            }
+        }
+        if let Err((field, message)) = model.parameters.solver.validate() {
+            return Err(ModelError::invalid(format!("parameters.solver.tau_leaping.{}", field), message));
+        }
+        let simulation_params = LatticeParams::from_model(&model.parameters);
+
+        let mut simulation = Simulation::new(simulation_params);
+        if let Some(seed) = model.parameters.seed {
+            simulation.set_seed(seed);
+        }
+        simulation.set_solver(model.parameters.solver);
+        simulation.set_allow_fast_surface_reactions(model.parameters.allow_fast_surface_reactions);
+        simulation.rendering = model.rendering.clone().unwrap_or_default();
+        if let Some(max_reactions) = model.parameters.max_reactions {
+            simulation.set_max_reactions(max_reactions as usize);
+        }
+        simulation.json_regions(&model.regions)?;
+        simulation.prepare_regions();
+        simulation.json_particles(&model.particles)?;
+        simulation.json_transitions(&model.transitions)?;
+        simulation.json_boundaries(&model.boundaries)?;
+        simulation.json_reactions(&model.reactions)?;
+        Ok(simulation)
+    }
+
 }
 
 // Read from json
 impl Simulation {
-    fn json_regions(&mut self, regions: &Value) {
-        // regions is a list of regions. Loop over them and add
-        assert!(regions.is_array());
-        for region in regions.as_array().unwrap() {
-            let region_type = region["type"].as_str().unwrap();
-            match region_type {
-                "cube" => {
-                    let name = region["name"].as_str().unwrap();
-                    let p0: [f32; 3] = json_to_array(&region["p0"]).unwrap();
-                    let pf: [f32; 3] = json_to_array(&region["pf"]).unwrap();
-                    let diffusion_rate = region["base_diffusion_rate"].as_f64().unwrap() as f32;
-                    self.add_region(RegionType::Cube {
-                        name: name.to_string(), 
-                        p0, 
-                        pf
-                    }, diffusion_rate);
-                },
-                "capsid" => {
-                    let shell_name = region["shell_name"].as_str().unwrap();
-                    let interior_name = region["interior_name"].as_str().unwrap();
-                    let center: [f32; 3] = json_to_array(&region["center"]).unwrap();
-                    let dir: [f32; 3] = json_to_array(&region["dir"]).unwrap();
-                    let internal_radius = region["internal_radius"].as_f64().unwrap() as f32;
-                    let external_radius = region["external_radius"].as_f64().unwrap() as f32;
-                    let total_length = region["total_length"].as_f64().unwrap() as f32;
-                    let diffusion_rate = region["base_diffusion_rate"].as_f64().unwrap() as f32;
-                    // let transition_rate = region["transition_rate"].as_f64().unwrap() as f32;
-                    self.add_region(RegionType::Capsid { 
-                        shell_name: shell_name.to_string(), 
-                        interior_name: interior_name.to_string(), 
-                        center, 
-                        dir, 
-                        internal_radius,
-                        external_radius, 
-                        total_length 
-                    }, diffusion_rate);
+    fn json_regions(&mut self, regions: &[RegionModel]) -> Result<(), ModelError> {
+        let dims = self.lattice_params.dims();
+        for (i, region) in regions.iter().enumerate() {
+            let path = format!("regions[{}]", i);
+            match &region.shape {
+                RegionType::Cube { p0, pf, .. } => {
+                    // add_region_cube asserts this, and would index out of the lattice otherwise
+                    if (0..3).any(|axis| p0[axis] < 0. || p0[axis] > pf[axis] || pf[axis] > dims[axis]) {
+                        return Err(ModelError::invalid(
+                            path,
+                            format!("cube must satisfy 0 <= p0 <= pf <= dimensions, got p0 = {:?} and pf = {:?}", p0, pf)
+                        ));
+                    }
                 },
-                "sparse" => {
-                    panic!("Sparse regions not implemented yet");
+                RegionType::Sparse { to_region, max_volume, base_region, .. } => {
+                    // add_sparse_region panics (or never ends) on any of these
+                    let to_region_idx = match self.find_region_index(to_region) {
+                        Some(idx) => idx,
+                        None => return Err(ModelError::invalid(format!("{}.to_region", path), format!("unknown region `{}`", to_region))),
+                    };
+                    let available = self.regions.volumes[to_region_idx];
+                    if *max_volume == 0 || *max_volume >= available {
+                        return Err(ModelError::invalid(
+                            format!("{}.max_volume", path),
+                            format!("must be between 1 and the volume of `{}` ({} voxels)", to_region, available)
+                        ));
+                    }
+                    let voxel_size = self.lattice_params.get_voxel_size();
+                    if voxel_size.iter().any(|size| base_region.radius < *size) {
+                        return Err(ModelError::invalid(
+                            format!("{}.base_region.radius", path),
+                            format!("must span at least one voxel ({:?})", voxel_size)
+                        ));
+                    }
                 },
-                _ => panic!("Region type not implemented yet")
+                _ => (),
             }
+            self.add_region(region.shape.clone(), region.base_diffusion_rate);
         }
+        Ok(())
     }
     
-    fn json_particles(&mut self, particles: &Value) {
-        // particles is a list of particles. Loop over them and add
-        assert!(particles.is_array());
-        for particle in particles.as_array().unwrap() {
-            let name = particle["name"].as_str().unwrap();
-            let to_region = particle["to_region"].as_str().unwrap();
-            let logging = particle["logging"].as_bool().unwrap();
-            let is_reservoir = particle["is_reservoir"].as_bool().unwrap();
-
-            if particle.get("count") != None {
-                let count = particle["count"].as_u64().unwrap() as u32;
-                self.add_particle_count(name, to_region, count, logging, is_reservoir);
-            } else if particle.get("concentration") != None {
-                let concentration = particle["concentration"].as_f64().unwrap() as f32;
-                self.add_particle_concentration(name, to_region, concentration, logging, is_reservoir);
+    fn json_particles(&mut self, particles: &[ParticleModel]) -> Result<(), ModelError> {
+        for (i, particle) in particles.iter().enumerate() {
+            let path = format!("particles[{}]", i);
+            if self.lattices[0].find_particle(&particle.name).is_some() {
+                return Err(ModelError::invalid(format!("{}.name", path), format!("particle `{}` is declared twice", particle.name)));
+            }
+            if self.find_region_index(&particle.to_region).is_none() {
+                return Err(ModelError::invalid(format!("{}.to_region", path), format!("unknown region `{}`", particle.to_region)));
+            }
+            // Sometimes diffusion rate is given. Other times it is not.
+            for region in particle.diffusion_rate.keys() {
+                if self.find_region_index(region).is_none() {
+                    return Err(ModelError::invalid(format!("{}.diffusion_rate.{}", path, region), format!("unknown region `{}`", region)));
+                }
+            }
+            let transport = particle.diffusion_tensor.iter().map(|entry| ("diffusion_tensor", entry))
+                .chain(particle.drift.iter().map(|entry| ("drift", entry)));
+            for (field, (region, values)) in transport {
+                let field_path = format!("{}.{}.{}", path, field, region);
+                if self.find_region_index(region).is_none() {
+                    return Err(ModelError::invalid(field_path, format!("unknown region `{}`", region)));
+                }
+                if field == "diffusion_tensor" && values.iter().any(|value| *value < 0.) {
+                    return Err(ModelError::invalid(field_path, "diffusion rates must not be negative"));
+                }
+            }
+            let voxels = self.lattice_params.res().iter().product::<u32>() as usize;
+            if !particle.drift_field.is_empty() && particle.drift_field.len() != voxels {
+                return Err(ModelError::invalid(
+                    format!("{}.drift_field", path),
+                    format!("{} velocities, the lattice has {} voxels", particle.drift_field.len(), voxels)
+                ));
+            }
 
-            } else {
-                panic!("Particle must have count or concentration");
+            let region_idx = self.find_region_index(&particle.to_region).unwrap();
+            let (count, field) = match (particle.count, particle.concentration) {
+                (Some(count), None) => (count, "count"),
+                // Same rounding as add_particle_concentration
+                (None, Some(concentration)) => ((concentration * self.regions.volumes[region_idx] as f32) as u32, "concentration"),
+                (Some(_), Some(_)) => return Err(ModelError::invalid(path, "particle must have either a count or a concentration, not both")),
+                (None, None) => return Err(ModelError::invalid(path, "particle must have a count or a concentration")),
+            };
+            // add_particle_count would leave the rest out
+            let free_sites = self.free_sites(region_idx, particle.is_reservoir);
+            if count > free_sites {
+                return Err(ModelError::invalid(
+                    format!("{}.{}", path, field),
+                    format!("{} particles do not fit in `{}`, there is room for {}", count, particle.to_region, free_sites)
+                ));
             }
 
-            // Sometimes diffusion rate is given. Other times it is not.
-            if particle.get("diffusion_rate") != None {
-                let diffusion_rate = particle["diffusion_rate"].as_object().unwrap();
-                for (region, value) in diffusion_rate.iter() {
-                    let rate = value.as_f64().unwrap() as f32;
-                    self.set_diffusion_rate_particle(name, region, rate);
+            match particle.concentration {
+                Some(concentration) => self.add_particle_concentration(&particle.name, &particle.to_region, concentration, particle.logging, particle.is_reservoir),
+                None => self.add_particle_count(&particle.name, &particle.to_region, count, particle.logging, particle.is_reservoir),
+            }
+
+            for (region, rate) in particle.diffusion_rate.iter() {
+                self.set_diffusion_rate_particle(&particle.name, region, *rate);
+            }
+            for (region, tensor) in particle.diffusion_tensor.iter() {
+                self.set_diffusion_tensor_particle(&particle.name, region, *tensor);
+            }
+            for (region, velocity) in particle.drift.iter() {
+                self.set_drift_particle(&particle.name, region, *velocity);
+            }
+            if !particle.drift_field.is_empty() {
+                self.set_drift_field_particle(&particle.name, particle.drift_field.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn json_transitions(&mut self, transitions: &[TransitionModel]) -> Result<(), ModelError> {
+        // Particles are declared by now, so per-species transitions can be resolved
+        for (i, transition) in transitions.iter().enumerate() {
+            let path = format!("transitions[{}]", i);
+            for (field, region) in [("from", &transition.from), ("to", &transition.to)] {
+                if self.find_region_index(region).is_none() {
+                    return Err(ModelError::invalid(format!("{}.{}", path, field), format!("unknown region `{}`", region)));
                 }
             }
+            if transition.from == transition.to {
+                return Err(ModelError::invalid(
+                    format!("{}.to", path),
+                    "a transition needs two different regions, use the particle diffusion_rate within a region"
+                ));
+            }
+
+            match &transition.species {
+                Some(species) => {
+                    if self.lattices[0].find_particle(species).is_none() {
+                        return Err(ModelError::invalid(format!("{}.species", path), format!("unknown species `{}`", species)));
+                    }
+                    self.set_transition_rate_particle(species, &transition.from, &transition.to, transition.rate);
+                },
+                None => self.set_transition_rate(&transition.from, &transition.to, transition.rate),
+            }
+        }
+        Ok(())
+    }
 
+    fn json_boundaries(&mut self, boundaries: &[BoundaryCondition]) -> Result<(), ModelError> {
+        for (i, condition) in boundaries.iter().enumerate() {
+            let path = format!("boundaries[{}].{}", i, condition.name());
+            if let Err((field, message)) = condition.validate() {
+                return Err(ModelError::invalid(format!("{}.{}", path, field), message));
+            }
+            if let Some(region) = condition.region() {
+                if self.find_region_index(region).is_none() {
+                    return Err(ModelError::invalid(format!("{}.region", path), format!("unknown region `{}`", region)));
+                }
+            }
+            for species in condition.species() {
+                if self.lattices[0].find_particle(species).is_none() {
+                    return Err(ModelError::invalid(format!("{}.species", path), format!("unknown species `{}`", species)));
+                }
+            }
+            self.add_boundary_condition(condition.clone());
         }
+        Ok(())
     }
 
-    fn json_reactions(&mut self, reactions: &Value) {
-        // reactions are objects "reaction" : f32
-        if reactions.is_null() {
+    fn json_reactions(&mut self, reactions: &[(String, ReactionModel)]) -> Result<(), ModelError> {
+        // reactions are objects "equation" : rate, see `equation` for the grammar
+        if reactions.is_empty() {
             println!("No reactions found");
-            return;
+            return Ok(());
         }
-        for (reaction_str, value) in reactions.as_object().unwrap() {
-            let reaction = reaction_str.as_str();
-            let mut line_vec = split_whitespace(reaction);
-            // line_vec = line_vec[1..].to_vec();
-            debug!("Line vec: {:?}", line_vec);
-    
-            // Reference: reactants: Vec<&str>, products: Vec<&str>, k: f32
-            let mut reactants: Vec<&str> = Vec::new();
-            let mut products: Vec<&str> = Vec::new();
-            let mut k: f32 = value.as_f64().unwrap() as f32;
-    
-            let mut before_arrow: bool = true;
-    
-            for value in line_vec {
-                match value.parse::<f32>() {
-                    Ok(new_k) => {
-                        k = k*new_k;
-                        continue;
+        for (reaction, ReactionModel { rate, law, regions, logging, schedule }) in reactions {
+            let path = format!("reactions.{}", reaction);
+            let equation = match equation::parse(reaction) {
+                Ok(Some(equation)) => equation,
+                // Only a comment
+                Ok(None) => continue,
+                Err(e) => return Err(ModelError::invalid(path, e.to_string())),
+            };
+            debug!("Equation: {:?}", equation);
+
+            for term in equation.reactants.iter().chain(equation.products.iter()) {
+                if self.lattices[0].find_particle(&term.species).is_none() {
+                    return Err(ModelError::invalid(path, format!("column {}: unknown species `{}`", term.column, term.species)));
+                }
+                match &term.region {
+                    Some(region) if self.find_region_index(region).is_none() => {
+                        return Err(ModelError::invalid(path, format!("column {}: unknown region `{}`", term.column, region)));
                     },
-                    Err(_) => ()
+                    _ => (),
+                }
+            }
+            let reactant_names = equation.reactant_names();
+            let product_names = equation.product_names();
+            let reactants = reactant_names.iter().map(String::as_str).collect::<Vec<&str>>();
+            let products = product_names.iter().map(String::as_str).collect::<Vec<&str>>();
+            let mut surface = equation::surface_regions(&reactants, &products);
+            if equation.reversible {
+                surface = surface.and(equation::surface_regions(&products, &reactants));
+            }
+            match surface {
+                Err(message) => return Err(ModelError::invalid(path, message)),
+                Ok(Some(_)) if *law != RateLaw::MassAction => {
+                    return Err(ModelError::invalid(format!("{}.law", path), "surface reactions follow mass action"));
+                },
+                Ok(Some(_)) if !regions.is_empty() => {
+                    return Err(ModelError::invalid(format!("{}.regions", path), "surface reactions take their regions from the equation"));
+                },
+                Ok(_) => (),
+            }
+            for (i, region) in regions.iter().enumerate() {
+                if self.find_region_index(region).is_none() {
+                    return Err(ModelError::invalid(format!("{}.regions[{}]", path, i), format!("unknown region `{}`", region)));
+                }
+            }
+            let law_path = format!("{}.law.{}", path, law.name());
+            if let Err((field, message)) = law.validate() {
+                return Err(ModelError::invalid(format!("{}.{}", law_path, field), message));
+            }
+            match law.species() {
+                Some(species) if self.lattices[0].find_particle(species).is_none() => {
+                    let field = if let RateLaw::MichaelisMenten { .. } = law { "substrate" } else { "species" };
+                    return Err(ModelError::invalid(format!("{}.{}", law_path, field), format!("unknown species `{}`", species)));
+                },
+                None if matches!(law, RateLaw::MichaelisMenten { .. }) && equation.reactants.is_empty() => {
+                    return Err(ModelError::invalid(law_path, "a reaction without reactants needs a `substrate`"));
+                },
+                _ => (),
+            }
+            for (i, signal) in schedule.iter().enumerate() {
+                let signal_path = format!("{}.schedule[{}]", path, i);
+                if equation.reversible {
+                    return Err(ModelError::invalid(signal_path, "only `->` reactions can be scheduled"));
+                }
+                if let Err((field, message)) = signal.validate() {
+                    return Err(ModelError::invalid(format!("{}.{}.{}", signal_path, signal.name(), field), message));
+                }
+            }
+
+            let regions = regions.iter().map(String::as_str).collect::<Vec<&str>>();
+            let first_added = self.reaction_logging.len();
+            match (equation.reversible, rate) {
+                (true, _) if *law != RateLaw::MassAction => return Err(ModelError::invalid(path, "only mass-action reactions can be reversible")),
+                (false, RateModel::Forward(rate)) => self.add_reaction_with_law(reactants, products, *rate, law.clone(), &regions),
+                (true, RateModel::Reversible(forward, reverse)) => {
+                    self.add_reaction_in_regions(reactants.clone(), products.clone(), *forward, &regions);
+                    self.add_reaction_in_regions(products, reactants, *reverse, &regions);
+                },
+                (false, RateModel::Reversible(..)) => return Err(ModelError::invalid(path, "a `->` reaction has a single rate")),
+                (true, RateModel::Forward(_)) => return Err(ModelError::invalid(path, "a `<->` reaction needs two rates, [forward, reverse]")),
+            }
+            // Both halves of a reversible reaction are logged
+            for added in first_added..self.reaction_logging.len() {
+                self.set_reaction_logging(added, *logging);
+            }
+            if let (RateModel::Forward(rate), false) = (rate, schedule.is_empty()) {
+                // The signals are in the units of the rate
+                let mut reactants = self.host_reactants(first_added + 1);
+                if let Some(surface) = &self.surface_reactions[first_added] {
+                    reactants.extend(&surface.reactants);
+                }
+                let factor = self.convert_rate(ReactionRate { value: 1., ..*rate }, law, &reactants);
+                for signal in schedule {
+                    self.schedule_signal(first_added, signal.scaled(factor));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Read from SBML
+impl Simulation {
+    /// Adds the species and reactions of an SBML model. Each compartment maps onto the region with its
+    /// name, or its id, so regions must be added and prepared first. Amounts become particle counts, concentrations
+    /// the molecules in the size of the compartment, and rates molar rates. Nothing is added on error.
+    pub fn add_sbml(&mut self, sbml: &SbmlModel) -> Result<(), SbmlError> {
+        if self.regions.index_buffer.is_none() {
+            return Err(SbmlError::invalid("regions", "regions must be prepared before species are added"));
+        }
+        let mut regions = HashMap::new();
+        for compartment in sbml.compartments.iter() {
+            let region = compartment.name.iter()
+                .chain(std::iter::once(&compartment.id))
+                .find(|name| self.find_region_index(name).is_some());
+            if let Some(region) = region {
+                regions.insert(compartment.id.as_str(), region.as_str());
+            }
+        }
+
+        for species in sbml.species.iter() {
+            let element = format!("species `{}`", species.id);
+            if !regions.contains_key(species.compartment.as_str()) {
+                return Err(SbmlError::invalid(element, format!("no region matches compartment `{}`", species.compartment)));
+            }
+            if self.lattices[0].find_particle(&species.id).is_some() {
+                return Err(SbmlError::invalid(element, "a particle with this name already exists"));
+            }
+            if let InitialValue::Amount(value) | InitialValue::Concentration(value) = species.initial {
+                if value < 0. {
+                    return Err(SbmlError::invalid(element, "initial value is negative"));
+                }
+            }
+        }
+
+        for species in sbml.species.iter() {
+            let region = regions[species.compartment.as_str()];
+            let count = match species.initial {
+                InitialValue::Amount(amount) => amount,
+                // The reader keeps concentrations only in compartments with a size
+                InitialValue::Concentration(concentration) => {
+                    let size = sbml.compartments.iter().find(|c| c.id == species.compartment).and_then(|c| c.size);
+                    concentration * AVOGADRO * size.unwrap_or_default()
+                },
+            };
+            self.add_particle_count(&species.id, region, count.round() as u32, false, species.is_reservoir);
+        }
+        for reaction in sbml.reactions.iter() {
+            let reactants = reaction.reactants.iter().map(String::as_str).collect();
+            let products = reaction.products.iter().map(String::as_str).collect();
+            self.add_reaction(reactants, products, ReactionRate::molar(reaction.rate as f32));
+        }
+        Ok(())
+    }
+}
+
+// Write to json
+impl Simulation {
+    /// Describes the simulation as a model that `from_model` builds again: same regions, species,
+    /// diffusion and transition rates, reservoirs, logging flags and reactions. Particles are placed at random
+    /// again when it is loaded, so only their counts are kept. Constants and expressions of the original file
+    /// are not known anymore: their values are written instead. The rendering section is the one that was read, or
+    /// a default window, so that `RenderParams::from_file` opens the output too.
+    pub fn to_model(&self) -> Model {
+        let particles = self.particle_declarations.iter().enumerate().map(|(i, declaration)| {
+            let mut particle = declaration.clone();
+            particle.diffusion_rate = self.particle_diffusion_rates(i + 1);
+            let regions_of = |values: &BTreeMap<(String, String), [f32; 3]>| -> BTreeMap<String, [f32; 3]> {
+                values.iter()
+                    .filter(|((name, _), _)| *name == particle.name)
+                    .map(|((_, region), value)| (region.clone(), *value))
+                    .collect()
+            };
+            particle.diffusion_tensor = regions_of(&self.diffusion_tensors);
+            particle.drift = regions_of(&self.drifts);
+            particle.drift_field = self.drift_fields.get(&particle.name).cloned().unwrap_or_default();
+            particle
+        }).collect();
+
+        Model {
+            constants: Vec::new(),
+            parameters: ParametersModel {
+                max_reactions: Some(self.max_reactions as u32).filter(|max| *max as usize != DEFAULT_MAX_REACTIONS),
+                solver: self.solver(),
+                seed: Some(self.seed).filter(|_| self.seed_given),
+                allow_fast_surface_reactions: self.allow_fast_surface_reactions,
+                ..self.lattice_params.to_model()
+            },
+            rendering: Some(self.rendering.clone()),
+            regions: self.region_declarations.clone(),
+            particles,
+            transitions: self.transition_entries(),
+            boundaries: self.boundary_conditions.clone(),
+            reactions: self.reaction_entries(),
+        }
+    }
+
+    pub fn save_model<P: AsRef<Path>>(&self, path: P) -> Result<(), ModelError> {
+        self.to_model().to_file(path)
+    }
+
+    fn particle_diffusion_rates(&self, particle_idx: usize) -> BTreeMap<String, f32> {
+        // A new particle starts with the rates of the previous one, so only what changed is written.
+        // The first particle lists every region: the void column depends on how the regions were set up.
+        let mut rates = BTreeMap::new();
+        for (region_idx, region) in self.regions.types.iter().enumerate() {
+            let rate = self.diffusion_matrix[[region_idx, region_idx, particle_idx]];
+            if particle_idx == 1 || rate != self.diffusion_matrix[[region_idx, region_idx, particle_idx - 1]] {
+                rates.insert(region.name().expect("Regions are primitives").to_string(), rate);
+            }
+        }
+        rates
+    }
+
+    fn transition_entries(&self) -> Vec<TransitionModel> {
+        // Off-diagonal entries start at 0. A pair shared by every particle is written once, without species.
+        let names = &self.lattices[0].particle_names;
+        let mut transitions = Vec::new();
+        for (from_idx, from) in self.regions.types.iter().enumerate() {
+            for (to_idx, to) in self.regions.types.iter().enumerate() {
+                let rates = (1..names.len()).map(|particle| self.diffusion_matrix[[from_idx, to_idx, particle]]).collect::<Vec<f32>>();
+                if from_idx == to_idx || rates.iter().all(|rate| *rate == 0.) {
+                    continue;
+                }
+
+                let transition = |species: Option<&String>, rate: f32| TransitionModel {
+                    from: from.name().expect("Regions are primitives").to_string(),
+                    to: to.name().expect("Regions are primitives").to_string(),
+                    species: species.cloned(),
+                    rate,
                 };
-                match value {
-                    "->" => before_arrow = false,
-                    "+" => (),
-                    _ => {
-                        if before_arrow {
-                            reactants.push(value);
-                        } else {
-                            products.push(value);
-                        }
+                if rates.iter().all(|rate| *rate == rates[0]) {
+                    transitions.push(transition(None, rates[0]));
+                } else {
+                    for (particle, rate) in rates.iter().enumerate().filter(|(_, rate)| **rate != 0.) {
+                        transitions.push(transition(Some(&names[particle + 1]), *rate));
+                    }
+                }
+            }
+        }
+        transitions
+    }
+
+    fn reaction_entries(&self) -> Vec<(String, ReactionModel)> {
+        let num_reactions = self.reaction_params.raw_params.num_reactions as usize;
+
+        // Row 0 of the reaction tensors is a placeholder
+        (1..=num_reactions).map(|reaction| {
+            let rate = ReactionRate::per_voxel(self.reaction_rates[[reaction, 0]]).into();
+            let law = self.reaction_laws[reaction - 1].clone();
+            // Surface reactions take their regions from the equation
+            let regions = match self.surface_reactions[reaction - 1] {
+                Some(_) => Vec::new(),
+                None => self.reaction_regions[reaction - 1].clone(),
+            };
+            let logging = self.reaction_logging[reaction - 1];
+            let schedule = self.rate_schedules[reaction - 1].clone();
+            (self.reaction_equation(reaction), ReactionModel { rate, law, regions, logging, schedule })
+        }).collect()
+    }
+
+    /// Equation of a row of the reaction tables, "A + B -> C", with the regions of the terms of a surface reaction.
+    fn reaction_equation(&self, reaction: usize) -> String {
+        let names = &self.lattices[0].particle_names;
+        let reactants = self.host_reactants(reaction);
+
+        // Products are what is left after the reactants are consumed
+        let mut products = Vec::<usize>::new();
+        for species in 1..names.len() {
+            let consumed = reactants.iter().filter(|idx| **idx == species).count() as i32;
+            let produced = consumed + self.stoichiometry_matrix[[reaction, species]];
+            products.extend(std::iter::repeat_n(species, produced.max(0) as usize));
+        }
+        let mut reactants = reactants.iter().map(|idx| names[*idx].clone()).collect::<Vec<String>>();
+        let mut products = products.iter().map(|idx| names[*idx].clone()).collect::<Vec<String>>();
+        if let Some(surface) = &self.surface_reactions[reaction - 1] {
+            let host = &self.reaction_regions[reaction - 1][0];
+            for term in reactants.iter_mut().chain(products.iter_mut()) {
+                *term = format!("{}@{}", term, host);
+            }
+            reactants.extend(surface.reactants.iter().map(|idx| format!("{}@{}", names[*idx], surface.neighbour_region)));
+            products.extend(surface.products.iter().map(|idx| format!("{}@{}", names[*idx], surface.neighbour_region)));
+        }
+        format!("{} -> {}", equation_side(&reactants), equation_side(&products))
+    }
+
+    /// Reactants of a row of the reaction tables, as species indices with repetitions. For surface reactions, the
+    /// reactants in the host voxel.
+    fn host_reactants(&self, reaction: usize) -> Vec<usize> {
+        (0..self.reactions_idx.shape()[1])
+            .map(|j| self.reactions_idx[[reaction, j]] as usize)
+            .filter(|idx| *idx != 0)
+            .collect()
+    }
+}
+
+/// One side of an equation, "2 A + B", with repeated species grouped in order of appearance.
+fn equation_side(species: &[String]) -> String {
+    let mut terms = Vec::<(&String, u32)>::new();
+    for name in species {
+        match terms.iter_mut().find(|(s, _)| *s == name) {
+            Some((_, coefficient)) => *coefficient += 1,
+            None => terms.push((name, 1)),
+        }
+    }
+    if terms.is_empty() {
+        return "0".to_string();
+    }
+    terms.iter()
+        .map(|(name, coefficient)| match coefficient {
+            1 => name.to_string(),
+            c => format!("{} {}", c, name),
+        })
+        .collect::<Vec<String>>()
+        .join(" + ")
+}
+
+// Validation
+impl Simulation {
+    /// Looks for values that the shaders would take without failing, but that make the results wrong.
+    pub fn validate(&self) -> Validation {
+        let mut validation = Validation::default();
+        let names = &self.lattices[0].particle_names;
+        let num_species = self.reaction_params.raw_params.num_species as usize;
+        let num_reactions = self.reaction_params.raw_params.num_reactions as usize;
+
+        // rdme.wgsl: each of the 6 neighbours is taken with probability D * tau / lambda^2
+        let scale = self.lattice_params.raw.tau as f64 / (self.lattice_params.lambda() as f64).powi(2);
+        let num_regions = self.regions.types.len();
+        for particle in 1..names.len() {
+            for from in 0..num_regions {
+                for to in 0..num_regions {
+                    // Replaced by the tensor, checked below
+                    let region = self.regions.types[from].name().unwrap_or_default();
+                    if from == to && self.diffusion_tensors.contains_key(&(names[particle].clone(), region.to_string())) {
+                        continue;
+                    }
+                    let probability = 6. * self.diffusion_matrix[[from, to, particle]] as f64 * scale;
+                    if probability <= 1. {
+                        continue;
+                    }
+                    let issue = Issue::DiffusionProbability {
+                        particle: names[particle].clone(),
+                        from_region: self.regions.types[from].name().unwrap_or_default().to_string(),
+                        to_region: self.regions.types[to].name().unwrap_or_default().to_string(),
+                        probability,
+                    };
+                    if from == to {
+                        validation.errors.push(issue);
+                    } else {
+                        validation.warnings.push(issue);
                     }
                 }
             }
-            self.add_reaction(reactants, products, k);
         }
+
+        // rdme.wgsl: with a tensor or drift the jumps differ per direction and voxel. One issue per particle, where
+        // they are highest
+        let res = self.lattice_params.get_res_usize();
+        for particle in names.iter().skip(1) {
+            let directed = self.diffusion_tensors.keys().chain(self.drifts.keys()).any(|(name, _)| name == particle)
+                || self.drift_fields.contains_key(particle);
+            if !directed {
+                continue;
+            }
+            let highest = (0..res[0]).flat_map(|x| (0..res[1]).flat_map(move |y| (0..res[2]).map(move |z| [x, y, z])))
+                .map(|voxel| (voxel, self.jump_probabilities(particle, voxel).iter().map(|p| *p as f64).sum::<f64>()))
+                .fold(([0; 3], 0.), |highest, current| if current.1 > highest.1 { current } else { highest });
+            if highest.1 > 1. {
+                validation.errors.push(Issue::JumpProbability { particle: particle.clone(), voxel: highest.0, probability: highest.1 });
+            }
+        }
+
+        // cme.wgsl: cme_surface fires a surface reaction at most once per voxel and step. One issue per reaction, where
+        // it is most likely with the current counts
+        let periodic = self.lattice_params.periodic();
+        let tau = self.lattice_params.raw.tau as f64;
+        let counts_at = |voxel: [usize; 3]| self.lattices[0].concentrations.data.slice(s![voxel[0], voxel[1], voxel[2], ..]).to_vec();
+        for reaction in (0..self.surface_reactions.len()).filter(|reaction| self.surface_reactions[*reaction].is_some()) {
+            let highest = (0..res[0]).flat_map(|x| (0..res[1]).flat_map(move |y| (0..res[2]).map(move |z| [x, y, z])))
+                .filter(|voxel| self.happens_in(reaction + 1, self.regions.regions[*voxel] as usize))
+                .map(|voxel| {
+                    let neighbours: Vec<(&str, Vec<i32>)> = Face::ALL.iter()
+                        .filter_map(|face| boundary::neighbour(voxel, *face, res, periodic))
+                        .map(|neighbour| (self.regions.types[self.regions.regions[neighbour] as usize].name().unwrap_or_default(), counts_at(neighbour)))
+                        .collect();
+                    let neighbours: Vec<(&str, &[i32])> = neighbours.iter().map(|(region, counts)| (*region, counts.as_slice())).collect();
+                    (voxel, self.surface_propensity(reaction, &counts_at(voxel), &neighbours) as f64 * tau)
+                })
+                .fold(([0; 3], 0.), |highest, current| if current.1 > highest.1 { current } else { highest });
+            if highest.1 > MAX_SURFACE_FIRINGS {
+                let issue = Issue::SurfaceFirings { reaction: self.reaction_equation(reaction + 1), voxel: highest.0, firings: highest.1 };
+                if self.allow_fast_surface_reactions {
+                    validation.warnings.push(issue);
+                } else {
+                    validation.errors.push(issue);
+                }
+            }
+        }
+
+        // cme.wgsl: the propensity table is sized when the shader is built
+        if num_reactions > self.max_reactions {
+            validation.errors.push(Issue::TooManyReactions { reactions: num_reactions, max: self.max_reactions });
+        }
+        if num_species > MAX_TEXTURE_SPECIES {
+            validation.errors.push(Issue::TooManySpecies { species: num_species, max: MAX_TEXTURE_SPECIES });
+        }
+
+        // Placement happens when particles are added, so what did not fit was recorded then
+        for (particle, region, missing) in self.unplaced_particles.iter() {
+            validation.errors.push(Issue::ParticlesDoNotFit { particle: particle.clone(), region: region.clone(), missing: *missing });
+        }
+        validation
+    }
+
+    /// Checks that the storage buffers of the simulation fit in a device with these `limits`. The lattice
+    /// grows with `max_particles_site`, and the reaction tables with the reactions and species.
+    pub fn validate_limits(&self, limits: &wgpu::Limits) -> Validation {
+        let mut validation = Validation::default();
+        let lattice = &self.lattices[0];
+        // Tables built by `prepare_for_gpu` are measured as they will be
+        let (boundary_table, absorbing_faces) = self.build_boundary_tables();
+        let (_, surface_slots) = self.surface_slots();
+        let buffers = [
+            ("lattice", lattice.lattice.buffer_size()),
+            ("occupancy", lattice.occupancy.buffer_size()),
+            ("locks", lattice.lock_cells.buffer_size()),
+            ("concentrations", lattice.concentrations.buffer_size()),
+            ("reservoirs", lattice.reservoir.buffer_size()),
+            ("regions", self.regions.regions.buffer_size()),
+            ("diffusion matrix", self.diffusion_matrix.buffer_size()),
+            ("transport", self.build_transport_table().buffer_size()),
+            ("stoichiometry matrix", self.stoichiometry_matrix.buffer_size()),
+            ("reactions idx", self.reactions_idx.buffer_size()),
+            ("reaction rates", self.reaction_rates.buffer_size()),
+            ("reaction mask", self.build_reaction_mask().buffer_size()),
+            ("rate laws", self.rate_laws.buffer_size()),
+            ("surface reactions", (self.surface_reactions.len() + 1) * surface_slots * std::mem::size_of::<i32>()),
+            ("boundary conditions", boundary_table.buffer_size()),
+            ("absorbing faces", absorbing_faces.buffer_size()),
+            ("reaction counters", self.reaction_counters() * std::mem::size_of::<i32>()),
+        ];
+        for (buffer, size) in buffers {
+            if size as u64 > limits.max_storage_buffer_binding_size as u64 {
+                validation.errors.push(Issue::BufferTooLarge { buffer: buffer.to_string(), size: size as u64, max: limits.max_storage_buffer_binding_size as u64 });
+            }
+        }
+        validation
     }
 }
 
@@ -373,18 +1083,71 @@ impl Simulation {
             Some("Concentration buffer")
         );
 
-        // Particles to log:
-        let mut hash_concentration = HashMap::new();
+        // Firings of each reaction, in total and per region. See `logged_statistics` for the layout
+        let counters = self.reaction_counters();
+        let mut reaction_tensor = Tensor1::<i32>::from_data(vec![0; counters], StrideShape::from((counters,)));
+        reaction_tensor.create_buffer(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::MAP_READ,
+            Some("Reaction counters buffer")
+        );
+
+        let logging_stats = self.logged_statistics().into_iter().collect();
+        StatisticsGroup::new(vec![concentration_tensor, reaction_tensor], logging_stats, device)
+    }
+
+    /// Length of the reaction counters buffer: for each row of the reaction tables its total and one value per region.
+    fn reaction_counters(&self) -> usize {
+        (self.reaction_params.raw_params.num_reactions as usize + 1) * (self.regions.types.len() + 1)
+    }
+
+    /// Series that the readbacks log, with the statistics buffer they read and the index in it. Particles are
+    /// logged by name. A reaction is `reaction:<equation>` for its total firings and `reaction:<equation>@<region>`
+    /// for the firings in each region where it happens. The n-th reaction with an equation that is already taken,
+    /// e.g. the same reaction in another region, is `reaction:<equation>#<n>`. With surface reactions, `surface:lost` and
+    /// `surface:lost@<region>` count their events lost to a neighbour that was changing. Counters start at 0 and keep
+    /// adding up.
+    pub fn logged_statistics(&self) -> BTreeMap<String, [u32; 2]> {
+        let mut statistics = BTreeMap::new();
         for particle in self.lattices[0].logging_particles.iter() {
-            hash_concentration.insert(
-                self.lattices[0].particle_names[*particle as usize].clone(), 
-                [
-                    0, // Idx of the bind group (concentration = 0) 
-                    *particle // Padding of the storage buffer. It's 1D, so it equals the idx of the particle
-                ]
-            );
+            // Concentrations are buffer 0, one value per particle
+            statistics.insert(self.lattices[0].particle_names[*particle as usize].clone(), [0, *particle]);
         }
-        StatisticsGroup::new(vec![concentration_tensor], hash_concentration, device)
+
+        // Reaction counters are buffer 1: for each reaction its total and then one value per region
+        let num_regions = self.regions.types.len();
+        let mut equations = HashMap::<String, usize>::new();
+        for (i, logging) in self.reaction_logging.iter().enumerate() {
+            let reaction = i + 1;
+            let equation = self.reaction_equation(reaction);
+            // Numbered among all the reactions, so that the names do not depend on which ones are logged
+            let taken = equations.entry(equation.clone()).or_default();
+            *taken += 1;
+            if !*logging {
+                continue;
+            }
+            let name = match *taken {
+                1 => format!("reaction:{}", equation),
+                n => format!("reaction:{}#{}", equation, n),
+            };
+            let first = (reaction * (num_regions + 1)) as u32;
+            for region in (0..num_regions).filter(|region| self.happens_in(reaction, *region)) {
+                let region_name = self.regions.types[region].name().unwrap_or_default();
+                statistics.insert(format!("{}@{}", name, region_name), [1, first + 1 + region as u32]);
+            }
+            statistics.insert(name, [1, first]);
+        }
+
+        // Row 0 of the counters, which no reaction uses
+        let surface = (0..self.surface_reactions.len()).filter(|reaction| self.surface_reactions[*reaction].is_some()).collect::<Vec<usize>>();
+        if !surface.is_empty() {
+            for region in (0..num_regions).filter(|region| surface.iter().any(|reaction| self.happens_in(reaction + 1, *region))) {
+                let region_name = self.regions.types[region].name().unwrap_or_default();
+                statistics.insert(format!("surface:lost@{}", region_name), [1, 1 + region as u32]);
+            }
+            statistics.insert("surface:lost".to_string(), [1, 0]);
+        }
+        statistics
     }
 
 
@@ -428,6 +1191,10 @@ impl Simulation {
     pub fn add_region(&mut self, reg: RegionType, diffusion_rate: f32) {
         //self.regions.names.push(String::from(name));
         let transition_rate: f32 = 0.; //8.15E-14 / 6.;
+        if !matches!(reg, RegionType::Sparse { .. }) {
+            // Sparse regions are recorded by add_sparse_region
+            self.region_declarations.push(RegionModel { shape: reg.clone(), base_diffusion_rate: diffusion_rate });
+        }
         match reg {
             RegionType::Cube { name, p0, pf} => self.add_region_cube(&name, p0, pf, diffusion_rate, transition_rate),
             RegionType::Sphere { name, center, radius } => self.add_region_sphere(&name, center, radius, diffusion_rate, transition_rate),
@@ -475,7 +1242,10 @@ impl Simulation {
                 self.join_regions("inside1", &interior_name);
                 self.join_regions("inside2", &interior_name);
             }
-            _ => panic!("Region type not implemented yet")
+            RegionType::Sparse { name, to_region, max_volume, base_region } => {
+                let base_region = RegionType::Sphere { name: base_region.name, center: base_region.center, radius: base_region.radius };
+                self.add_sparse_region(&name, base_region, &to_region, max_volume, diffusion_rate);
+            }
         }
         info!("Added region {:?}", self.regions.types.last().unwrap());
     }
@@ -504,7 +1274,7 @@ impl Simulation {
         let mut added = false;
         while curr_volume < max_volume {            
             // Generate a random point making sure that the sphere fits.
-            let point = self.regions.generate_boundary_aware(to_region_idx, voxel_size, &radius_voxels);
+            let point = self.regions.generate_boundary_aware(to_region_idx, voxel_size, &radius_voxels, &mut self.rng);
 
             // Add the region iterating over the tensor
             for i in point[0] - radius_voxels[0]..point[0] + radius_voxels[0] {
@@ -526,9 +1296,18 @@ impl Simulation {
 
         if added {
             let base_region = Sphere { name: name.to_string(), center: [0.; 3], radius: radius };
-            self.regions.types.push(RegionType::Sparse { name: name.to_string(), base_region: base_region });
+            self.regions.types.push(RegionType::Sparse { name: name.to_string(), to_region: to_region.to_string(), max_volume, base_region: base_region });
             self.update_matrices_region(diffusion_rate, transition_rate);
             self.regions.volumes.push(curr_volume);
+            self.region_declarations.push(RegionModel {
+                shape: RegionType::Sparse {
+                    name: name.to_string(),
+                    to_region: to_region.to_string(),
+                    max_volume,
+                    base_region: Sphere { name: String::new(), center: [0.; 3], radius },
+                },
+                base_diffusion_rate: diffusion_rate,
+            });
         } else {
             panic!("Could not add sparse region");
         }
@@ -742,17 +1521,8 @@ impl Simulation {
         self.regions.volumes.push(volume);
     }
 
-    fn find_region_index(&mut self, name: &str) -> Option<usize> {
-        self.regions.types.iter().position(|region| {
-            match region {
-                RegionType::Cube { name: region_name, .. } => { region_name == name },
-                RegionType::Sphere { name: region_name, .. } => { region_name == name },
-                RegionType::Cylinder { name: region_name,.. } => { region_name == name },
-                RegionType::SemiSphere { name: region_name,.. } => { region_name == name },
-                RegionType::Sparse { name: region_name, .. } => { region_name == name },
-                _ => { false }
-            }
-        })
+    fn find_region_index(&self, name: &str) -> Option<usize> {
+        self.regions.types.iter().position(|region| region.name() == Some(name))
     }
 
     fn join_regions(&mut self, region_delete: &str, to_region: &str) {
@@ -789,8 +1559,9 @@ impl Simulation {
         self.regions.remove_region(region_delete_idx as usize);
     }
 
+    /// Adds a species and places `count` particles of it at random in `to_region`. Particles that do not fit, because
+    /// the sites of the region are full, are left out and `validate` reports them.
     pub fn add_particle_count(&mut self, name: &str, to_region: &str, count: u32, logging: bool, is_reservoir: bool) {
-        // Add particles to the simulation. It will be added to the region specified by to_region
         let region_idx = self.find_region_index(to_region).expect("Region not found");
         let particle_idx = self.lattices[0].particle_names.len() as Particle;
 
@@ -798,7 +1569,11 @@ impl Simulation {
 
         let regions_idx_buffer = &self.regions.index_buffer.as_ref().unwrap()[&(region_idx as u32)];
 
-        self.lattices[0].init_random_particles_region(particle_idx, count, regions_idx_buffer, is_reservoir);
+        let placed = self.lattices[0].init_random_particles_region(particle_idx, count, regions_idx_buffer, is_reservoir, &mut self.rng);
+        if placed < count {
+            warn!("Only {} of {} particles of type {} fit in region {}", placed, count, name, to_region);
+            self.unplaced_particles.push((name.to_string(), to_region.to_string(), count - placed));
+        }
 
         // Update the diffusion matrix
         self.diffusion_matrix.copy_dimension(2);
@@ -809,7 +1584,8 @@ impl Simulation {
         if logging {
             self.lattices[0].logging_particles.push(particle_idx);
         }
-        info!("{} particles of type {} added to region {}", count, name, to_region);
+        self.declare_particle(name, to_region, placed, logging, is_reservoir);
+        info!("{} particles of type {} added to region {}", placed, name, to_region);
         debug!("Concentrations after adding particles: {:?}", self.lattices[0].concentrations.shape());
     }
 
@@ -827,7 +1603,11 @@ impl Simulation {
         let particle_idx = self.lattices[0].particle_names.len() as Particle;
         self.lattices[0].particle_names.push(String::from(name));
         let regions_idx_buffer = &self.regions.index_buffer.as_ref().unwrap()[&(region_idx as u32)];
-        self.lattices[0].fill_region_particles(particle_idx, regions_idx_buffer);
+        let sites = regions_idx_buffer.len() as u32;
+        let count = self.lattices[0].fill_region_particles(particle_idx, regions_idx_buffer);
+        if count < sites {
+            self.unplaced_particles.push((name.to_string(), to_region.to_string(), sites - count));
+        }
         // Update the diffusion matrix
         self.diffusion_matrix.copy_dimension(2);
 
@@ -837,6 +1617,7 @@ impl Simulation {
         if logging {
             self.lattices[0].logging_particles.push(particle_idx);
         }
+        self.declare_particle(name, to_region, count, logging, false);
     }
 
     pub fn particle_random_walk(&mut self, name: &str, to_region: &str, total_length: f32, block_length: f32, radius: f32, logging: bool) {
@@ -847,7 +1628,7 @@ impl Simulation {
         self.lattices[0].particle_names.push(String::from(name));
 
         // let regions_idx_buffer = &self.regions.index_buffer.as_ref().unwrap()[&(region_idx as u32)];
-        let _ = self.lattices[0].init_random_walk_particles(particle_idx, total_length, block_length, radius, region_idx, &self.regions);
+        let _ = self.lattices[0].init_random_walk_particles(particle_idx, total_length, block_length, radius, region_idx, &self.regions, &mut self.rng);
 
         // Update the diffusion matrix
         self.diffusion_matrix.copy_dimension(2);
@@ -858,11 +1639,42 @@ impl Simulation {
         if logging {
             self.lattices[0].logging_particles.push(particle_idx);
         }
+        let count = self.lattices[0].concentrations.data.index_axis(Axis(3), particle_idx as usize).sum() as u32;
+        self.declare_particle(name, to_region, count, logging, false);
 
         info!("Particle {} added to region {} as random walk", name, to_region);
 
     }
 
+    /// Particles that can still be placed in a region: free slots of its sites, or free reservoir sites.
+    fn free_sites(&self, region_idx: usize, is_reservoir: bool) -> u32 {
+        let lattice = &self.lattices[0];
+        self.regions.index_buffer.as_ref().unwrap()[&(region_idx as u32)].iter().map(|position| {
+            let [x, y, z] = lattice.idx_to_site_usize(*position);
+            if is_reservoir {
+                (lattice.reservoir[[x, y, z]] == 0) as u32
+            } else {
+                self.lattice_params.raw.max_particles_site - lattice.occupancy[[x, y, z]]
+            }
+        }).sum()
+    }
+
+    fn declare_particle(&mut self, name: &str, to_region: &str, count: u32, logging: bool, is_reservoir: bool) {
+        // Filled regions and random walks are kept as plain counts: a model can only place particles at random
+        self.particle_declarations.push(ParticleModel {
+            name: name.to_string(),
+            to_region: to_region.to_string(),
+            count: Some(count),
+            concentration: None,
+            logging,
+            is_reservoir,
+            diffusion_rate: BTreeMap::new(),
+            diffusion_tensor: BTreeMap::new(),
+            drift: BTreeMap::new(),
+            drift_field: Vec::new(),
+        });
+    }
+
     #[allow(dead_code)]
     pub fn set_diffusion_rate(&mut self, region: &str, diffusion_rate: f32) {
         // Write diffusion rate for all the particles in a region
@@ -901,20 +1713,252 @@ impl Simulation {
         self.diffusion_matrix[[from_region_idx, to_region_idx, particle_idx]] = transition_rate;
     }
 
-    pub fn add_reaction(&mut self, reactants: Vec<&str>, products: Vec<&str>, k: f32) {
-        info!("Adding reaction: {} -> {} with rate {}", reactants.iter().map(|x| *x).collect::<Vec<&str>>().join(" + "), products.iter().map(|x| *x).collect::<Vec<&str>>().join(" + "), k);
-        // Add a reaction to the simulation. It is independent of the region since particles are defined per region.
-        // Maybe the following can be made two map iters
+    /// Adds a clamped concentration, a flux into a region or absorbing faces, see `BoundaryCondition`. The species
+    /// and regions it names must exist.
+    pub fn add_boundary_condition(&mut self, condition: BoundaryCondition) {
+        if let Err((field, message)) = condition.validate() {
+            panic!("Boundary condition {}: `{}` {}", condition.name(), field, message);
+        }
+        if let Some(region) = condition.region() {
+            self.find_region_index(region).unwrap_or_else(|| panic!("Region {} not found", region));
+        }
+        for species in condition.species() {
+            self.lattices[0].find_particle(species).unwrap_or_else(|| panic!("Species {} not found", species));
+        }
+        info!("Adding boundary condition: {:?}", condition);
+        self.boundary_conditions.push(condition);
+    }
+
+    pub fn boundary_conditions(&self) -> &[BoundaryCondition] {
+        &self.boundary_conditions
+    }
+
+    /// Voxels of a region next to another region or to a face of the lattice, where its fluxes enter. Faces of
+    /// periodic axes do not count.
+    pub fn boundary_voxels(&self, region: &str) -> usize {
+        let region_idx = self.find_region_index(region).expect("Region not found") as Region;
+        let res = self.lattice_params.get_res_usize();
+        let periodic = self.lattice_params.periodic();
+        let region_at = |voxel: [usize; 3]| self.regions.regions[voxel];
+        (0..res[0]).flat_map(|x| (0..res[1]).flat_map(move |y| (0..res[2]).map(move |z| [x, y, z])))
+            .filter(|voxel| region_at(*voxel) == region_idx && boundary::at_boundary(*voxel, res, periodic, region_at))
+            .count()
+    }
+
+    /// Boundary table and absorbing faces for `cme.wgsl` and `rdme.wgsl`. Region indices and the number of voxels a
+    /// flux is spread over only settle once every region is added, so they are built right before the upload.
+    fn build_boundary_tables(&self) -> (Tensor2<f32>, Tensor1<u32>) {
+        let species_idx = |species: &str| self.lattices[0].find_particle(species).expect("Species are checked when the condition is added");
+        let region_idx = |region: &str| self.find_region_index(region).expect("Regions are checked when the condition is added");
+
+        let mut table = Tensor2::<f32>::zeros((1, 4).f());
+        for condition in self.boundary_conditions.iter() {
+            match condition {
+                BoundaryCondition::Clamp { species, region, concentration } => {
+                    table.concatenate_vector(&vec![CLAMP as f32, species_idx(species) as f32, region_idx(region) as f32, *concentration], 0);
+                },
+                BoundaryCondition::Flux { species, region, rate } => {
+                    // Spread over the boundary of the region, as particles per voxel and step
+                    let per_voxel = match self.boundary_voxels(region) {
+                        0 => 0.,
+                        voxels => rate * self.lattice_params.raw.tau / voxels as f32,
+                    };
+                    table.concatenate_vector(&vec![FLUX as f32, species_idx(species) as f32, region_idx(region) as f32, per_voxel], 0);
+                },
+                BoundaryCondition::Sink { .. } => {},
+            }
+        }
+        let absorbing = self.absorbing_masks();
+        let num_species = absorbing.len();
+        (table, Tensor1::<u32>::from_data(absorbing, StrideShape::from((num_species,))))
+    }
+
+    /// Faces of the lattice that absorb each species, one bit per face in the order of `Face::ALL`.
+    fn absorbing_masks(&self) -> Vec<u32> {
+        let names = &self.lattices[0].particle_names;
+        // Absorbing axes take every species through both faces
+        let absorbing_axes = Face::ALL.iter()
+            .filter(|face| self.lattice_params.boundaries()[face.axis()] == AxisBoundary::Absorbing)
+            .fold(0, |mask, face| mask | 1 << face.index());
+        let mut absorbing = vec![absorbing_axes; names.len()];
+        absorbing[0] = 0;
+        for condition in self.boundary_conditions.iter() {
+            if let BoundaryCondition::Sink { faces, species } = condition {
+                let mask = faces.iter().fold(0, |mask, face| mask | 1 << face.index());
+                let species = if species.is_empty() {
+                    (1..names.len()).collect::<Vec<usize>>()
+                } else {
+                    species.iter().map(|species| self.lattices[0].find_particle(species).expect("Species are checked when the condition is added")).collect()
+                };
+                for idx in species {
+                    absorbing[idx] |= mask;
+                }
+            }
+        }
+        absorbing
+    }
+
+    /// Diffusion rates along x, y and z for a particle in a region. They replace its diffusion rate there, and drift
+    /// is added on top, see `transport`.
+    pub fn set_diffusion_tensor_particle(&mut self, particle: &str, region: &str, diffusion: [f32; 3]) {
+        self.find_region_index(region).unwrap_or_else(|| panic!("Region {} not found", region));
+        self.lattices[0].find_particle(particle).unwrap_or_else(|| panic!("Species {} not found", particle));
+        assert!(diffusion.iter().all(|rate| *rate >= 0.), "Diffusion rates must not be negative");
+        self.diffusion_tensors.insert((particle.to_string(), region.to_string()), diffusion);
+    }
+
+    /// Constant drift velocity, in m/s, of a particle in a region.
+    pub fn set_drift_particle(&mut self, particle: &str, region: &str, velocity: [f32; 3]) {
+        self.find_region_index(region).unwrap_or_else(|| panic!("Region {} not found", region));
+        self.lattices[0].find_particle(particle).unwrap_or_else(|| panic!("Species {} not found", particle));
+        self.drifts.insert((particle.to_string(), region.to_string()), velocity);
+    }
+
+    /// Drift velocity of a particle in each voxel, z fastest, then y and x. It adds to the drift of the regions.
+    pub fn set_drift_field_particle(&mut self, particle: &str, velocities: Vec<[f32; 3]>) {
+        self.lattices[0].find_particle(particle).unwrap_or_else(|| panic!("Species {} not found", particle));
+        let voxels = self.lattice_params.res().iter().product::<u32>() as usize;
+        assert_eq!(velocities.len(), voxels, "A drift field needs a velocity per voxel");
+        self.drift_fields.insert(particle.to_string(), velocities);
+    }
+
+    fn transport_velocity(&self, particle: &str, region: &str, voxel: [usize; 3]) -> [f32; 3] {
+        let mut velocity = self.drifts.get(&(particle.to_string(), region.to_string())).copied().unwrap_or_default();
+        if let Some(field) = self.drift_fields.get(particle) {
+            let res = self.lattice_params.get_res_usize();
+            let field_velocity = field[(voxel[0] * res[1] + voxel[1]) * res[2] + voxel[2]];
+            for (velocity, field_velocity) in velocity.iter_mut().zip(field_velocity) {
+                *velocity += field_velocity;
+            }
+        }
+        velocity
+    }
+
+    /// Probabilities of a particle in a voxel to jump to each neighbour in a step: -x, +x, -y, +y, -z, +z, as
+    /// `rdme.wgsl` computes them. Drift only acts within a region and through absorbing faces of the lattice.
+    pub fn jump_probabilities(&self, particle: &str, voxel: [usize; 3]) -> [f32; 6] {
+        let particle_idx = self.lattices[0].find_particle(particle).unwrap_or_else(|| panic!("Species {} not found", particle));
+        let res = self.lattice_params.get_res_usize();
+        let periodic = self.lattice_params.periodic();
+        let absorbing = self.absorbing_masks()[particle_idx];
+        let (tau, lambda) = (self.lattice_params.raw.tau, self.lattice_params.lambda());
+        let src = self.regions.regions[voxel] as usize;
+        let region = self.regions.types[src].name().expect("Regions are primitives");
+        let tensor = self.diffusion_tensors.get(&(particle.to_string(), region.to_string()));
+        let velocity = self.transport_velocity(particle, region, voxel);
+
+        let mut probabilities = [0.; 6];
+        for (face, probability) in Face::ALL.iter().zip(probabilities.iter_mut()) {
+            let dest = match boundary::neighbour(voxel, *face, res, periodic) {
+                Some(neighbour) => self.regions.regions[neighbour] as usize,
+                None if absorbing & (1 << face.index()) != 0 => src,
+                None => continue,
+            };
+            if dest != src {
+                *probability = transport::jump_probability(self.diffusion_matrix[[src, dest, particle_idx]], 0., tau, lambda);
+                continue;
+            }
+            let (axis, sign) = transport::DIRECTIONS[face.index()];
+            let diffusion = tensor.map_or(self.diffusion_matrix[[src, src, particle_idx]], |tensor| tensor[axis]);
+            *probability = transport::jump_probability(diffusion, sign * velocity[axis], tau, lambda);
+        }
+        probabilities
+    }
+
+    /// Table of `rdme.wgsl` with the diffusion tensors and drift, for `n` regions and `s` species (with the void):
+    /// `3ns` diffusion rates, -1 where a particle has no tensor in a region, `3ns` region drifts, `s` field numbers,
+    /// -1 for particles without a field, then the fields, 3 values per voxel.
+    fn build_transport_table(&self) -> Tensor1<f32> {
+        let names = &self.lattices[0].particle_names;
+        let regions: Vec<String> = self.regions.types.iter().map(|region| region.name().expect("Regions are primitives").to_string()).collect();
+
+        let mut tensors = Vec::with_capacity(3 * regions.len() * names.len());
+        let mut drifts = Vec::with_capacity(3 * regions.len() * names.len());
+        for region in regions.iter() {
+            for particle in names.iter() {
+                let key = (particle.clone(), region.clone());
+                tensors.extend(self.diffusion_tensors.get(&key).copied().unwrap_or([-1.; 3]));
+                drifts.extend(self.drifts.get(&key).copied().unwrap_or_default());
+            }
+        }
+        let mut field_numbers = Vec::with_capacity(names.len());
+        let mut fields = Vec::new();
+        for particle in names.iter() {
+            match self.drift_fields.get(particle) {
+                Some(field) => {
+                    field_numbers.push((fields.len() / (3 * field.len())) as f32);
+                    fields.extend(field.iter().flatten());
+                },
+                None => field_numbers.push(-1.),
+            }
+        }
+        let table: Vec<f32> = tensors.into_iter().chain(drifts).chain(field_numbers).chain(fields).collect();
+        let size = table.len();
+        Tensor1::<f32>::from_data(table, StrideShape::from((size,)))
+    }
+
+    pub fn add_reaction(&mut self, reactants: Vec<&str>, products: Vec<&str>, rate: impl Into<ReactionRate>) {
+        // Add a reaction to the simulation. It happens in every region.
+        self.add_reaction_in_regions(reactants, products, rate, &[]);
+    }
+
+    /// Adds a reaction that only happens in `regions`, or in all of them if it is empty.
+    pub fn add_reaction_in_regions(&mut self, reactants: Vec<&str>, products: Vec<&str>, rate: impl Into<ReactionRate>, regions: &[&str]) {
+        self.add_reaction_with_law(reactants, products, rate, RateLaw::MassAction, regions);
+    }
+
+    /// Adds a reaction whose propensity follows `law`, in `regions` or in all of them if it is empty. Rates with units
+    /// of laws other than mass action are taken as amounts per second, like a zeroth-order rate.
+    ///
+    /// Species named with their region, `L@background` and `R@membrane`, make a surface reaction between neighbouring
+    /// voxels: it happens in the voxels of the region of the first reactant, with the terms of the other region taken
+    /// from and placed in one of their neighbours. Surface reactions follow mass action and fire once per voxel and
+    /// time step at most, so `k * tau` should stay small; `validate` rejects them when it does not, unless
+    /// `set_allow_fast_surface_reactions` is set. Their events are also lost when a neighbouring voxel is changing at
+    /// the same time, more often the more densely they fire; `logged_statistics` counts them as `surface:lost`.
+    pub fn add_reaction_with_law(&mut self, reactants: Vec<&str>, products: Vec<&str>, rate: impl Into<ReactionRate>, law: RateLaw, regions: &[&str]) {
+        if let Err((field, message)) = law.validate() {
+            panic!("Rate law {}: `{}` {}", law.name(), field, message);
+        }
+        let surface = equation::surface_regions(&reactants, &products)
+            .unwrap_or_else(|message| panic!("Reaction {} -> {}: {}", reactants.join(" + "), products.join(" + "), message));
+        let mut regions = regions.to_vec();
+        let mut other_region = None;
+        if let Some((host, other)) = surface {
+            assert!(law == RateLaw::MassAction, "Surface reactions follow mass action");
+            assert!(regions.is_empty(), "Surface reactions take their regions from the equation");
+            regions.push(host);
+            other_region = other;
+        }
+        for region in regions.iter().chain(other_region.iter()) {
+            if self.find_region_index(region).is_none() {
+                panic!("Region {} not found", region);
+            }
+        }
+        // The reaction tables hold the host voxel. Terms in the neighbour voxel of a surface reaction are kept apart
+        let in_neighbour = |name: &str| other_region.is_some() && equation::split_region(name).1 == other_region;
         let mut reactants_idx = Vec::<usize>::new();
-        for reactant in reactants {
-            match self.lattices[0].find_particle(reactant) {
+        let mut neighbour_reactants = Vec::<usize>::new();
+        for reactant in reactants.iter() {
+            match self.lattices[0].find_particle(equation::split_region(reactant).0) {
+                Some(idx) if in_neighbour(reactant) => neighbour_reactants.push(idx),
                 Some(idx) => reactants_idx.push(idx),
                 None => panic!("Reactant {} not found", reactant)
             };
         }
+        let all_reactants = reactants_idx.iter().chain(&neighbour_reactants).copied().collect::<Vec<usize>>();
+        let k = self.convert_rate(rate.into(), &law, &all_reactants);
+        let law_species = match law.species() {
+            Some(species) => self.lattices[0].find_particle(species).unwrap_or_else(|| panic!("Species {} of the rate law not found", species)),
+            None if matches!(law, RateLaw::MichaelisMenten { .. }) => *reactants_idx.first().expect("Michaelis-Menten reactions without reactants need a substrate"),
+            None => 0,
+        };
+        info!("Adding reaction: {} -> {} with rate {}", reactants.join(" + "), products.join(" + "), k);
         let mut products_idx = Vec::<usize>::new();
+        let mut neighbour_products = Vec::<usize>::new();
         for product in products {
-            match self.lattices[0].find_particle(product) {
+            match self.lattices[0].find_particle(equation::split_region(product).0) {
+                Some(idx) if in_neighbour(product) => neighbour_products.push(idx),
                 Some(idx) => products_idx.push(idx),
                 None => panic!("Product {} not found", product)
             };
@@ -933,8 +1977,12 @@ impl Simulation {
         }
         debug!("New stoichiometry matrix: {} with {} rows and {} columns", self.stoichiometry_matrix, self.stoichiometry_matrix.shape()[0], self.stoichiometry_matrix.shape()[1]);
 
-        // Update index matrix
-        reactants_idx.extend(std::iter::repeat(0 as usize).take(3 - reactants_idx.len()));
+        // Update index matrix. Rows are as wide as the largest reaction, padded with the void species
+        while self.reactions_idx.shape()[1] < reactants_idx.len() {
+            self.reactions_idx.enlarge_dimension(1, 0);
+        }
+        self.reaction_params.raw_params.reactant_slots = self.reactions_idx.shape()[1] as u32;
+        reactants_idx.resize(self.reactions_idx.shape()[1], 0);
         let reactants_idx_u32 = reactants_idx.iter().map(|x| *x as u32).collect::<Vec<u32>>();
         self.reactions_idx.concatenate_vector(&reactants_idx_u32, 0);
         debug!("Reactants idx : {:?}", reactants_idx_u32);
@@ -943,8 +1991,163 @@ impl Simulation {
         // Update reaction rates vector
         self.reaction_rates.concatenate_vector(&vec![k], 0);
         debug!("New reaction rates vector: {}", self.reaction_rates);
+        // Update rate law table. Rows are as wide as the largest law, padded with zeros
+        let mut law_row = law.to_lattice(law_species);
+        while self.rate_laws.shape()[1] < law_row.len() {
+            self.rate_laws.enlarge_dimension(1, 0.);
+        }
+        self.reaction_params.raw_params.law_slots = self.rate_laws.shape()[1] as u32;
+        law_row.resize(self.rate_laws.shape()[1], 0.);
+        self.rate_laws.concatenate_vector(&law_row, 0);
+        debug!("New rate law table: {}", self.rate_laws);
+
+        self.reaction_laws.push(law);
+        self.reaction_regions.push(regions.iter().map(|region| region.to_string()).collect());
+        self.reaction_logging.push(false);
+        self.rate_schedules.push(Vec::new());
+        self.surface_reactions.push(other_region.map(|region| SurfaceReaction {
+            neighbour_region: region.to_string(),
+            reactants: neighbour_reactants,
+            products: neighbour_products,
+        }));
         self.reaction_params.raw_params.num_reactions += 1;
     }
+
+    /// Mask of shape (reactions + 1, regions) that `cme.wgsl` multiplies the propensities by. Region indices
+    /// only settle once every region is added, so it is built right before the upload.
+    fn build_reaction_mask(&self) -> Tensor2<u32> {
+        let num_regions = self.regions.types.len();
+        let mut mask = Tensor2::<u32>::zeros((self.reaction_regions.len() + 1, num_regions).f());
+        for reaction in 1..=self.reaction_regions.len() {
+            // Surface reactions have their own kernel, see build_surface_table
+            if self.surface_reactions[reaction - 1].is_some() {
+                continue;
+            }
+            for region in (0..num_regions).filter(|region| self.happens_in(reaction, *region)) {
+                mask[[reaction, region]] = 1;
+            }
+        }
+        mask
+    }
+
+    /// Whether a row of the reaction tables happens in voxels of `region`. Surface reactions happen in their host region.
+    fn happens_in(&self, reaction: usize, region: usize) -> bool {
+        let regions = &self.reaction_regions[reaction - 1];
+        let name = self.regions.types[region].name().unwrap_or_default();
+        regions.is_empty() || regions.iter().any(|r| r == name)
+    }
+
+    /// Neighbour reactants and width of a row of the surface reaction table.
+    fn surface_slots(&self) -> (usize, usize) {
+        let num_species = self.reaction_params.raw_params.num_species as usize;
+        let reactant_slots = self.surface_reactions.iter().flatten().map(|surface| surface.reactants.len()).max().unwrap_or(0).max(1);
+        (reactant_slots, 2 + reactant_slots + num_species + 1)
+    }
+
+    /// Table of the surface reactions for `cme_surface` in cme.wgsl, a row per reaction. Rows of other reactions are 0.
+    fn build_surface_table(&mut self) -> Tensor2<i32> {
+        let (reactant_slots, width) = self.surface_slots();
+        self.reaction_params.raw_params.surface_slots = width as u32;
+        self.reaction_params.raw_params.surface_reactant_slots = reactant_slots as u32;
+
+        let mut table = Tensor2::<i32>::zeros((self.surface_reactions.len() + 1, width).f());
+        for (i, surface) in self.surface_reactions.iter().enumerate() {
+            let Some(surface) = surface else { continue };
+            let neighbour_region = self.find_region_index(&surface.neighbour_region).expect("Regions are checked when the reaction is added");
+            let host_region = self.find_region_index(&self.reaction_regions[i][0]).expect("Regions are checked when the reaction is added");
+            table[[i + 1, 0]] = neighbour_region as i32 + 1;
+            table[[i + 1, 1]] = host_region as i32;
+            for (slot, species) in surface.reactants.iter().enumerate() {
+                table[[i + 1, 2 + slot]] = *species as i32;
+            }
+            // Change of the neighbour voxel
+            for species in surface.reactants.iter() {
+                table[[i + 1, 2 + reactant_slots + species]] -= 1;
+            }
+            for species in surface.products.iter() {
+                table[[i + 1, 2 + reactant_slots + species]] += 1;
+            }
+        }
+        table
+    }
+
+    /// Per-voxel rate of a reaction with `law` and these reactants, given as species indices with repetitions.
+    fn convert_rate(&self, rate: ReactionRate, law: &RateLaw, reactants_idx: &[usize]) -> f32 {
+        match law {
+            RateLaw::MassAction => rate.stochastic_rate(reactants_idx, self.lattice_params.voxel_volume()),
+            _ => rate.to_lattice(0, self.lattice_params.voxel_volume()),
+        }
+    }
+
+    /// Sets the per-voxel rate of a reaction to `value` from the simulated `time` on. The reaction is given by its
+    /// position among the added reactions, as in `propensities`.
+    pub fn schedule_rate(&mut self, reaction: usize, time: f32, value: f32) {
+        self.schedule_signal(reaction, RateSignal::Step { time, value });
+    }
+
+    /// Makes the rate of a reaction follow `signal`, given in per-voxel rates, once it starts. See `schedule`.
+    pub fn schedule_signal(&mut self, reaction: usize, signal: RateSignal) {
+        if let Err((field, message)) = signal.validate() {
+            panic!("Rate signal {}: `{}` {}", signal.name(), field, message);
+        }
+        self.rate_schedules[reaction].push(signal);
+    }
+
+    /// Per-voxel rate of each reaction at the simulated `time`, as the shader gets it.
+    pub fn rates_at(&self, time: f32) -> Vec<f32> {
+        self.rate_schedules.iter().enumerate()
+            .map(|(i, signals)| schedule::rate_at(self.reaction_rates[[i + 1, 0]], signals, time))
+            .collect()
+    }
+
+    fn upload_scheduled_rates(&mut self, time: f32, queue: &wgpu::Queue) {
+        if self.rate_schedules.iter().all(Vec::is_empty) {
+            return;
+        }
+        let rates = self.rates_at(time);
+        if rates == self.uploaded_rates {
+            return;
+        }
+        // Row 0 is the placeholder
+        let data = std::iter::once(0.).chain(rates.iter().copied()).collect::<Vec<f32>>();
+        // Into the rates buffer itself: no allocation per step
+        queue.write_buffer(self.reaction_rates.buffer(), 0, bytemuck::cast_slice(&data));
+        self.uploaded_rates = rates;
+    }
+
+    /// Logs the firings of a reaction, given by its position among the added reactions as in `propensities`.
+    pub fn set_reaction_logging(&mut self, reaction: usize, logging: bool) {
+        self.reaction_logging[reaction] = logging;
+    }
+
+    /// Propensity of each reaction in a voxel of `region` with `counts[species]` particles, as `cme.wgsl` computes it.
+    /// Surface reactions need the neighbours of the voxel, they are 0 here (see `surface_propensity`).
+    pub fn propensities(&self, region: &str, counts: &[i32]) -> Vec<f32> {
+        let num_reactions = self.reaction_params.raw_params.num_reactions as usize;
+        (1..=num_reactions).map(|reaction| {
+            let regions = &self.reaction_regions[reaction - 1];
+            if self.surface_reactions[reaction - 1].is_some() || (!regions.is_empty() && !regions.iter().any(|r| r == region)) {
+                return 0.;
+            }
+            let reactants = (0..self.reactions_idx.shape()[1]).map(|j| self.reactions_idx[[reaction, j]]).collect::<Vec<u32>>();
+            let law = (0..self.rate_laws.shape()[1]).map(|j| self.rate_laws[[reaction, j]]).collect::<Vec<f32>>();
+            rates::law_propensity(self.reaction_rates[[reaction, 0]], &reactants, &law, counts)
+        }).collect()
+    }
+
+    /// Propensity of a surface reaction in a voxel of its host region with `counts[species]` particles, as `cme.wgsl`
+    /// computes it. `neighbours` are the region and counts of the adjacent voxels; only those in the other region of
+    /// the reaction count. The reaction is given by its position as in `propensities`, other reactions give 0.
+    pub fn surface_propensity(&self, reaction: usize, counts: &[i32], neighbours: &[(&str, &[i32])]) -> f32 {
+        let Some(surface) = &self.surface_reactions[reaction] else { return 0. };
+        let reactants = (0..self.reactions_idx.shape()[1]).map(|j| self.reactions_idx[[reaction + 1, j]]).collect::<Vec<u32>>();
+        let neighbour_reactants = surface.reactants.iter().map(|species| *species as u32).collect::<Vec<u32>>();
+        let neighbours = neighbours.iter()
+            .filter(|(region, _)| *region == surface.neighbour_region)
+            .map(|(_, counts)| *counts)
+            .collect::<Vec<&[i32]>>();
+        rates::surface_propensity(self.reaction_rates[[reaction + 1, 0]], &reactants, &neighbour_reactants, counts, &neighbours)
+    }
 }
 
 
@@ -1000,6 +2203,17 @@ impl Simulation {
                         },
                         count: None,
                     },
+                    // Diffusion tensors and drift
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(self.transport_table.buffer_size() as _)
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("Data bind group layout")
             }
@@ -1019,6 +2233,28 @@ impl Simulation {
                         },
                         count: None,
                     },
+                    // Clamps and fluxes
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer { 
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(self.boundary_table.buffer_size() as _),
+                        },
+                        count: None,
+                    },
+                    // Absorbing faces
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer { 
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(self.absorbing_faces.buffer_size() as _),
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("Boundaries bind group layout")
             }
@@ -1133,6 +2369,36 @@ impl Simulation {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer { 
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(self.reaction_mask.buffer_size() as _)
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer { 
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(self.rate_laws.buffer_size() as _)
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer { 
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(self.surface_table.buffer_size() as _)
+                        },
+                        count: None,
+                    },
                 ],
                 label: None
             }
@@ -1177,6 +2443,10 @@ impl Simulation {
                         binding: 4,
                         resource: self.diffusion_matrix.buffer.as_ref().expect("").as_entire_binding(),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: self.transport_table.binding_resource(),
+                    },
                 ],
                 label: Some("Data bind group"),
             })
@@ -1235,6 +2505,18 @@ impl Simulation {
                         binding: 3,
                         resource: self.reaction_rates.binding_resource(),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: self.reaction_mask.binding_resource(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: self.rate_laws.binding_resource(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: self.surface_table.binding_resource(),
+                    },
                 ],
                 label: Some("Reactions bind group"),
             })
@@ -1248,6 +2530,14 @@ impl Simulation {
                         binding: 0,
                         resource: self.lattices[0].reservoir.binding_resource(),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: self.boundary_table.binding_resource(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.absorbing_faces.binding_resource(),
+                    },
                 ],
                 label: Some("Boundaries bind group"),
             })
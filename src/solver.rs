@@ -0,0 +1,130 @@
+//! The reaction loop of `cme.wgsl` for one voxel, on the CPU.
+//!
+//! `Voxel::step` takes the same decisions as the `cme` kernel for each `ReactionSolver`, drawing from the same
+//! `random::Stream`, so the statistics of the solvers can be checked without a GPU. It covers mass-action reactions
+//! in a voxel without regions, rate laws, reservoirs or boundary conditions.
+
+use crate::random::Stream;
+use crate::rates;
+use crate::reactions_params::ReactionSolver;
+
+/// Gillespie steps of a voxel in one time step. `CME::new` compiles it into `cme.wgsl`.
+pub const MAX_EVENTS: u32 = 1024;
+
+/// Number of events of a Poisson process with the given mean, as `poisson` in `cme.wgsl`. Normal approximation for
+/// large means.
+pub fn poisson(mean: f32, stream: &mut Stream) -> i32 {
+    if mean > 30. {
+        let u1 = 1. - stream.next_f32();
+        let u2 = stream.next_f32();
+        let normal = (-2. * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos();
+        return ((mean + mean.sqrt() * normal).round_ties_even() as i32).max(0);
+    }
+    let limit = (-mean).exp();
+    let mut product = stream.next_f32();
+    let mut events = 0;
+    while product > limit {
+        product *= stream.next_f32();
+        events += 1;
+    }
+    events
+}
+
+/// Reactions of a voxel, with the layout of the shader: species are numbered from 1 and `counts[0]` is unused.
+#[derive(Debug, Clone)]
+pub struct Voxel {
+    /// Per-voxel rate of each reaction
+    pub rates: Vec<f32>,
+    /// Row of the reactions index of each reaction, padded with species 0
+    pub reactants: Vec<Vec<u32>>,
+    /// Row of the stoichiometry matrix of each reaction, also from species 1
+    pub changes: Vec<Vec<i32>>,
+    pub max_particles_site: u32,
+    pub counts: Vec<i32>,
+}
+
+impl Voxel {
+    /// Cumulative propensities, as `compute_propensities` leaves them in `cumm_propensity`.
+    fn propensities(&self) -> Vec<f32> {
+        let mut cumulative = vec![0.; self.rates.len() + 1];
+        for (i, (k, reactants)) in self.rates.iter().zip(self.reactants.iter()).enumerate() {
+            cumulative[i + 1] = cumulative[i] + rates::propensity(*k, reactants, &self.counts);
+        }
+        cumulative
+    }
+
+    fn select_reaction(&self, cumulative: &[f32], stream: &mut Stream) -> usize {
+        let threshold = stream.next_f32() * cumulative[cumulative.len() - 1];
+        let mut i = 1;
+        while i < self.rates.len() && threshold >= cumulative[i] {
+            i += 1;
+        }
+        i - 1
+    }
+
+    /// Fires a reaction `times` times, as `fire` does: nothing changes, and it returns false, if a count would
+    /// become negative or the particles would not fit in the site.
+    fn fire(&mut self, reaction: usize, times: i32, firings: &mut [u32]) -> bool {
+        let counts = self.counts.iter().zip(self.changes[reaction].iter()).map(|(count, change)| count + times * change);
+        let mut occupancy = 0;
+        for count in counts.clone().skip(1) {
+            if count < 0 {
+                return false;
+            }
+            occupancy += count;
+        }
+        // As in the shader, the last slot of a site stays empty
+        if occupancy as u32 >= self.max_particles_site {
+            return false;
+        }
+        self.counts = counts.collect();
+        firings[reaction] += times as u32;
+        true
+    }
+
+    /// Advances the voxel by a time step of `tau` as the `cme` kernel does, and returns how many times each reaction
+    /// fired.
+    pub fn step(&mut self, solver: ReactionSolver, tau: f32, stream: &mut Stream) -> Vec<u32> {
+        let mut firings = vec![0; self.rates.len()];
+        if solver == ReactionSolver::SingleEvent {
+            let cumulative = self.propensities();
+            let total = cumulative[self.rates.len()];
+            if total > 0. && stream.next_f32() <= 1. - (-total * tau).exp() {
+                let reaction = self.select_reaction(&cumulative, stream);
+                self.fire(reaction, 1, &mut firings);
+            }
+            return firings;
+        }
+
+        let mut t = 0.;
+        for _ in 0..MAX_EVENTS {
+            let cumulative = self.propensities();
+            let total = cumulative[self.rates.len()];
+            if total <= 0. {
+                break;
+            }
+
+            let remaining = tau - t;
+            if let ReactionSolver::TauLeaping { threshold } = solver {
+                if total * remaining > threshold {
+                    for reaction in 0..self.rates.len() {
+                        let mut times = poisson((cumulative[reaction + 1] - cumulative[reaction]) * remaining, stream);
+                        while times > 0 && !self.fire(reaction, times, &mut firings) {
+                            times /= 2;
+                        }
+                    }
+                    break;
+                }
+            }
+
+            t += -(1. - stream.next_f32()).ln() / total;
+            if t > tau {
+                break;
+            }
+            // Rejected if it does not fit, as the kernel does
+            let reaction = self.select_reaction(&cumulative, stream);
+            self.fire(reaction, 1, &mut firings);
+        }
+        firings
+    }
+}
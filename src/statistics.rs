@@ -11,7 +11,7 @@ pub struct SolverStatisticSample<T> {
 pub struct StatisticsGroup {
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
-    pub stats: Vec<Tensor1<i32>>,  // For now it's only the concentration of the particles
+    pub stats: Vec<Tensor1<i32>>,  // Concentration of the particles and firings of the reactions
     pub logging_stats: HashMap<String, [u32; 2]>  // Idx, padding
 }
 
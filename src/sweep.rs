@@ -0,0 +1,257 @@
+//! Parameter sweeps: one model file expanded into many runs.
+//!
+//! A sweep manifest names a model file, relative to the manifest, and the values to give to some of its keys:
+//!
+//! ```json
+//! {
+//!     "model": "easy.json",
+//!     "seed": 7,
+//!     "sweep": [
+//!         {"key": "reactions.A + B -> C", "values": [1.0, 5.82, 10.0]},
+//!         {"key": "constants.D_cyto", "log_range": {"from": 1e-15, "to": 1e-13, "steps": 3}},
+//!         {"key": "particles[0].count", "grid": {"from": 100, "to": 400, "steps": 4}},
+//!         {"key": "parameters.tau", "random": {"from": 1e-3, "to": 5e-3, "samples": 2}}
+//!     ]
+//! }
+//! ```
+//!
+//! Keys are written as the paths in `ModelError`. Every combination of values is a run. Each run gets an id
+//! computed from its values, so it stays the same when the manifest is reordered or a value set grows, and
+//! its model is written as `<id>.json` next to a `runs.json` with the values of every run.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::model::{self, Model, ModelError};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepManifest {
+    /// Model file that the runs start from
+    pub model: PathBuf,
+    /// Seed of the `random` value sets
+    #[serde(default)]
+    pub seed: u64,
+    pub sweep: Vec<SweepParameter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SweepParameter {
+    /// Path of the value in the model file, e.g. `particles[0].count`
+    pub key: String,
+    #[serde(flatten)]
+    pub values: ValueSet,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueSet {
+    /// Values as they are, expressions included
+    Values(Vec<Value>),
+    /// `steps` values evenly spaced from `from` to `to`
+    Grid { from: f64, to: f64, steps: usize },
+    /// `steps` values from `from` to `to` with a constant ratio
+    LogRange { from: f64, to: f64, steps: usize },
+    /// Uniform samples between `from` and `to`, or log-uniform ones with `log`. `integer` rounds them.
+    Random {
+        from: f64,
+        to: f64,
+        samples: usize,
+        #[serde(default)]
+        log: bool,
+        #[serde(default)]
+        integer: bool,
+    },
+}
+
+impl ValueSet {
+    /// The values of the set. `seed` only matters for random samples.
+    pub fn values(&self, seed: u64) -> Result<Vec<Value>, String> {
+        let steps = |from: f64, to: f64, steps: usize, log: bool| -> Result<Vec<Value>, String> {
+            if steps == 0 {
+                return Err("`steps` must be at least 1".to_string());
+            }
+            Ok((0..steps).map(|i| {
+                let t = if steps == 1 { 0. } else { i as f64 / (steps - 1) as f64 };
+                model::number(if log { from * (to / from).powf(t) } else { from + (to - from) * t })
+            }).collect())
+        };
+        let check_log = |from: f64, to: f64| match from > 0. && to > 0. {
+            true => Ok(()),
+            false => Err("a logarithmic scale needs positive `from` and `to`".to_string()),
+        };
+
+        match *self {
+            ValueSet::Values(ref values) if values.is_empty() => Err("`values` is empty".to_string()),
+            ValueSet::Values(ref values) => Ok(values.clone()),
+            ValueSet::Grid { from, to, steps: n } => steps(from, to, n, false),
+            ValueSet::LogRange { from, to, steps: n } => {
+                check_log(from, to)?;
+                steps(from, to, n, true)
+            },
+            ValueSet::Random { from, to, samples, log, integer } => {
+                if log {
+                    check_log(from, to)?;
+                }
+                if samples == 0 {
+                    return Err("`samples` must be at least 1".to_string());
+                }
+                // Unlike `StdRng`, the same samples in every release of rand
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                Ok((0..samples).map(|_| {
+                    let t = rng.gen::<f64>();
+                    let value = if log { from * (to / from).powf(t) } else { from + (to - from) * t };
+                    model::number(if integer { value.round() } else { value })
+                }).collect())
+            },
+        }
+    }
+}
+
+/// One combination of values of a sweep.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    pub id: String,
+    /// Value given to each key of the manifest
+    pub values: Map<String, Value>,
+    /// The model file of the run, as JSON. It is written to `<id>.json`, not to `runs.json`.
+    #[serde(skip)]
+    pub model: Value,
+}
+
+impl Run {
+    pub fn to_model(&self) -> Result<Model, ModelError> {
+        Model::from_value(self.model.clone())
+    }
+
+    /// Name of the model file of the run written by `Sweep::write`.
+    pub fn file_name(&self) -> String {
+        format!("{}.json", self.id)
+    }
+}
+
+pub struct Sweep {
+    pub manifest: SweepManifest,
+    /// The model with its includes and templates expanded, but with its expressions, so that sweeping
+    /// a constant changes everything that uses it
+    base: Value,
+}
+
+impl Sweep {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ModelError> {
+        let path = path.as_ref();
+        let manifest: SweepManifest = model::from_value(model::read_file(path)?, "")?;
+        let model_path = path.parent().unwrap_or_else(|| Path::new("")).join(&manifest.model);
+        let base = model::compose(model::read_file(&model_path)?, Some(&model_path))?;
+        Ok(Sweep { manifest, base })
+    }
+
+    /// Sweep of a model that is already in memory. Includes are found relative to the working directory.
+    pub fn new(manifest: SweepManifest, model: Value) -> Result<Self, ModelError> {
+        let base = model::compose(model, None)?;
+        Ok(Sweep { manifest, base })
+    }
+
+    /// Every combination of values, each with its model checked by `Model::from_value`.
+    pub fn runs(&self) -> Result<Vec<Run>, ModelError> {
+        let mut value_sets = Vec::new();
+        for (i, parameter) in self.manifest.sweep.iter().enumerate() {
+            if find(&mut self.base.clone(), &parameter.key).is_none() {
+                return Err(ModelError::invalid(format!("sweep[{}].key", i), format!("`{}` is not in the model", parameter.key)));
+            }
+            // Each key has its own samples, which do not change when other keys are added
+            let seed = self.manifest.seed ^ stable_hash(&parameter.key);
+            let values = parameter.values.values(seed).map_err(|message| ModelError::invalid(format!("sweep[{}]", i), message))?;
+            value_sets.push(values);
+        }
+
+        let mut runs: Vec<Run> = Vec::new();
+        for combination in combinations(&value_sets) {
+            let mut model = self.base.clone();
+            let mut values = Map::new();
+            for (parameter, value) in self.manifest.sweep.iter().zip(combination) {
+                *find(&mut model, &parameter.key).expect("keys are checked above") = value.clone();
+                values.insert(parameter.key.clone(), value);
+            }
+
+            // Sorted, so that the order of the manifest does not matter
+            let sorted = values.iter().collect::<BTreeMap<&String, &Value>>();
+            let id = format!("run-{:016x}", stable_hash(&serde_json::to_string(&sorted).expect("JSON values serialize")));
+            if let Some(run) = runs.iter().find(|run| run.id == id) {
+                return Err(ModelError::invalid("sweep", format!("two runs have the same values {}", Value::Object(run.values.clone()))));
+            }
+            let run = Run { id, values, model };
+            run.to_model().map_err(|e| match e {
+                ModelError::Schema { path, message } | ModelError::Invalid { path, message } => {
+                    ModelError::invalid(path, format!("run {} ({}): {}", run.id, Value::Object(run.values.clone()), message))
+                },
+                e => e,
+            })?;
+            runs.push(run);
+        }
+        Ok(runs)
+    }
+
+    /// Writes the model of every run to `directory`, and their values to `runs.json`.
+    pub fn write<P: AsRef<Path>>(&self, directory: P) -> Result<Vec<Run>, ModelError> {
+        let directory = directory.as_ref();
+        let runs = self.runs()?;
+        std::fs::create_dir_all(directory).map_err(|source| ModelError::Io { file: directory.to_path_buf(), source })?;
+
+        for run in runs.iter() {
+            write_json(&directory.join(run.file_name()), &run.model)?;
+        }
+        write_json(&directory.join("runs.json"), &runs)?;
+        Ok(runs)
+    }
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), ModelError> {
+    serde_json::to_string_pretty(value)
+        .map_err(std::io::Error::from)
+        .and_then(|data| std::fs::write(path, data))
+        .map_err(|source| ModelError::Io { file: path.to_path_buf(), source })
+}
+
+/// Cartesian product of the value sets, the last one changing fastest.
+fn combinations(value_sets: &[Vec<Value>]) -> Vec<Vec<Value>> {
+    value_sets.iter().fold(vec![Vec::new()], |combinations, values| {
+        combinations.iter()
+            .flat_map(|combination| values.iter().map(move |value| {
+                let mut combination = combination.clone();
+                combination.push(value.clone());
+                combination
+            }))
+            .collect()
+    })
+}
+
+/// Value at `key`, a path like `particles[0].count` or `reactions.A + B -> C`. Object keys can contain
+/// dots, the longest key that matches is taken.
+fn find<'a>(value: &'a mut Value, key: &str) -> Option<&'a mut Value> {
+    if key.is_empty() {
+        return Some(value);
+    }
+    if let Some(rest) = key.strip_prefix('[') {
+        let (index, rest) = rest.split_once(']')?;
+        let element = value.as_array_mut()?.get_mut(index.parse::<usize>().ok()?)?;
+        return find(element, rest.strip_prefix('.').unwrap_or(rest));
+    }
+    let fields = value.as_object_mut()?;
+    let field = fields.keys()
+        .filter(|field| key.strip_prefix(field.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '['])))
+        .max_by_key(|field| field.len())?
+        .clone();
+    let rest = &key[field.len()..];
+    find(fields.get_mut(&field)?, rest.strip_prefix('.').unwrap_or(rest))
+}
+
+/// FNV-1a, which unlike `DefaultHasher` gives the same ids on every platform and Rust version.
+fn stable_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
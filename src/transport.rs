@@ -0,0 +1,30 @@
+//! Directed transport: anisotropic diffusion and drift.
+//!
+//! A particle jumps to each of the 6 neighbours of its voxel with its own probability. Diffusion gives
+//! `D tau / lambda^2`, with `D` the rate along the axis of the jump when the particle has a diffusion tensor in
+//! its region. A drift velocity `v` adds `v tau / lambda` to the jumps along it and nothing against it (upwind), so
+//! the mean displacement is `v tau` per step. Drift is constant in a region, given per voxel, or the sum of both.
+
+use crate::boundary::Face;
+
+/// Directions of the jumps, in the order of the shaders.
+pub const DIRECTIONS: [(usize, f32); 6] = [(0, -1.), (0, 1.), (1, -1.), (1, 1.), (2, -1.), (2, 1.)];
+
+/// Probability of one jump in a time step, as `rdme.wgsl` computes it. `velocity` is the drift along the
+/// direction of the jump, negative if it points the other way.
+pub fn jump_probability(diffusion: f32, velocity: f32, tau: f32, lambda: f32) -> f32 {
+    diffusion * tau / (lambda * lambda) + velocity.max(0.) * tau / lambda
+}
+
+/// Jump of a particle for a uniform number in [0, 1), picked as `rdme.wgsl` does from the cumulative probabilities
+/// of the 6 directions. `None` stays in the voxel.
+pub fn select_jump(probabilities: [f32; 6], uniform: f32) -> Option<Face> {
+    let mut cumulative = 0.;
+    for (face, probability) in Face::ALL.iter().zip(probabilities) {
+        cumulative += probability;
+        if uniform < cumulative {
+            return Some(*face);
+        }
+    }
+    None
+}
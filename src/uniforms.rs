@@ -6,6 +6,8 @@ use std::time::{SystemTime, UNIX_EPOCH};
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub struct Uniform {
+    // Wall clock, for rendering only: no simulation kernel reads it. They draw from `Simulation::seed` and
+    // `frame_num`, so the time a run starts does not change it
     pub itime: u32,
     pub frame_num: u32,
     pub slice: u32,
@@ -1,5 +1,6 @@
 use cgmath::num_traits::ToPrimitive;
-use rand::{Rng, rngs::ThreadRng};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
 use serde::Deserialize;
 use serde_json::Value;
 use std::convert::TryFrom;
@@ -11,7 +12,7 @@ macro_rules! debug_println_old {
     ($($arg:tt)*) => (if ::std::cfg!(debug_assertions) { ::std::println!($($arg)*); })
 }
 
-pub fn random_direction_sphere(rng: &mut ThreadRng) -> [f32; 3] {
+pub fn random_direction_sphere(rng: &mut ChaCha8Rng) -> [f32; 3] {
     let theta = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
     let phi = (1.0 - 2.0 * rng.gen::<f32>()).acos();
     let x = phi.sin() * theta.cos();
@@ -21,12 +22,12 @@ pub fn random_direction_sphere(rng: &mut ThreadRng) -> [f32; 3] {
 }
 
 
-pub fn random_walk_direction(rng: &mut ThreadRng, lattice: &Lattice, site: &[u32; 3], block_length: f32, step_backwards: f32, regions_idx_buffer: &Vec<u32>) -> [f32; 3] {
+pub fn random_walk_direction(rng: &mut ChaCha8Rng, lattice: &Lattice, site: &[u32; 3], block_length: f32, step_backwards: f32, regions_idx_buffer: &Vec<u32>) -> [f32; 3] {
     /// Generates a random direction for the random walk. Taking into account the distance to the boundary of the region.
     ///
     /// # Arguments
     ///
-    /// * `rng` - A mutable reference to a `ThreadRng` object, which is used to generate random numbers.
+    /// * `rng` - A mutable reference to a `ChaCha8Rng` object, which is used to generate random numbers.
     /// * `lattice` - A reference to a `Lattice` object, which represents the lattice in which the particle is moving.
     /// * `site` - A reference to an array of three `u32` values, which represents the current site of the particle in the lattice.
     /// * `block_length` - A `f32` value representing the length of a block in the lattice.
@@ -69,11 +70,6 @@ pub fn random_walk_direction(rng: &mut ThreadRng, lattice: &Lattice, site: &[u32
     direction
 }
 
-pub fn split_whitespace(s: &str) -> Vec<&str> {
-    let words: Vec<&str> = s.split_whitespace().collect();
-    return words;
-}
-
 pub fn split_comma(s: &str) -> Vec<&str> {
     let words: Vec<&str> = s.split(",").collect();
     return words;
@@ -0,0 +1,98 @@
+//! Checks run on a built `Simulation` before it is uploaded to the GPU.
+//!
+//! The shaders have limits set when they are built and assume probabilities below 1. Breaking them does not fail,
+//! it produces wrong dynamics, so `Simulation::validate` looks for it on the CPU first.
+
+use std::fmt;
+
+/// Largest species index the texture can show, as it is encoded as `species / 255`.
+pub const MAX_TEXTURE_SPECIES: usize = 255;
+
+/// Largest `max_reactions`. Every invocation of `cme.wgsl` holds `max_reactions + 1` propensities in a private array,
+/// 4 KiB at this size; larger arrays spill out of registers or fail to compile on some drivers.
+pub const MAX_REACTIONS_CAP: usize = 1023;
+
+/// Expected firings of a surface reaction in a voxel and step, `a * tau`, above which it is reported. `cme_surface`
+/// fires it once at most, with probability `1 - exp(-a * tau)`, which is 5% short of the rate at this value.
+pub const MAX_SURFACE_FIRINGS: f64 = 0.1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// The probability to leave a voxel, `6 * D * tau / lambda^2`, is above 1. When `from_region` and
+    /// `to_region` differ it only happens in voxels next to `to_region`.
+    DiffusionProbability { particle: String, from_region: String, to_region: String, probability: f64 },
+    /// With a diffusion tensor or drift, the jump probabilities of a particle add up to more than 1. `voxel` is
+    /// where they are highest.
+    JumpProbability { particle: String, voxel: [usize; 3], probability: f64 },
+    /// A surface reaction would fire `firings` times per step in `voxel`, with the current counts, but fires once
+    /// at most
+    SurfaceFirings { reaction: String, voxel: [usize; 3], firings: f64 },
+    TooManyReactions { reactions: usize, max: usize },
+    TooManySpecies { species: usize, max: usize },
+    /// `missing` particles were left out when they were added, as the sites of `region` were full
+    ParticlesDoNotFit { particle: String, region: String, missing: u32 },
+    /// A storage buffer, in bytes, is larger than the device can bind
+    BufferTooLarge { buffer: String, size: u64, max: u64 },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::DiffusionProbability { particle, from_region, to_region, probability } if from_region == to_region => {
+                write!(f, "particle `{}` leaves a voxel of `{}` with probability {:.3} per step. Lower tau or the diffusion rate", particle, from_region, probability)
+            },
+            Issue::DiffusionProbability { particle, from_region, to_region, probability } => {
+                write!(f, "particle `{}` can leave a voxel of `{}` next to `{}` with probability {:.3} per step", particle, from_region, to_region, probability)
+            },
+            Issue::JumpProbability { particle, voxel, probability } => {
+                write!(f, "particle `{}` leaves voxel {:?} with probability {:.3} per step. Lower tau, the diffusion tensor or the drift", particle, voxel, probability)
+            },
+            Issue::SurfaceFirings { reaction, voxel, firings } => {
+                write!(f, "surface reaction `{}` would fire {:.3} times per step in voxel {:?}, but fires once at most. Lower tau or the rate", reaction, firings, voxel)
+            },
+            Issue::TooManyReactions { reactions, max } => write!(f, "{} reactions, at most {} are supported", reactions, max),
+            Issue::TooManySpecies { species, max } => write!(f, "{} species, at most {} can be rendered", species, max),
+            Issue::ParticlesDoNotFit { particle, region, missing } => {
+                write!(f, "{} particles of `{}` did not fit in `{}` and were left out. Raise max_particles_site or lower the count", missing, particle, region)
+            },
+            Issue::BufferTooLarge { buffer, size, max } => {
+                write!(f, "the {} buffer takes {} bytes, the device binds at most {}. Lower the resolution, max_particles_site or the number of species and reactions", buffer, size, max)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Validation {
+    /// The simulation would run, but not as intended
+    pub errors: Vec<Issue>,
+    /// Suspicious values that may be intended
+    pub warnings: Vec<Issue>,
+}
+
+impl Validation {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Adds the errors and warnings of `other`.
+    pub fn extend(&mut self, other: Validation) {
+        self.errors.extend(other.errors);
+        self.warnings.extend(other.warnings);
+    }
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the simulation has {} errors and {} warnings", self.errors.len(), self.warnings.len())?;
+        for error in self.errors.iter() {
+            write!(f, "\n  error: {}", error)?;
+        }
+        for warning in self.warnings.iter() {
+            write!(f, "\n  warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Validation {}
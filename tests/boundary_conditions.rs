@@ -0,0 +1,64 @@
+use simulation::{BoundaryCondition, Face, LatticeParams, Model, Simulation};
+
+fn model(boundaries: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9}},
+        "regions": [{{"type": "sphere", "name": "cell", "center": [0.2, 0.2, 0.2], "radius": 0.15, "base_diffusion_rate": 1e-14}}],
+        "particles": [
+            {{"name": "A", "to_region": "cell", "count": 10}},
+            {{"name": "B", "to_region": "background", "count": 10}}
+        ],
+        "boundaries": {}
+    }}"#, boundaries).parse().unwrap()
+}
+
+#[test]
+fn boundaries_in_model_files() {
+    let model = model(r#"[
+        {"clamp": {"species": "A", "region": "cell", "concentration": 0.5}},
+        {"flux": {"species": "B", "region": "background", "rate": 100}},
+        {"sink": {"faces": ["-x", "+x"], "species": ["B"]}}
+    ]"#);
+    let simulation = Simulation::from_model(&model).unwrap();
+    assert_eq!(simulation.boundary_conditions()[2], BoundaryCondition::Sink { faces: vec![Face::XMin, Face::XMax], species: vec!["B".to_string()] });
+
+    let written = simulation.to_model();
+    assert_eq!(written.boundaries, model.boundaries);
+    let text = serde_json::to_string(&written).unwrap();
+    assert!(text.contains(r#"{"sink":{"faces":["-x","+x"],"species":["B"]}}"#));
+}
+
+#[test]
+fn boundary_voxels() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [8, 8, 8], 3e-3, 50e-9));
+    simulation.prepare_regions();
+    // The faces of the lattice
+    assert_eq!(simulation.boundary_voxels("background"), 8 * 8 * 8 - 6 * 6 * 6);
+
+    let simulation = Simulation::from_model(&model("[]")).unwrap();
+    let cell = simulation.boundary_voxels("cell");
+    assert!(cell > 0 && cell < simulation.boundary_voxels("background"));
+}
+
+#[test]
+fn boundary_errors() {
+    let error = Simulation::from_model(&model(r#"[{"clamp": {"species": "A", "region": "nucleus", "concentration": 1}}]"#)).err().unwrap();
+    assert_eq!(error.path(), Some("boundaries[0].clamp.region"));
+
+    let error = Simulation::from_model(&model(r#"[{"sink": {"faces": ["-z"]}}, {"flux": {"species": "A", "region": "cell", "rate": -1}}]"#)).err().unwrap();
+    assert_eq!(error.path(), Some("boundaries[1].flux.rate"));
+
+    let error = Simulation::from_model(&model(r#"[{"sink": {"faces": ["-z"], "species": ["C"]}}]"#)).err().unwrap();
+    assert_eq!(error.path(), Some("boundaries[0].sink.species"));
+
+    let error = Simulation::from_model(&model(r#"[{"sink": {"faces": []}}]"#)).err().unwrap();
+    assert_eq!(error.path(), Some("boundaries[0].sink.faces"));
+}
+
+#[test]
+#[should_panic(expected = "Species C not found")]
+fn unknown_species_in_code() {
+    let mut simulation = Simulation::from_model(&model("[]")).unwrap();
+    simulation.add_boundary_condition(BoundaryCondition::Sink { faces: vec![Face::XMin], species: vec!["A".to_string()] });
+    simulation.add_boundary_condition(BoundaryCondition::Flux { species: "C".to_string(), region: "cell".to_string(), rate: 1. });
+}
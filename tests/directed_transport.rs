@@ -0,0 +1,81 @@
+use simulation::{LatticeParams, Model, Simulation};
+use simulation::transport::jump_probability;
+use simulation::validation::Issue;
+
+fn model(transport: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [4, 4, 4], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 100e-9}},
+        "particles": [{{"name": "A", "to_region": "background", "count": 10{}}}]
+    }}"#, transport).parse().unwrap()
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1e-6
+}
+
+#[test]
+fn upwind_drift() {
+    // 1e-14 * 3e-3 / (100e-9)^2 = 0.003, 1e-6 * 3e-3 / 100e-9 = 0.03
+    assert!(close(jump_probability(1e-14, 0., 3e-3, 100e-9), 0.003));
+    assert!(close(jump_probability(1e-14, 1e-6, 3e-3, 100e-9), 0.033));
+    assert!(close(jump_probability(1e-14, -1e-6, 3e-3, 100e-9), 0.003));
+}
+
+#[test]
+fn transport_in_model_files() {
+    let model = model(r#", "diffusion_tensor": {"background": [1e-14, 2e-14, 0]}, "drift": {"background": [0, "-1e-6", 0]}"#);
+    let simulation = Simulation::from_model(&model).unwrap();
+
+    let expected = [0.003, 0.003, 0.036, 0.006, 0., 0.];
+    let probabilities = simulation.jump_probabilities("A", [1, 1, 1]);
+    assert!(probabilities.iter().zip(expected).all(|(p, e)| close(*p, e)), "{:?}", probabilities);
+    // Reflecting faces take nothing
+    assert!(close(simulation.jump_probabilities("A", [0, 0, 1])[2], 0.));
+    assert!(simulation.validate().is_ok());
+
+    let written = simulation.to_model();
+    assert_eq!(written.particles[0].diffusion_tensor, model.particles[0].diffusion_tensor);
+    assert_eq!(written.particles[0].drift["background"], [0., -1e-6, 0.]);
+}
+
+#[test]
+fn drift_fields() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    simulation.prepare_regions();
+    simulation.add_particle_count("A", "background", 10, false, false);
+    simulation.set_diffusion_tensor_particle("A", "background", [1e-14; 3]);
+    // Only the voxel [1, 2, 2] moves along +z
+    let idx = |[x, y, z]: [usize; 3]| (x * 4 + y) * 4 + z;
+    let mut field = vec![[0.; 3]; 64];
+    field[idx([1, 2, 2])] = [0., 0., 1e-6];
+    simulation.set_drift_field_particle("A", field);
+
+    assert!(close(simulation.jump_probabilities("A", [1, 2, 2])[5], 0.033));
+    assert!(close(simulation.jump_probabilities("A", [1, 2, 3])[5], 0.));
+
+    let written = simulation.to_model();
+    assert_eq!(written.particles[0].drift_field[idx([1, 2, 2])], [0., 0., 1e-6]);
+}
+
+#[test]
+fn transport_errors() {
+    let error = Simulation::from_model(&model(r#", "drift": {"cell": [1e-6, 0, 0]}"#)).err().unwrap();
+    assert_eq!(error.path(), Some("particles[0].drift.cell"));
+
+    let error = Simulation::from_model(&model(r#", "diffusion_tensor": {"background": [1e-14, -1e-14, 0]}"#)).err().unwrap();
+    assert_eq!(error.path(), Some("particles[0].diffusion_tensor.background"));
+
+    let error = Simulation::from_model(&model(r#", "drift_field": [[0, 0, 0]]"#)).err().unwrap();
+    assert_eq!(error.path(), Some("particles[0].drift_field"));
+
+    // 4e-5 * 3e-3 / 100e-9 = 1.2 along +x, on top of diffusion in the 6 directions
+    let mut simulation = Simulation::from_model(&model(r#", "drift": {"background": [4e-5, 0, 0]}"#)).unwrap();
+    simulation.set_diffusion_tensor_particle("A", "background", [1e-14; 3]);
+    match simulation.validate().errors.as_slice() {
+        [Issue::JumpProbability { particle, probability, .. }] => {
+            assert_eq!(particle, "A");
+            assert!((probability - 1.218).abs() < 1e-5);
+        },
+        other => panic!("unexpected errors {:?}", other),
+    }
+}
@@ -0,0 +1,68 @@
+use simulation::{LatticeParams, Model, RegionType};
+
+const MODEL: &str = r#"{
+    "constants": {
+        "D_cyto": 1.358e-14,
+        "R": "dimensions[0] / 2 - 0.05",
+        "n": 16
+    },
+    "parameters": {"lattice_resolution": ["n", "n", "n"], "dimensions": [0.8, 0.8, 0.8], "tau": 3e-3, "lambda": "dimensions[0] / n * 1e-6"},
+    "regions": [
+        {"type": "sphere", "name": "cell", "center": ["R + 0.05", "R + 0.05", "R + 0.05"], "radius": "R", "base_diffusion_rate": "D_cyto * 0.5"}
+    ],
+    "particles": [
+        {"name": "A", "to_region": "cell", "count": "2^5", "diffusion_rate": {"cell": "D_cyto"}}
+    ]
+}"#;
+
+fn error(constants: &str) -> (String, String) {
+    let model = MODEL.replacen(r#""n": 16"#, &format!(r#""n": 16, {}"#, constants), 1);
+    let error = model.parse::<Model>().unwrap_err();
+    (error.path().unwrap().to_string(), error.to_string())
+}
+
+#[test]
+fn expressions() {
+    let model: Model = MODEL.parse().unwrap();
+    assert_eq!(model.constants, vec![("D_cyto".to_string(), 1.358e-14), ("R".to_string(), 0.8 / 2. - 0.05), ("n".to_string(), 16.)]);
+    assert_eq!(model.parameters.lattice_resolution, [16; 3]);
+    assert_eq!(model.parameters.lambda, 5e-8);
+    assert_eq!(model.regions[0].shape, RegionType::Sphere { name: "cell".to_string(), center: [0.4; 3], radius: 0.35 });
+    assert_eq!(model.regions[0].base_diffusion_rate, 0.679e-14);
+    assert_eq!(model.particles[0].count, Some(32));
+    assert_eq!(model.particles[0].diffusion_rate["cell"], 1.358e-14);
+}
+
+#[test]
+fn precedence() {
+    let model: Model = MODEL.replace(r#""n": 16"#, r#""n": 16, "x": "-2^2 + (1 + 2) * 3 / 2 - 1e-1""#).parse().unwrap();
+    assert_eq!(model.constants[3], ("x".to_string(), 0.4));
+}
+
+#[test]
+fn expression_errors() {
+    assert_eq!(error(r#""x": "2 * D_cyt""#), ("constants.x".to_string(), "constants.x: column 5: unknown constant `D_cyt`".to_string()));
+    assert_eq!(error(r#""x": "dimensions[3]""#).0, "constants.x");
+    assert_eq!(error(r#""x": "dimensions""#).0, "constants.x");
+    assert_eq!(error(r#""x": "(1 + 2""#).1, "constants.x: column 7: expected `)`");
+    assert_eq!(error(r#""x": "1 / (n - 16)""#).1, "constants.x: `1 / (n - 16)` is inf");
+    assert!(error(r#""x": "y", "y": "x + 1""#).1.contains("circular definition: constants.x -> constants.y -> constants.x"));
+}
+
+#[test]
+fn names_are_not_expressions() {
+    let model: Model = MODEL.replace(r#""name": "cell""#, r#""name": "R""#).replace(r#""to_region": "cell""#, r#""to_region": "R""#).parse().unwrap();
+    assert_eq!(model.regions[0].shape.name(), Some("R"));
+    assert_eq!(model.particles[0].to_region, "R");
+}
+
+#[test]
+fn lattice_parameters() {
+    let parameters = serde_json::json!({"lattice_resolution": [32, 32, 32], "dimensions": [0.8, 0.8, 0.8], "tau": 3e-3, "lambda": "dimensions[0] / lattice_resolution[0] * 1e-6"});
+    let lattice_params = LatticeParams::from_json_value(&parameters).unwrap();
+    assert_eq!(lattice_params.lambda(), 2.5e-8);
+
+    let parameters = serde_json::json!({"lattice_resolution": [32, 32, 32], "dimensions": [0.8, 0.8, 0.8], "tau": "D", "lambda": 1e-8});
+    let error = LatticeParams::from_json_value(&parameters).err().unwrap();
+    assert_eq!(error.path(), Some("parameters.tau"));
+}
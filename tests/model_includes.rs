@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use simulation::{Model, RegionType};
+
+const ENVELOPE: &str = r#"{
+    "constants": {"D_cyto": 1e-14, "R": 0.3},
+    "parameters": {"lattice_resolution": [16, 16, 16], "dimensions": [0.8, 0.8, 0.8], "tau": 3e-3, "lambda": 50e-9},
+    "regions": [
+        {"type": "spherical_shell", "shell_name": "membrane", "interior_name": "interior", "center": [0.4, 0.4, 0.4],
+         "internal_radius": "R", "external_radius": "R + 0.05", "base_diffusion_rate": "D_cyto"}
+    ],
+    "templates": {
+        "vesicle": {
+            "arguments": ["name", "x", "r"],
+            "regions": [{"type": "sphere", "name": "${name}", "center": ["${x}", 0.4, 0.4], "radius": "${r}", "base_diffusion_rate": "D_cyto / 2"}],
+            "particles": [{"name": "${name}_cargo", "to_region": "${name}", "count": 10}],
+            "reactions": {"${name}_cargo -> 0": 0.1}
+        }
+    }
+}"#;
+
+/// Writes `files` in a directory of their own and returns its path.
+fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("cell_simulation_includes_{}", test));
+    for (name, data) in files {
+        let path = directory.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+    directory
+}
+
+fn error(test: &str, files: &[(&str, &str)]) -> (String, String) {
+    let directory = write_files(test, files);
+    let error = Model::from_file(directory.join(files.last().unwrap().0)).unwrap_err();
+    (error.path().unwrap_or_default().to_string(), error.to_string())
+}
+
+#[test]
+fn includes_and_instances() {
+    let directory = write_files("merge", &[
+        ("modules/envelope.json", ENVELOPE),
+        ("variant.json", r#"{
+            "include": "modules/envelope.json",
+            "constants": {"R": 0.35},
+            "particles": [{"name": "A", "to_region": "interior", "count": 40}],
+            "instances": [
+                {"template": "vesicle", "name": "left", "x": 0.2, "r": 0.05},
+                {"template": "vesicle", "name": "right", "x": 0.6, "r": "R / 7"}
+            ]
+        }"#),
+    ]);
+    let model = Model::from_file(directory.join("variant.json")).unwrap();
+
+    let regions = model.regions.iter().map(|r| r.shape.name().unwrap_or("membrane")).collect::<Vec<&str>>();
+    assert_eq!(regions, ["membrane", "left", "right"]);
+    let particles = model.particles.iter().map(|p| p.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(particles, ["A", "left_cargo", "right_cargo"]);
+    let reactions = model.reactions.iter().map(|(equation, _)| equation.as_str()).collect::<Vec<&str>>();
+    assert_eq!(reactions, ["left_cargo -> 0", "right_cargo -> 0"]);
+
+    // The variant overrides the radius of the envelope, and its instances use it too
+    assert_eq!(model.constants[1], ("R".to_string(), 0.35));
+    assert!(matches!(model.regions[0].shape, RegionType::SphericalShell { internal_radius, .. } if internal_radius == 0.35));
+    assert!(matches!(model.regions[2].shape, RegionType::Sphere { radius, .. } if radius == 0.05));
+    assert_eq!(model.regions[1].base_diffusion_rate, 5e-15);
+}
+
+#[test]
+fn conflicts() {
+    let other_envelope = r#"{"regions": [{"type": "sphere", "name": "interior", "center": [0.4, 0.4, 0.4], "radius": 0.1, "base_diffusion_rate": 0}]}"#;
+    let (path, message) = error("duplicate_region", &[
+        ("envelope.json", ENVELOPE),
+        ("other.json", other_envelope),
+        ("variant.json", r#"{"include": ["envelope.json", "other.json"]}"#),
+    ]);
+    assert_eq!(path, "include[1]");
+    assert!(message.contains("region `interior` is defined in"), "{}", message);
+
+    let (path, _) = error("duplicate_instance", &[
+        ("envelope.json", ENVELOPE),
+        ("variant.json", r#"{"include": "envelope.json", "instances": [
+            {"template": "vesicle", "name": "left", "x": 0.2, "r": 0.05},
+            {"template": "vesicle", "name": "left", "x": 0.6, "r": 0.05}
+        ]}"#),
+    ]);
+    assert_eq!(path, "instances[1]");
+
+    let (path, message) = error("different_constants", &[
+        ("envelope.json", ENVELOPE),
+        ("constants.json", r#"{"constants": {"D_cyto": 2e-14}}"#),
+        ("variant.json", r#"{"include": ["envelope.json", "constants.json"]}"#),
+    ]);
+    assert_eq!(path, "include[1]");
+    assert!(message.contains("`constants.D_cyto` is defined differently"), "{}", message);
+}
+
+#[test]
+fn include_cycle() {
+    let (path, message) = error("cycle", &[
+        ("a.json", r#"{"include": "b.json"}"#),
+        ("b.json", r#"{"include": "a.json"}"#),
+    ]);
+    assert_eq!(path, "include");
+    assert!(message.contains("includes itself"), "{}", message);
+}
+
+#[test]
+fn template_arguments() {
+    let instance = |instance: &str, test: &str| error(test, &[
+        ("envelope.json", ENVELOPE),
+        ("variant.json", &format!(r#"{{"include": "envelope.json", "instances": [{}]}}"#, instance)),
+    ]);
+    assert_eq!(instance(r#"{"template": "vesicle", "name": "left", "x": 0.2}"#, "missing").1,
+        "instances[0]: missing argument `r` of template `vesicle`");
+    assert_eq!(instance(r#"{"template": "vesicle", "name": "left", "x": 0.2, "r": 0.1, "z": 1}"#, "unknown").0, "instances[0].z");
+    assert_eq!(instance(r#"{"template": "cell", "name": "left"}"#, "unknown_template").0, "instances[0].template");
+}
@@ -0,0 +1,120 @@
+use simulation::{RegionType, Simulation};
+use simulation::model::{Model, RegionModel};
+
+fn round_trip(json: &str) -> RegionModel {
+    let region: RegionModel = serde_json::from_str(json).unwrap();
+    let written = serde_json::to_string(&region).unwrap();
+    let read: RegionModel = serde_json::from_str(&written).unwrap();
+    assert_eq!(region, read, "{} was written back as {}", json, written);
+    region
+}
+
+fn model_with_regions(regions: &str) -> String {
+    format!(r#"{{
+        "parameters": {{
+            "lattice_resolution": [16, 16, 16],
+            "dimensions": [0.8, 0.8, 0.8],
+            "tau": 3e-3,
+            "lambda": 50e-9
+        }},
+        "regions": {}
+    }}"#, regions)
+}
+
+#[test]
+fn cube() {
+    let region = round_trip(r#"{"type": "cube", "name": "box", "p0": [0.0, 0.0, 0.0], "pf": [0.4, 0.4, 0.4], "base_diffusion_rate": 1e-14}"#);
+    assert_eq!(region.shape, RegionType::Cube { name: "box".to_string(), p0: [0.; 3], pf: [0.4; 3] });
+    assert_eq!(region.base_diffusion_rate, 1e-14);
+}
+
+#[test]
+fn sphere() {
+    let region = round_trip(r#"{"type": "sphere", "name": "vesicle", "center": [0.4, 0.4, 0.4], "radius": 0.2, "base_diffusion_rate": 1e-14}"#);
+    assert_eq!(region.shape, RegionType::Sphere { name: "vesicle".to_string(), center: [0.4; 3], radius: 0.2 });
+}
+
+#[test]
+fn semi_sphere() {
+    let region = round_trip(r#"{"type": "semi_sphere", "name": "cap", "center": [0.4, 0.4, 0.4], "radius": 0.2, "direction": [0.0, 0.0, 1.0], "base_diffusion_rate": 0.0}"#);
+    assert_eq!(region.shape, RegionType::SemiSphere { name: "cap".to_string(), center: [0.4; 3], radius: 0.2, direction: [0., 0., 1.] });
+}
+
+#[test]
+fn cylinder() {
+    let region = round_trip(r#"{"type": "cylinder", "name": "rod", "p0": [0.4, 0.4, 0.1], "pf": [0.4, 0.4, 0.7], "radius": 0.1, "base_diffusion_rate": 0.0}"#);
+    assert_eq!(region.shape, RegionType::Cylinder { name: "rod".to_string(), p0: [0.4, 0.4, 0.1], pf: [0.4, 0.4, 0.7], radius: 0.1 });
+}
+
+#[test]
+fn spherical_shell() {
+    let region = round_trip(r#"{"type": "spherical_shell", "shell_name": "membrane", "interior_name": "lumen", "center": [0.4, 0.4, 0.4],
+        "internal_radius": 0.3, "external_radius": 0.35, "base_diffusion_rate": 0.0}"#);
+    assert_eq!(region.shape, RegionType::SphericalShell {
+        shell_name: "membrane".to_string(), interior_name: "lumen".to_string(), center: [0.4; 3], internal_radius: 0.3, external_radius: 0.35
+    });
+}
+
+#[test]
+fn cylindrical_shell() {
+    let region = round_trip(r#"{"type": "cylindrical_shell", "shell_name": "membrane", "interior_name": "lumen", "p0": [0.4, 0.4, 0.1], "pf": [0.4, 0.4, 0.7],
+        "internal_radius": 0.3, "external_radius": 0.35, "base_diffusion_rate": 0.0}"#);
+    assert_eq!(region.shape, RegionType::CylindricalShell {
+        shell_name: "membrane".to_string(), interior_name: "lumen".to_string(), p0: [0.4, 0.4, 0.1], pf: [0.4, 0.4, 0.7], internal_radius: 0.3, external_radius: 0.35
+    });
+}
+
+#[test]
+fn capsid() {
+    let region = round_trip(r#"{"type": "capsid", "shell_name": "membrane", "interior_name": "interior", "center": [0.4, 0.4, 1.0], "dir": [0.0, 0.0, 1.0],
+        "internal_radius": 0.37, "external_radius": 0.4, "total_length": 2.0, "base_diffusion_rate": 1.358E-14}"#);
+    assert_eq!(region.shape, RegionType::Capsid {
+        shell_name: "membrane".to_string(), interior_name: "interior".to_string(), center: [0.4, 0.4, 1.], dir: [0., 0., 1.],
+        internal_radius: 0.37, external_radius: 0.4, total_length: 2.
+    });
+}
+
+#[test]
+fn sparse() {
+    let region = round_trip(r#"{"type": "sparse", "name": "obstacles", "to_region": "interior", "max_volume": 500,
+        "base_region": {"type": "sphere", "radius": 0.05}, "base_diffusion_rate": 0.0}"#);
+    match region.shape {
+        RegionType::Sparse { name, to_region, max_volume, base_region } => {
+            assert_eq!(name, "obstacles");
+            assert_eq!(to_region, "interior");
+            assert_eq!(max_volume, 500);
+            assert_eq!(base_region.radius, 0.05);
+        },
+        other => panic!("Expected a sparse region, got {:?}", other),
+    }
+}
+
+#[test]
+fn errors_point_at_the_field() {
+    let model = model_with_regions(r#"[{"type": "sphere", "name": "vesicle", "center": [0.4, 0.4, 0.4], "radius": "big", "base_diffusion_rate": 0.0}]"#);
+    let error = model.parse::<Model>().unwrap_err();
+    assert_eq!(error.path(), Some("regions[0].radius"));
+
+    let model = model_with_regions(r#"[{"type": "torus", "name": "ring", "base_diffusion_rate": 0.0}]"#);
+    let error = model.parse::<Model>().unwrap_err();
+    assert_eq!(error.path(), Some("regions[0]"));
+
+    let model = model_with_regions(r#"[{"type": "sparse", "name": "obstacles", "to_region": "nowhere", "max_volume": 10,
+        "base_region": {"type": "sphere", "radius": 0.2}, "base_diffusion_rate": 0.0}]"#);
+    let model = model.parse::<Model>().unwrap();
+    let error = Simulation::from_model(&model).err().unwrap();
+    assert_eq!(error.path(), Some("regions[0].to_region"));
+}
+
+#[test]
+fn vesicle_with_obstacles() {
+    let model = model_with_regions(r#"[
+        {"type": "spherical_shell", "shell_name": "membrane", "interior_name": "lumen", "center": [0.4, 0.4, 0.4],
+         "internal_radius": 0.3, "external_radius": 0.35, "base_diffusion_rate": 1e-14},
+        {"type": "sparse", "name": "obstacles", "to_region": "lumen", "max_volume": 20,
+         "base_region": {"type": "sphere", "radius": 0.1}, "base_diffusion_rate": 0.0}
+    ]"#);
+    let model = model.parse::<Model>().unwrap();
+    let simulation = Simulation::from_model(&model).unwrap();
+    assert_eq!(simulation.lattice_params.raw.n_regions, 4);
+}
@@ -0,0 +1,104 @@
+use simulation::{LatticeParams, Model, ReactionRate, RegionType, Simulation};
+use simulation::model::{RenderingModel, TransitionModel};
+
+const MODEL: &str = r#"{
+    "parameters": {
+        "lattice_resolution": [16, 16, 16],
+        "dimensions": [0.8, 0.8, 0.8],
+        "tau": 3e-3,
+        "lambda": 50e-9
+    },
+    "rendering": {"width": 800, "height": 600},
+    "regions": [
+        {"type": "spherical_shell", "shell_name": "membrane", "interior_name": "interior", "center": [0.4, 0.4, 0.4],
+         "internal_radius": 0.3, "external_radius": 0.35, "base_diffusion_rate": 1e-14}
+    ],
+    "particles": [
+        {"name": "A", "to_region": "interior", "count": 40, "logging": true, "diffusion_rate": {"membrane": 0.0}},
+        {"name": "B", "to_region": "interior", "concentration": 0.01},
+        {"name": "Food", "to_region": "background", "count": 10, "is_reservoir": true, "diffusion_rate": {"interior": 2e-14}}
+    ],
+    "transitions": [
+        {"from": "interior", "to": "membrane", "rate": 1e-15},
+        {"from": "membrane", "to": "interior", "species": "A", "rate": 2e-15}
+    ],
+    "reactions": {
+        "2 A -> B": 1e-3,
+        "B + Food -> A + Food": 2.5,
+        "B -> 0": 0.1
+    }
+}"#;
+
+fn reload(model: &Model) -> Model {
+    let written = serde_json::to_string_pretty(model).unwrap();
+    let read: Model = written.parse().unwrap();
+    Simulation::from_model(&read).unwrap().to_model()
+}
+
+#[test]
+fn model_round_trip() {
+    let model: Model = MODEL.parse().unwrap();
+    let written = Simulation::from_model(&model).unwrap().to_model();
+
+    assert_eq!(written.parameters, model.parameters);
+    assert_eq!(written.rendering, model.rendering);
+    assert_eq!(written.regions, model.regions);
+    assert_eq!(written.reactions, model.reactions);
+    let names = written.particles.iter().map(|p| p.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(names, ["A", "B", "Food"]);
+    assert!(written.particles[0].logging);
+    assert!(written.particles[2].is_reservoir);
+    // Concentrations are written as the count they turned into
+    assert!(written.particles[1].count.is_some() && written.particles[1].concentration.is_none());
+    assert_eq!(written.particles[0].diffusion_rate["membrane"], 0.);
+    assert_eq!(written.particles[2].diffusion_rate["interior"], 2e-14);
+    assert_eq!(written.transitions.len(), 2);
+    for transition in &model.transitions {
+        assert!(written.transitions.contains(transition), "{:?} was not written", transition);
+    }
+
+    assert_eq!(reload(&written), written);
+}
+
+#[test]
+fn built_simulation_round_trip() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.8, 0.8, 0.8], [16, 16, 16], 3e-3, 50e-9));
+    simulation.add_region(RegionType::Sphere { name: "vesicle".to_string(), center: [0.4; 3], radius: 0.3 }, 1e-14);
+    simulation.prepare_regions();
+    simulation.add_particle_count("A", "vesicle", 20, false, false);
+    simulation.fill_region("B", "vesicle", true);
+    simulation.set_diffusion_rate("vesicle", 5e-15);
+    simulation.add_reaction(vec!["A", "B"], vec!["B", "B"], 0.5);
+
+    let written = simulation.to_model();
+    // A default window, so that RenderParams::from_file opens it
+    assert_eq!(written.rendering, Some(RenderingModel::default()));
+    assert!(written.particles[1].count.unwrap() > 0);
+    assert_eq!(written.particles[0].diffusion_rate["vesicle"], 5e-15);
+    assert_eq!(written.reactions, vec![("A + B -> 2 B".to_string(), ReactionRate::per_voxel(0.5).into())]);
+
+    assert_eq!(reload(&written), written);
+}
+
+#[test]
+fn save_and_load() {
+    let model: Model = MODEL.parse().unwrap();
+    let simulation = Simulation::from_model(&model).unwrap();
+    let path = std::env::temp_dir().join("cell_simulation_save_and_load.json");
+    simulation.save_model(&path).unwrap();
+
+    assert_eq!(Model::from_file(&path).unwrap(), simulation.to_model());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn transition_errors() {
+    let error_path = |transition: &str| {
+        let mut model: Model = MODEL.parse().unwrap();
+        model.transitions = vec![serde_json::from_str::<TransitionModel>(transition).unwrap()];
+        Simulation::from_model(&model).err().unwrap().path().unwrap().to_string()
+    };
+    assert_eq!(error_path(r#"{"from": "nucleus", "to": "interior", "rate": 1.0}"#), "transitions[0].from");
+    assert_eq!(error_path(r#"{"from": "interior", "to": "interior", "rate": 1.0}"#), "transitions[0].to");
+    assert_eq!(error_path(r#"{"from": "interior", "to": "membrane", "species": "Z", "rate": 1.0}"#), "transitions[0].species");
+}
@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use simulation::{Model, Simulation};
+use simulation::sweep::{Run, Sweep, ValueSet};
+
+const MODEL: &str = r#"{
+    "constants": {"D": 1e-14},
+    "parameters": {"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9},
+    "regions": [{"type": "sphere", "name": "cell", "center": [0.2, 0.2, 0.2], "radius": 0.15, "base_diffusion_rate": "D"}],
+    "particles": [
+        {"name": "A", "to_region": "cell", "count": 10},
+        {"name": "B", "to_region": "cell", "count": 10}
+    ],
+    "reactions": {"A + B -> B": 0.5}
+}"#;
+
+fn write_files(test: &str, manifest: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("cell_simulation_sweep_{}", test));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("model.json"), MODEL).unwrap();
+    std::fs::write(directory.join("sweep.json"), manifest).unwrap();
+    directory
+}
+
+fn runs(test: &str, sweep: &str) -> Vec<Run> {
+    let directory = write_files(test, &format!(r#"{{"model": "model.json", "seed": 3, "sweep": {}}}"#, sweep));
+    Sweep::from_file(directory.join("sweep.json")).unwrap().runs().unwrap()
+}
+
+#[test]
+fn value_sets() {
+    assert_eq!(ValueSet::Grid { from: 100., to: 400., steps: 4 }.values(0).unwrap(), [100, 200, 300, 400]);
+    assert_eq!(ValueSet::LogRange { from: 1e-3, to: 1e-1, steps: 3 }.values(0).unwrap(), [1e-3, 1e-2, 1e-1]);
+    assert!(ValueSet::LogRange { from: 0., to: 1., steps: 3 }.values(0).is_err());
+
+    let random = ValueSet::Random { from: 10., to: 20., samples: 5, log: false, integer: true };
+    let samples = random.values(7).unwrap();
+    assert_eq!(samples, random.values(7).unwrap());
+    assert_ne!(samples, random.values(8).unwrap());
+    assert!(samples.iter().all(|s| s.as_u64().is_some_and(|s| (10..=20).contains(&s))));
+}
+
+#[test]
+fn expands_runs() {
+    let runs = runs("expand", r#"[
+        {"key": "reactions.A + B -> B", "values": [0.1, 0.2]},
+        {"key": "constants.D", "log_range": {"from": 1e-15, "to": 1e-13, "steps": 3}},
+        {"key": "particles[1].count", "random": {"from": 5, "to": 50, "samples": 2, "integer": true}}
+    ]"#);
+    assert_eq!(runs.len(), 12);
+
+    let model = runs[0].to_model().unwrap();
+    assert_eq!(model.reactions[0].1, simulation::ReactionRate::per_voxel(0.1).into());
+    // The swept constant reaches the expressions that use it
+    assert_eq!(model.regions[0].base_diffusion_rate, 1e-15);
+    assert_eq!(runs[0].values["particles[1].count"], model.particles[1].count.unwrap());
+
+    let mut ids = runs.iter().map(|run| run.id.clone()).collect::<Vec<String>>();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 12);
+}
+
+#[test]
+fn stable_ids() {
+    let grid = r#"{"key": "particles[0].count", "grid": {"from": 10, "to": 30, "steps": 3}}"#;
+    let values = r#"{"key": "reactions.A + B -> B", "values": [0.1, 0.2]}"#;
+    let runs_a = runs("ids_a", &format!("[{}, {}]", grid, values));
+    let runs_b = runs("ids_b", &format!("[{}, {}]", values, grid));
+
+    let id = |runs: &[Run], count: u64, rate: f64| {
+        runs.iter().find(|run| run.values["particles[0].count"] == count && run.values["reactions.A + B -> B"] == rate).unwrap().id.clone()
+    };
+    assert_eq!(id(&runs_a, 20, 0.2), id(&runs_b, 20, 0.2));
+}
+
+#[test]
+fn writes_runs() {
+    let directory = write_files("write", r#"{"model": "model.json", "sweep": [{"key": "parameters.tau", "values": [1e-3, 2e-3]}]}"#);
+    let output = directory.join("runs");
+    let runs = Sweep::from_file(directory.join("sweep.json")).unwrap().write(&output).unwrap();
+
+    let records: Vec<Run> = serde_json::from_str(&std::fs::read_to_string(output.join("runs.json")).unwrap()).unwrap();
+    assert_eq!(records.iter().map(|r| (&r.id, &r.values)).collect::<Vec<_>>(), runs.iter().map(|r| (&r.id, &r.values)).collect::<Vec<_>>());
+
+    let model = Model::from_file(output.join(runs[1].file_name())).unwrap();
+    assert_eq!(model.parameters.tau, 2e-3);
+    assert!(Simulation::from_model(&model).is_ok());
+}
+
+#[test]
+fn sweep_errors() {
+    let error = |sweep: &str, test: &str| {
+        let directory = write_files(test, &format!(r#"{{"model": "model.json", "sweep": {}}}"#, sweep));
+        Sweep::from_file(directory.join("sweep.json")).and_then(|sweep| sweep.runs()).err().unwrap()
+    };
+    assert_eq!(error(r#"[{"key": "particles[2].count", "values": [1]}]"#, "missing_key").path(), Some("sweep[0].key"));
+    assert_eq!(error(r#"[{"key": "parameters.tau", "grid": {"from": 1, "to": 2, "steps": 0}}]"#, "steps").path(), Some("sweep[0]"));
+    assert_eq!(error(r#"[{"key": "particles[0].count", "values": [-1]}]"#, "invalid_run").path(), Some("particles[0].count"));
+}
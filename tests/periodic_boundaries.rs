@@ -0,0 +1,49 @@
+use simulation::{AxisBoundary, Face, LatticeParams, Model, Simulation};
+use simulation::boundary::{at_boundary, neighbour};
+
+fn model(parameters: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9{}}},
+        "particles": [{{"name": "A", "to_region": "background", "count": 10}}]
+    }}"#, parameters).parse().unwrap()
+}
+
+#[test]
+fn wrapping_neighbours() {
+    let res = [4, 4, 4];
+    let periodic = [true, false, false];
+    assert_eq!(neighbour([0, 1, 3], Face::XMin, res, periodic), Some([3, 1, 3]));
+    assert_eq!(neighbour([3, 1, 3], Face::XMax, res, periodic), Some([0, 1, 3]));
+    assert_eq!(neighbour([0, 1, 3], Face::ZMax, res, periodic), None);
+
+    // Only the z faces are left in a single region
+    let faces = (0..64).filter(|i| at_boundary([i / 16, i / 4 % 4, i % 4], res, [true, true, false], |_| 0)).count();
+    assert_eq!(faces, 2 * 16);
+}
+
+#[test]
+fn axis_boundaries_in_model_files() {
+    let simulation = Simulation::from_model(&model(r#", "axis_boundaries": ["periodic", "periodic", "absorbing"]"#)).unwrap();
+    assert_eq!(simulation.lattice_params.boundaries(), [AxisBoundary::Periodic, AxisBoundary::Periodic, AxisBoundary::Absorbing]);
+    assert_eq!(simulation.lattice_params.periodic(), [true, true, false]);
+    assert_eq!(simulation.boundary_voxels("background"), 2 * 8 * 8);
+
+    let written = simulation.to_model();
+    assert_eq!(written.parameters.axis_boundaries, simulation.lattice_params.boundaries());
+
+    // Reflecting axes are not written
+    let written = Simulation::from_model(&model("")).unwrap().to_model();
+    assert!(!serde_json::to_string(&written).unwrap().contains("axis_boundaries"));
+
+    let error = r#"{"parameters": {"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9,
+        "axis_boundaries": ["periodic", "open", "periodic"]}}"#.parse::<Model>().unwrap_err();
+    assert_eq!(error.path(), Some("parameters.axis_boundaries[1]"));
+}
+
+#[test]
+fn axis_boundaries_in_code() {
+    let mut lattice_params = LatticeParams::new([0.4, 0.4, 0.4], [8, 8, 8], 3e-3, 50e-9);
+    assert_eq!(lattice_params.boundaries(), [AxisBoundary::Reflecting; 3]);
+    lattice_params.set_boundaries([AxisBoundary::Reflecting, AxisBoundary::Periodic, AxisBoundary::Reflecting]);
+    assert_eq!(lattice_params.raw.boundaries, [0, 1, 0]);
+}
@@ -0,0 +1,80 @@
+use simulation::{LatticeParams, ReactionRate, Simulation};
+use simulation::rates::{self, symmetry_factor};
+
+const SPECIES: [&str; 3] = ["A", "B", "C"];
+
+/// Reference propensity straight from the definition: `k` times the ways to choose the reactants.
+fn reference(k: f32, reactants: &[&str], counts: &[i32]) -> f64 {
+    let mut propensity = k as f64;
+    for (i, species) in SPECIES.iter().enumerate() {
+        let m = reactants.iter().filter(|r| *r == species).count() as i32;
+        let n = counts[i + 1];
+        // C(n, m)
+        let mut ways = 1.;
+        for j in 0..m {
+            ways *= (n - j).max(0) as f64 / (j + 1) as f64;
+        }
+        propensity *= ways;
+    }
+    propensity
+}
+
+fn simulation(reactions: &[(Vec<&'static str>, f32)]) -> Simulation {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    simulation.prepare_regions();
+    for species in SPECIES {
+        simulation.add_particle_count(species, "background", 0, false, false);
+    }
+    for (reactants, k) in reactions {
+        simulation.add_reaction(reactants.clone(), vec!["C"], *k);
+    }
+    simulation
+}
+
+#[test]
+fn matches_cpu_reference() {
+    let reactions = vec![
+        (vec![], 0.5),
+        (vec!["A"], 0.1),
+        (vec!["A", "B"], 0.2),
+        (vec!["A", "A"], 0.3),
+        (vec!["A", "B", "A"], 0.4),
+        (vec!["A", "A", "A", "B"], 0.05),
+        (vec!["B", "B", "B", "B", "B"], 0.01),
+    ];
+    let simulation = simulation(&reactions);
+
+    for a in 0..8 {
+        for b in 0..8 {
+            let counts = [0, a, b, 3];
+            let propensities = simulation.propensities("background", &counts);
+            assert_eq!(propensities.len(), reactions.len());
+            for ((reactants, k), propensity) in reactions.iter().zip(propensities) {
+                let expected = reference(*k, reactants, &counts);
+                assert!((propensity as f64 - expected).abs() <= 1e-5 * expected.max(1.), "{:?} with {:?}: {} instead of {}", reactants, counts, propensity, expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn homo_reactions() {
+    // 5 particles of A make 10 pairs, not 25
+    assert_eq!(rates::propensity(1., &[1, 1, 0], &[0, 5]), 10.);
+    assert_eq!(rates::propensity(1., &[1, 1, 1], &[0, 5]), 10.);
+    assert_eq!(rates::propensity(1., &[1, 1], &[0, 1]), 0.);
+    assert_eq!(rates::propensity(2., &[0, 0, 0], &[0, 5]), 2.);
+}
+
+#[test]
+fn symmetry_of_rates_with_units() {
+    assert_eq!(symmetry_factor(&[1, 2]), 1.);
+    assert_eq!(symmetry_factor(&[1, 1]), 2.);
+    assert_eq!(symmetry_factor(&[1, 2, 1, 1]), 6.);
+
+    let volume = 1e-21;
+    let rate = ReactionRate::molar(1e6);
+    assert_eq!(rate.stochastic_rate(&[1, 1], volume), (rate.to_lattice(2, volume) as f64 * 2.) as f32);
+    assert_eq!(rate.stochastic_rate(&[1, 2], volume), rate.to_lattice(2, volume));
+    assert_eq!(ReactionRate::per_voxel(0.3).stochastic_rate(&[1, 1], volume), 0.3);
+}
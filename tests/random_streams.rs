@@ -0,0 +1,59 @@
+use simulation::{Face, LatticeParams};
+use simulation::random::{philox4x32, seed_key, to_unit_float, Stream, CME_STREAM, RDME_STREAM};
+use simulation::transport::select_jump;
+
+#[test]
+fn philox_known_answers() {
+    // Known-answer vectors of Random123 for Philox4x32-10
+    assert_eq!(philox4x32([0; 4], [0; 2]), [0x6627e8d5, 0xe169c58d, 0xbc57ac4c, 0x9b00dbd8]);
+    assert_eq!(philox4x32([u32::MAX; 4], [u32::MAX; 2]), [0x408f276d, 0x41c83b0e, 0xa20bc7c6, 0x6d5451fd]);
+    assert_eq!(
+        philox4x32([0x243f6a88, 0x85a308d3, 0x13198a2e, 0x03707344], [0xa4093822, 0x299f31d0]),
+        [0xd16cfe09, 0x94fdcceb, 0x5001e420, 0x24126ea1]
+    );
+}
+
+#[test]
+fn streams() {
+    let seed = 0x299f31d0_a4093822;
+    let mut stream = Stream::new(seed, 17, 3, CME_STREAM);
+    let drawn: Vec<u32> = (0..8).map(|_| stream.next_u32()).collect();
+    // Blocks of 4 words, numbered by the third word of the counter
+    assert_eq!(drawn[..4], philox4x32([17, 3, 0, CME_STREAM], seed_key(seed)));
+    assert_eq!(drawn[4..], philox4x32([17, 3, 1, CME_STREAM], seed_key(seed)));
+
+    let mut again = Stream::new(seed, 17, 3, CME_STREAM);
+    assert_eq!((0..8).map(|_| again.next_u32()).collect::<Vec<_>>(), drawn);
+    // Another kernel, slot or step is another stream
+    assert_ne!(Stream::new(seed, 17, 3, RDME_STREAM).next_u32(), drawn[0]);
+    assert_ne!(Stream::new(seed, 18, 3, CME_STREAM).next_u32(), drawn[0]);
+    assert_ne!(Stream::new(seed, 17, 4, CME_STREAM).next_u32(), drawn[0]);
+}
+
+#[test]
+fn unit_floats() {
+    assert_eq!(to_unit_float(0), 0.);
+    assert!(to_unit_float(u32::MAX) < 1.);
+    assert_eq!(to_unit_float(1 << 31), 0.5);
+
+    let mut stream = Stream::new(7, 0, 0, RDME_STREAM);
+    let mean = (0..10000).map(|_| stream.next_f32() as f64).sum::<f64>() / 10000.;
+    assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+}
+
+#[test]
+fn zero_draws_skip_empty_directions() {
+    // Words below 2^8 draw exactly 0, which must not take a direction without probability
+    let zero = to_unit_float(0xff);
+    assert_eq!(zero, 0.);
+    assert_eq!(select_jump([0., 0.1, 0., 0., 0., 0.], zero), Some(Face::XMax));
+    assert_eq!(select_jump([0.; 6], zero), None);
+    assert_eq!(select_jump([0., 0.1, 0., 0., 0., 0.], 0.1), None);
+}
+
+#[test]
+fn shader_key() {
+    let mut lattice_params = LatticeParams::new([0.4, 0.4, 0.4], [8, 8, 8], 3e-3, 50e-9);
+    lattice_params.set_seed(0x299f31d0_a4093822);
+    assert_eq!(lattice_params.raw.seed, [0xa4093822, 0x299f31d0]);
+}
@@ -0,0 +1,72 @@
+use simulation::{Model, RateLaw, Simulation};
+use simulation::rates::law_propensity;
+
+fn model(reactions: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9}},
+        "particles": [
+            {{"name": "S", "to_region": "background", "count": 10}},
+            {{"name": "R", "to_region": "background", "count": 10}},
+            {{"name": "P", "to_region": "background", "count": 0}}
+        ],
+        "reactions": {}
+    }}"#, reactions).parse().unwrap()
+}
+
+fn error(reactions: &str) -> (String, String) {
+    let error = Simulation::from_model(&model(reactions)).err().unwrap();
+    (error.path().unwrap_or_default().to_string(), error.to_string())
+}
+
+#[test]
+fn laws() {
+    let hill = |repressor| RateLaw::Hill { species: "R".to_string(), n: 2., half_saturation: 4., repressor };
+    let piecewise = RateLaw::Piecewise { species: "R".to_string(), thresholds: vec![5., 10.], values: vec![0., 1., 3.] };
+    let counts = [0, 6, 8, 0];
+
+    assert_eq!(law_propensity(2., &[1, 0], &RateLaw::MassAction.to_lattice(0), &counts), 12.);
+    assert_eq!(law_propensity(2., &[1, 0], &RateLaw::MichaelisMenten { km: 2., substrate: None }.to_lattice(1), &counts), 1.5);
+    assert_eq!(law_propensity(2., &[0], &hill(false).to_lattice(2), &counts), 1.6);
+    assert_eq!(law_propensity(2., &[0], &hill(true).to_lattice(2), &counts), 0.4);
+    assert_eq!(law_propensity(2., &[0], &piecewise.to_lattice(2), &counts), 2.);
+    assert_eq!(law_propensity(2., &[0], &piecewise.to_lattice(2), &[0, 0, 10, 0]), 6.);
+    assert_eq!(law_propensity(2., &[0], &piecewise.to_lattice(2), &[0, 0, 4, 0]), 0.);
+
+    // Without its reactants a reaction does not happen, whatever its law says
+    assert_eq!(law_propensity(2., &[3], &hill(true).to_lattice(2), &counts), 0.);
+    assert_eq!(law_propensity(2., &[0], &hill(true).to_lattice(2), &[0, 0, 0, 0]), 2.);
+}
+
+#[test]
+fn laws_in_model_files() {
+    let model = model(r#"{
+        "S -> P": {"rate": 2, "law": {"michaelis_menten": {"km": 2}}},
+        "0 -> P": {"rate": 2, "law": {"hill": {"species": "R", "n": 2, "K": 4, "repressor": true}}},
+        "P -> 0": {"rate": 2, "law": {"piecewise": {"species": "R", "thresholds": [5, 10], "values": [0, 1, 3]}}},
+        "S + R -> P": 0.5
+    }"#);
+    assert_eq!(model.reactions[0].1.law, RateLaw::MichaelisMenten { km: 2., substrate: None });
+
+    let simulation = Simulation::from_model(&model).unwrap();
+    assert_eq!(simulation.propensities("background", &[0, 6, 8, 1]), [1.5, 0.4, 2., 24.]);
+
+    let written = simulation.to_model();
+    assert_eq!(written.reactions, model.reactions);
+    assert_eq!(Simulation::from_model(&written).unwrap().propensities("background", &[0, 6, 8, 1]), [1.5, 0.4, 2., 24.]);
+}
+
+#[test]
+fn law_errors() {
+    assert_eq!(error(r#"{"S -> P": {"rate": 1, "law": {"michaelis_menten": {"km": 0}}}}"#).0, "reactions.S -> P.law.michaelis_menten.km");
+    assert_eq!(error(r#"{"0 -> P": {"rate": 1, "law": {"michaelis_menten": {"km": 1}}}}"#).0, "reactions.0 -> P.law.michaelis_menten");
+    assert_eq!(error(r#"{"S -> P": {"rate": 1, "law": {"piecewise": {"species": "R", "thresholds": [1], "values": [1]}}}}"#).0,
+        "reactions.S -> P.law.piecewise.values");
+
+    let (path, message) = error(r#"{"S -> P": {"rate": 1, "law": {"hill": {"species": "Q", "n": 1, "K": 1}}}}"#);
+    assert_eq!(path, "reactions.S -> P.law.hill.species");
+    assert!(message.contains("unknown species `Q`"), "{}", message);
+
+    let (path, message) = error(r#"{"S <-> P": {"rate": [1, 1], "law": {"michaelis_menten": {"km": 1}}}}"#);
+    assert_eq!(path, "reactions.S <-> P");
+    assert!(message.contains("only mass-action reactions can be reversible"), "{}", message);
+}
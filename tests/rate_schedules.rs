@@ -0,0 +1,68 @@
+use simulation::{Model, RateSignal, Simulation};
+use simulation::rates::AVOGADRO;
+use simulation::schedule::rate_at;
+
+fn model(reactions: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9}},
+        "particles": [{{"name": "A", "to_region": "background", "count": 10}}],
+        "reactions": {}
+    }}"#, reactions).parse().unwrap()
+}
+
+#[test]
+fn signals() {
+    let ramp = RateSignal::Ramp { start: 10., end: 20., from: 0., to: 4. };
+    let square = RateSignal::Square { start: 0., period: 4., duty: 0.25, low: 1., high: 3. };
+    let sine = RateSignal::Sine { start: 1., period: 4., mean: 2., amplitude: 1. };
+
+    assert_eq!([ramp.value(15.), ramp.value(25.)], [2., 4.]);
+    assert_eq!([square.value(0.5), square.value(1.5), square.value(4.5)], [3., 1., 3.]);
+    assert!((sine.value(2.) - 3.).abs() < 1e-6);
+
+    // The signal that started last sets the rate, the base rate is kept until the first one
+    let signals = [ramp, RateSignal::Step { time: 5., value: 1. }];
+    assert_eq!([rate_at(0.5, &signals, 0.), rate_at(0.5, &signals, 7.), rate_at(0.5, &signals, 15.)], [0.5, 1., 2.]);
+}
+
+#[test]
+fn schedules_in_model_files() {
+    let model = model(r#"{
+        "0 -> A": {"rate": 0, "schedule": [{"step": {"time": 30, "value": 2}}]},
+        "A -> 0": {"rate": 0.1, "schedule": [{"square": {"period": 10, "low": 0, "high": 0.2}}]},
+        "A + A -> 0": {"rate": {"value": 1e6, "units": "molar"}, "schedule": [{"ramp": {"start": 0, "end": 10, "from": 0, "to": 2e6}}]}
+    }"#);
+    let simulation = Simulation::from_model(&model).unwrap();
+
+    // Signals are in the units of their rate
+    let molecules_per_molar = AVOGADRO * 50e-9f64.powi(3) * 1e3;
+    let k = |rate: f64| (rate * 2. / molecules_per_molar) as f32;
+    assert_eq!(simulation.rates_at(0.)[..2], [0., 0.2]);
+    assert_eq!(simulation.rates_at(36.)[..2], [2., 0.]);
+    assert!((simulation.rates_at(5.)[2] - k(1e6)).abs() < k(1e6) * 1e-5);
+
+    // Written with per-voxel rates, like the rates themselves
+    let written = simulation.to_model();
+    assert_eq!(written.reactions[0].1.schedule, model.reactions[0].1.schedule);
+    assert_eq!(Simulation::from_model(&written).unwrap().rates_at(5.), simulation.rates_at(5.));
+}
+
+#[test]
+fn scheduled_in_code() {
+    let mut simulation = Simulation::from_model(&model("{}")).unwrap();
+    simulation.add_reaction(vec![], vec!["A"], 0.);
+    simulation.schedule_rate(0, 30., 2.);
+    simulation.schedule_signal(0, RateSignal::Ramp { start: 60., end: 70., from: 2., to: 0. });
+    assert_eq!(simulation.rates_at(29.9), [0.]);
+    assert_eq!(simulation.rates_at(30.), [2.]);
+    assert_eq!(simulation.rates_at(65.), [1.]);
+}
+
+#[test]
+fn schedule_errors() {
+    let error = Simulation::from_model(&model(r#"{"A <-> 0": {"rate": [1, 1], "schedule": [{"step": {"time": 1, "value": 2}}]}}"#)).err().unwrap();
+    assert_eq!(error.path(), Some("reactions.A <-> 0.schedule[0]"));
+
+    let error = Simulation::from_model(&model(r#"{"A -> 0": {"rate": 1, "schedule": [{"ramp": {"start": 5, "end": 5, "from": 0, "to": 1}}]}}"#)).err().unwrap();
+    assert_eq!(error.path(), Some("reactions.A -> 0.schedule[0].ramp.end"));
+}
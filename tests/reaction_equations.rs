@@ -0,0 +1,74 @@
+use simulation::{Model, RateModel, ReactionRate, Simulation};
+use simulation::equation::{self, ParseError};
+
+fn model(reactions: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9}},
+        "particles": [
+            {{"name": "A", "to_region": "background", "count": 10}},
+            {{"name": "B", "to_region": "background", "count": 10}}
+        ],
+        "reactions": {}
+    }}"#, reactions).parse().unwrap()
+}
+
+fn error(text: &str) -> ParseError {
+    equation::parse(text).unwrap_err()
+}
+
+#[test]
+fn coefficients() {
+    let equation = equation::parse("2 A + B -> 3B' # dimer").unwrap().unwrap();
+    assert_eq!(equation.reactant_names(), ["A", "A", "B"]);
+    assert_eq!(equation.product_names(), ["B'", "B'", "B'"]);
+    assert!(!equation.reversible);
+    assert_eq!(equation.products[0].column, 13);
+}
+
+#[test]
+fn sources_and_sinks() {
+    let source = equation::parse("0 -> A").unwrap().unwrap();
+    assert!(source.reactants.is_empty());
+    let sink = equation::parse("A -> ∅").unwrap().unwrap();
+    assert!(sink.products.is_empty());
+
+    assert!(equation::parse("  # only a comment").unwrap().is_none());
+    assert!(equation::parse("").unwrap().is_none());
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(error("A + B"), ParseError { column: 6, message: "expected `->` or `<->`".to_string() });
+    assert_eq!(error("A = B").column, 3);
+    assert_eq!(error("A + -> B").column, 5);
+    assert_eq!(error("0 A -> B").column, 1);
+    assert_eq!(error("A -> 0 + B").column, 6);
+    assert_eq!(error("A -> 2").column, 7);
+    assert_eq!(error("A -> B C").column, 8);
+}
+
+#[test]
+fn reversible_reactions() {
+    let model = model(r##"{"2 A <-> B": [0.5, 0.1], "B -> 0": 0.2, "# disabled A -> B": 1.0}"##);
+    assert_eq!(model.reactions[0].1.rate, RateModel::Reversible(ReactionRate::per_voxel(0.5), ReactionRate::per_voxel(0.1)));
+
+    let written = Simulation::from_model(&model).unwrap().to_model();
+    assert_eq!(written.reactions, vec![
+        ("2 A -> B".to_string(), ReactionRate::per_voxel(0.5).into()),
+        ("B -> 2 A".to_string(), ReactionRate::per_voxel(0.1).into()),
+        ("B -> 0".to_string(), ReactionRate::per_voxel(0.2).into()),
+    ]);
+}
+
+#[test]
+fn model_errors() {
+    let message = |reactions: &str| {
+        let error = Simulation::from_model(&model(reactions)).err().unwrap();
+        assert_eq!(error.path(), Some(format!("reactions.{}", reactions.split('"').nth(1).unwrap()).as_str()));
+        error.to_string()
+    };
+    assert!(message(r#"{"A + C -> B": 1.0}"#).contains("column 5: unknown species `C`"));
+    assert!(message(r#"{"A <-> B": 1.0}"#).contains("two rates"));
+    assert!(message(r#"{"A -> B": [1.0, 2.0]}"#).contains("single rate"));
+    assert!(message(r#"{"A -> -> B": 1.0}"#).contains("column 6"));
+}
@@ -0,0 +1,56 @@
+use simulation::{Model, RateModel, RateUnits, ReactionRate, Simulation};
+use simulation::rates::AVOGADRO;
+
+const LAMBDA: f64 = 50e-9;
+
+fn model(reactions: &str) -> String {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": {}}},
+        "particles": [
+            {{"name": "A", "to_region": "background", "count": 10}},
+            {{"name": "B", "to_region": "background", "count": 10}}
+        ],
+        "reactions": {}
+    }}"#, LAMBDA, reactions)
+}
+
+fn assert_close(value: f32, expected: f64) {
+    assert!(((value as f64 - expected) / expected).abs() < 1e-5, "{} is not {}", value, expected);
+}
+
+#[test]
+fn conversion_by_order() {
+    let volume = LAMBDA.powi(3);
+    let molecules_per_molar = AVOGADRO * volume * 1e3;
+
+    assert_eq!(ReactionRate::per_voxel(2.5).to_lattice(2, volume), 2.5);
+    assert_close(ReactionRate::molar(1e6).to_lattice(2, volume), 1e6 / molecules_per_molar);
+    assert_close(ReactionRate::molar(1e6).to_lattice(3, volume), 1e6 / molecules_per_molar.powi(2));
+    assert_close(ReactionRate::molar(0.3).to_lattice(1, volume), 0.3);
+    assert_close(ReactionRate::molar(1e-9).to_lattice(0, volume), 1e-9 * molecules_per_molar);
+    assert_close(ReactionRate::per_molecule(1e-20).to_lattice(2, volume), 1e-20 / volume);
+}
+
+#[test]
+fn units_in_model_files() {
+    let model: Model = model(r#"{"A + B -> B": {"value": 1e6, "units": "molar"}, "B -> A": 0.5}"#).parse().unwrap();
+    assert_eq!(model.reactions[0].1, ReactionRate { value: 1e6, units: RateUnits::Molar }.into());
+    assert_eq!(model.reactions[1].1, ReactionRate::per_voxel(0.5).into());
+
+    // The simulation keeps per-voxel rates only
+    let written = Simulation::from_model(&model).unwrap().to_model();
+    match written.reactions[0].1.rate {
+        RateModel::Forward(rate) => {
+            assert_eq!(rate.units, RateUnits::PerVoxel);
+            assert_close(rate.value, 1e6 / (AVOGADRO * LAMBDA.powi(3) * 1e3));
+        },
+        ref other => panic!("unexpected rate {:?}", other),
+    }
+    assert_eq!(written.reactions[1].1, ReactionRate::per_voxel(0.5).into());
+}
+
+#[test]
+fn unknown_units() {
+    let error = model(r#"{"A + B -> B": {"value": 1e6, "units": "furlongs"}}"#).parse::<Model>().unwrap_err();
+    assert_eq!(error.path(), Some("reactions.A + B -> B.units"));
+}
@@ -0,0 +1,162 @@
+use simulation::{LatticeParams, Model, ReactionSolver, Simulation};
+use simulation::random::{Stream, CME_STREAM};
+use simulation::solver::{Voxel, MAX_EVENTS};
+
+const SEED: u64 = 0x5eed;
+const TAU: f32 = 3e-3;
+
+fn model(solver: &str) -> Result<Model, simulation::ModelError> {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9{}}},
+        "particles": [{{"name": "A", "to_region": "background", "count": 10}}],
+        "reactions": {{"A -> 0": 0.5}}
+    }}"#, solver).parse()
+}
+
+#[test]
+fn solver_in_model_files() {
+    assert_eq!(model("").unwrap().parameters.solver, ReactionSolver::SingleEvent);
+    assert_eq!(model(r#", "solver": "ssa""#).unwrap().parameters.solver, ReactionSolver::Ssa);
+    let model = model(r#", "solver": {"tau_leaping": {"threshold": 10}}"#).unwrap();
+    assert_eq!(model.parameters.solver, ReactionSolver::TauLeaping { threshold: 10. });
+
+    let simulation = Simulation::from_model(&model).unwrap();
+    assert_eq!(simulation.solver(), ReactionSolver::TauLeaping { threshold: 10. });
+    assert_eq!(simulation.to_model().parameters, model.parameters);
+}
+
+#[test]
+fn default_solver() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    assert_eq!(simulation.solver(), ReactionSolver::SingleEvent);
+    simulation.set_solver(ReactionSolver::Ssa);
+    assert_eq!(simulation.solver(), ReactionSolver::Ssa);
+
+    // The default is not written
+    let written = serde_json::to_value(model("").unwrap().parameters).unwrap();
+    assert!(written.get("solver").is_none());
+}
+
+#[test]
+fn solver_errors() {
+    assert_eq!(model(r#", "solver": "euler""#).unwrap_err().path(), Some("parameters.solver"));
+    let error = Simulation::from_model(&model(r#", "solver": {"tau_leaping": {"threshold": 0}}"#).unwrap()).err().unwrap();
+    assert_eq!(error.path(), Some("parameters.solver.tau_leaping.threshold"));
+}
+
+#[test]
+#[should_panic(expected = "`threshold` must be positive")]
+fn invalid_threshold_in_code() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    simulation.set_solver(ReactionSolver::TauLeaping { threshold: f32::NAN });
+}
+
+/// `A -> A + B` producing `events` B per step on average, or `A -> 0` if `degradation`.
+fn voxel(events: f32, a: i32, max_particles_site: u32, degradation: bool) -> Voxel {
+    Voxel {
+        rates: vec![events / (a as f32 * TAU)],
+        reactants: vec![vec![1]],
+        changes: vec![if degradation { vec![0, -1, 0] } else { vec![0, 0, 1] }],
+        max_particles_site,
+        counts: vec![0, a, 0],
+    }
+}
+
+/// Mean and variance of the events of a reaction in one step of many voxels.
+fn events(solver: ReactionSolver, voxel: &Voxel) -> (f64, f64) {
+    let samples: Vec<f64> = (0..2000)
+        .map(|slot| voxel.clone().step(solver, TAU, &mut Stream::new(SEED, slot, 1, CME_STREAM))[0] as f64)
+        .collect();
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64;
+    (mean, variance)
+}
+
+#[test]
+fn fast_reactions() {
+    // 50 events per step, Poisson distributed
+    let fast = voxel(50., 10, 1000, false);
+    for solver in [ReactionSolver::Ssa, ReactionSolver::TauLeaping { threshold: 10. }] {
+        let (mean, variance) = events(solver, &fast);
+        assert!((mean - 50.).abs() < 4. * (50f64 / 2000.).sqrt(), "{:?}: mean {}", solver, mean);
+        assert!((variance / 50. - 1.).abs() < 0.15, "{:?}: variance {}", solver, variance);
+    }
+    // One event at most
+    let (mean, _) = events(ReactionSolver::SingleEvent, &fast);
+    assert!(mean <= 1.);
+    assert!(mean > 0.99);
+
+    // Slow enough for single events: all agree on the mean
+    let slow = voxel(0.2, 10, 1000, false);
+    for solver in [ReactionSolver::SingleEvent, ReactionSolver::Ssa, ReactionSolver::TauLeaping { threshold: 10. }] {
+        let (mean, _) = events(solver, &slow);
+        let expected = if solver == ReactionSolver::SingleEvent { 1. - (-0.2f64).exp() } else { 0.2 };
+        assert!((mean - expected).abs() < 4. * (0.2f64 / 2000.).sqrt(), "{:?}: mean {}", solver, mean);
+    }
+}
+
+#[test]
+fn event_cap() {
+    // `A <-> B` back and forth, far more often than the cap allows
+    let mut isomerization = Voxel {
+        rates: vec![1e6, 1e6],
+        reactants: vec![vec![1], vec![2]],
+        changes: vec![vec![0, -1, 1], vec![0, 1, -1]],
+        max_particles_site: 100,
+        counts: vec![0, 10, 10],
+    };
+    for slot in 0..10 {
+        let firings = isomerization.step(ReactionSolver::Ssa, TAU, &mut Stream::new(SEED, slot, 1, CME_STREAM));
+        assert_eq!(firings.iter().sum::<u32>(), MAX_EVENTS);
+        assert_eq!(isomerization.counts[1] + isomerization.counts[2], 20);
+    }
+}
+
+#[test]
+fn overflow_rollback() {
+    for slot in 0..100 {
+        let mut stream = Stream::new(SEED, slot, 1, CME_STREAM);
+
+        // Single steps that do not fit are rejected, and the last slot of the site stays empty
+        let mut full = voxel(50., 5, 20, false);
+        assert_eq!(full.step(ReactionSolver::Ssa, TAU, &mut stream), vec![14]);
+        assert_eq!(full.counts, vec![0, 5, 14]);
+
+        // A leap that does not fit is halved until it does
+        let mut leap = voxel(50., 5, 20, false);
+        let firings = leap.step(ReactionSolver::TauLeaping { threshold: 10. }, TAU, &mut stream);
+        assert!(firings[0] > 0 && 5 + firings[0] < 20, "{:?}", firings);
+        assert_eq!(leap.counts, vec![0, 5, firings[0] as i32]);
+
+        // Or until no count becomes negative
+        let mut degradation = voxel(50., 10, 20, true);
+        let firings = degradation.step(ReactionSolver::TauLeaping { threshold: 10. }, TAU, &mut stream);
+        assert!(firings[0] > 0 && firings[0] <= 10, "{:?}", firings);
+        assert_eq!(degradation.counts, vec![0, 10 - firings[0] as i32, 0]);
+    }
+}
+
+#[test]
+fn full_site_keeps_degrading() {
+    // `0 -> A` about 200 times per step and `A -> 0` about 5 times, in a site that a production fills
+    let full = Voxel {
+        rates: vec![200. / TAU, 5. / (19. * TAU)],
+        reactants: vec![vec![0], vec![1]],
+        changes: vec![vec![0, 1], vec![0, -1]],
+        max_particles_site: 20,
+        counts: vec![0, 19],
+    };
+    let samples = 1000;
+    let mut degradations = 0;
+    for slot in 0..samples {
+        let mut voxel = full.clone();
+        let firings = voxel.step(ReactionSolver::Ssa, TAU, &mut Stream::new(SEED, slot, 1, CME_STREAM));
+        // Productions only fit in the room that degradations free
+        assert!(firings[0] <= firings[1], "{:?}", firings);
+        assert_eq!(voxel.counts[1], 19 + firings[0] as i32 - firings[1] as i32);
+        degradations += firings[1];
+    }
+    // Rejected productions do not stop them: 5 per step, a little fewer while the site is not full
+    let mean = degradations as f64 / samples as f64;
+    assert!(mean > 4.6 && mean < 5.3, "mean {}", mean);
+}
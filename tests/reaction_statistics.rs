@@ -0,0 +1,73 @@
+use simulation::Simulation;
+
+fn simulation(reactions: &str) -> Simulation {
+    let model = format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9}},
+        "regions": [{{"type": "sphere", "name": "cell", "center": [0.2, 0.2, 0.2], "radius": 0.15, "base_diffusion_rate": 1e-14}}],
+        "particles": [
+            {{"name": "A", "to_region": "cell", "count": 10, "logging": true}},
+            {{"name": "B", "to_region": "cell", "count": 10}}
+        ],
+        "reactions": {}
+    }}"#, reactions).parse().unwrap();
+    Simulation::from_model(&model).unwrap()
+}
+
+#[test]
+fn logged_reactions() {
+    let simulation = simulation(r#"{
+        "A -> B": {"rate": 0.5, "logging": true},
+        "B -> 0": 0.1,
+        "A <-> 0": {"rate": [0.1, 0.2], "regions": ["cell"], "logging": true}
+    }"#);
+
+    // Two regions, so three counters per reaction from reaction 1
+    let statistics = simulation.logged_statistics();
+    let series = statistics.iter().map(|(name, idx)| (name.as_str(), *idx)).collect::<Vec<_>>();
+    assert_eq!(series, [
+        ("A", [0, 1]),
+        ("reaction:0 -> A", [1, 12]),
+        ("reaction:0 -> A@cell", [1, 14]),
+        ("reaction:A -> 0", [1, 9]),
+        ("reaction:A -> 0@cell", [1, 11]),
+        ("reaction:A -> B", [1, 3]),
+        ("reaction:A -> B@background", [1, 4]),
+        ("reaction:A -> B@cell", [1, 5]),
+    ]);
+
+    // The flag is kept in the model
+    let written = simulation.to_model();
+    assert!(written.reactions[0].1.logging && !written.reactions[1].1.logging && written.reactions[3].1.logging);
+    let text = serde_json::to_string(&written).unwrap();
+    assert_eq!(Simulation::from_model(&text.parse().unwrap()).unwrap().logged_statistics(), statistics);
+}
+
+#[test]
+fn logged_in_code() {
+    let mut simulation = simulation("{}");
+    simulation.add_reaction(vec!["A"], vec!["B"], 0.5);
+    assert!(!simulation.logged_statistics().keys().any(|name| name.starts_with("reaction:")));
+
+    simulation.set_reaction_logging(0, true);
+    assert_eq!(simulation.logged_statistics().get("reaction:A -> B"), Some(&[1, 3]));
+}
+
+#[test]
+fn same_equation_twice() {
+    let mut simulation = simulation("{}");
+    simulation.add_reaction_in_regions(vec!["A"], vec!["B"], 0.5, &["cell"]);
+    simulation.add_reaction_in_regions(vec!["B"], vec!["0"], 0.1, &[]);
+    simulation.add_reaction_in_regions(vec!["A"], vec!["B"], 0.2, &["background"]);
+    simulation.set_reaction_logging(0, true);
+    simulation.set_reaction_logging(2, true);
+
+    let statistics = simulation.logged_statistics();
+    let series = statistics.iter().map(|(name, idx)| (name.as_str(), *idx)).collect::<Vec<_>>();
+    assert_eq!(series, [
+        ("A", [0, 1]),
+        ("reaction:A -> B", [1, 3]),
+        ("reaction:A -> B#2", [1, 9]),
+        ("reaction:A -> B#2@background", [1, 10]),
+        ("reaction:A -> B@cell", [1, 5]),
+    ]);
+}
@@ -0,0 +1,56 @@
+use simulation::{Model, RateLaw, RateModel, ReactionModel, ReactionRate, Simulation};
+
+fn model(reactions: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9}},
+        "regions": [{{"type": "sphere", "name": "cell", "center": [0.2, 0.2, 0.2], "radius": 0.15, "base_diffusion_rate": 1e-14}}],
+        "particles": [
+            {{"name": "A", "to_region": "cell", "count": 10}},
+            {{"name": "B", "to_region": "cell", "count": 10}}
+        ],
+        "reactions": {}
+    }}"#, reactions).parse().unwrap()
+}
+
+#[test]
+fn reactions_in_regions() {
+    let model = model(r#"{
+        "A -> B": {"rate": 0.5, "regions": ["cell"]},
+        "B -> A": {"rate": {"value": 2, "units": "per_voxel"}},
+        "A <-> 0": {"rate": [0.1, 0.2], "regions": ["background"]}
+    }"#);
+    assert_eq!(model.reactions[0].1, ReactionModel { rate: ReactionRate::per_voxel(0.5).into(), law: RateLaw::MassAction, regions: vec!["cell".to_string()], logging: false, schedule: Vec::new() });
+    assert_eq!(model.reactions[1].1, ReactionRate::per_voxel(2.).into());
+    assert!(matches!(model.reactions[2].1.rate, RateModel::Reversible(..)));
+
+    let simulation = Simulation::from_model(&model).unwrap();
+    let counts = [0, 4, 3];
+    assert_eq!(simulation.propensities("cell", &counts), [2., 6., 0., 0.]);
+    assert_eq!(simulation.propensities("background", &counts), [0., 6., 0.4, 0.2]);
+
+    // Both halves of a reversible reaction keep its regions
+    let written = simulation.to_model();
+    assert_eq!(written.reactions[0].1, model.reactions[0].1);
+    assert_eq!(written.reactions[3], ("0 -> A".to_string(), ReactionModel { rate: ReactionRate::per_voxel(0.2).into(), law: RateLaw::MassAction, regions: vec!["background".to_string()], logging: false, schedule: Vec::new() }));
+    assert_eq!(Simulation::from_model(&written).unwrap().propensities("cell", &counts), [2., 6., 0., 0.]);
+}
+
+#[test]
+fn added_in_code() {
+    let mut simulation = Simulation::from_model(&model("{}")).unwrap();
+    simulation.add_reaction_in_regions(vec!["A", "B"], vec![], 0.5, &["cell"]);
+    simulation.add_reaction(vec!["A"], vec![], 0.1);
+    assert_eq!(simulation.propensities("cell", &[0, 2, 2]), [2., 0.2]);
+    assert_eq!(simulation.propensities("background", &[0, 2, 2]), [0., 0.2]);
+}
+
+#[test]
+fn region_errors() {
+    let error = Simulation::from_model(&model(r#"{"A -> B": {"rate": 0.5, "regions": ["nucleus"]}}"#)).err().unwrap();
+    assert_eq!(error.path(), Some("reactions.A -> B.regions[0]"));
+    assert!(error.to_string().contains("unknown region `nucleus`"), "{}", error);
+
+    let error = r#"{"parameters": {"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9},
+        "reactions": {"A -> B": {"rate": 0.5, "region": ["cell"]}}}"#.parse::<Model>().unwrap_err();
+    assert_eq!(error.path(), Some("reactions.A -> B.region"));
+}
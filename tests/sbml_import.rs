@@ -0,0 +1,205 @@
+use simulation::{LatticeParams, ReactionModel, ReactionRate, RegionType, Simulation};
+use simulation::rates::AVOGADRO;
+use simulation::sbml::{InitialValue, SbmlError, SbmlModel};
+
+fn sbml(species: &str, reactions: &str, extra: &str) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<sbml xmlns="http://www.sbml.org/sbml/level3/version2/core" level="3" version="2">
+  <model id="test" substanceUnits="mole" extentUnits="mole" volumeUnits="litre" timeUnits="minute">
+    <listOfUnitDefinitions>
+      <unitDefinition id="minute"><listOfUnits><unit kind="second" exponent="1" scale="0" multiplier="60"/></listOfUnits></unitDefinition>
+    </listOfUnitDefinitions>
+    <listOfCompartments>
+      <compartment id="c1" name="interior" size="1e-15" constant="true"/>
+    </listOfCompartments>
+    <listOfSpecies>{}</listOfSpecies>
+    <listOfParameters>
+      <parameter id="k1" value="60" constant="true"/>
+    </listOfParameters>
+    <listOfReactions>{}</listOfReactions>
+    {}
+  </model>
+</sbml>"#, species, reactions, extra)
+}
+
+const SPECIES: &str = r#"
+      <species id="A" compartment="c1" initialConcentration="1e-6" hasOnlySubstanceUnits="false" boundaryCondition="false" constant="false"/>
+      <species id="B" compartment="c1" initialConcentration="0" hasOnlySubstanceUnits="false" boundaryCondition="false" constant="false"/>
+      <species id="E" compartment="c1" initialAmount="10" substanceUnits="item" hasOnlySubstanceUnits="true" boundaryCondition="true" constant="false"/>"#;
+
+const REACTIONS: &str = r#"
+      <reaction id="dimerization" reversible="false">
+        <listOfReactants><speciesReference species="A" stoichiometry="2" constant="true"/></listOfReactants>
+        <listOfProducts><speciesReference species="B" stoichiometry="1" constant="true"/></listOfProducts>
+        <kineticLaw><math xmlns="http://www.w3.org/1998/Math/MathML">
+          <apply><times/><ci>c1</ci><ci>k1</ci><apply><power/><ci>A</ci><cn type="integer">2</cn></apply></apply>
+        </math></kineticLaw>
+      </reaction>
+      <reaction id="binding" reversible="true">
+        <listOfReactants>
+          <speciesReference species="E" stoichiometry="1" constant="true"/>
+          <speciesReference species="A" stoichiometry="1" constant="true"/>
+        </listOfReactants>
+        <listOfProducts><speciesReference species="B" stoichiometry="1" constant="true"/></listOfProducts>
+        <kineticLaw>
+          <math xmlns="http://www.w3.org/1998/Math/MathML">
+            <apply><minus/>
+              <apply><times/><ci>kf</ci><ci>E</ci><ci>A</ci></apply>
+              <apply><times/><ci>c1</ci><cn type="e-notation">2<sep/>-3</cn><ci>B</ci></apply>
+            </apply>
+          </math>
+          <listOfLocalParameters><localParameter id="kf" value="4"/></listOfLocalParameters>
+        </kineticLaw>
+      </reaction>"#;
+
+fn assert_close(value: f64, expected: f64) {
+    assert!((value / expected - 1.).abs() < 1e-9, "{} is not {}", value, expected);
+}
+
+#[test]
+fn reads_mass_action_reactions() {
+    let model: SbmlModel = sbml(SPECIES, REACTIONS, "").parse().unwrap();
+
+    assert_eq!(model.compartments[0].size, Some(1e-15));
+    assert_eq!(model.species[0].initial, InitialValue::Concentration(1e-6));
+    assert_eq!(model.species[2].initial, InitialValue::Amount(10.));
+    assert!(model.species[2].is_reservoir);
+
+    let reactions = model.reactions.iter()
+        .map(|r| (r.id.as_str(), r.reactants.join(" + "), r.products.join(" + ")))
+        .collect::<Vec<_>>();
+    assert_eq!(reactions, vec![
+        ("dimerization", "A + A".to_string(), "B".to_string()),
+        ("binding", "E + A".to_string(), "B".to_string()),
+        ("binding_reverse", "B".to_string(), "E + A".to_string()),
+    ]);
+    // Per minute to per second. `kf * E * A` takes E in molecules, not in moles
+    assert_close(model.reactions[0].rate, 1.);
+    assert_close(model.reactions[1].rate, 4. * AVOGADRO / 60.);
+    // First order: the size of the compartment cancels out
+    assert_close(model.reactions[2].rate, 2e-3 / 60.);
+}
+
+#[test]
+fn reads_any_number_of_reactants() {
+    let tetramerization = r#"
+      <reaction id="tetramerization" reversible="true">
+        <listOfReactants><speciesReference species="A" stoichiometry="4" constant="true"/></listOfReactants>
+        <listOfProducts><speciesReference species="B" stoichiometry="1" constant="true"/></listOfProducts>
+        <kineticLaw><math xmlns="http://www.w3.org/1998/Math/MathML">
+          <apply><minus/>
+            <apply><times/><ci>c1</ci><ci>k1</ci><apply><power/><ci>A</ci><cn type="integer">4</cn></apply></apply>
+            <apply><times/><ci>c1</ci><ci>k1</ci><ci>B</ci></apply>
+          </apply>
+        </math></kineticLaw>
+      </reaction>"#;
+    let model: SbmlModel = sbml(SPECIES, tetramerization, "").parse().unwrap();
+
+    assert_eq!(model.reactions.len(), 2);
+    assert_eq!(model.reactions[0].reactants, vec!["A"; 4]);
+    assert_eq!(model.reactions[1].products, vec!["A"; 4]);
+    // M^-3 s^-1 and s^-1
+    assert_close(model.reactions[0].rate, 1.);
+    assert_close(model.reactions[1].rate, 1.);
+}
+
+#[test]
+fn converts_units() {
+    // Amounts in moles and a volume in cubic micrometres
+    let species = r#"
+      <species id="A" compartment="c1" initialAmount="1e-21" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+      <species id="B" compartment="c1" initialAmount="0" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>"#;
+    let reaction = r#"
+      <reaction id="dimerization" reversible="false">
+        <listOfReactants><speciesReference species="A" stoichiometry="2" constant="true"/></listOfReactants>
+        <listOfProducts><speciesReference species="B" stoichiometry="1" constant="true"/></listOfProducts>
+        <kineticLaw><math xmlns="http://www.w3.org/1998/Math/MathML">
+          <apply><times/><ci>k1</ci><ci>A</ci><ci>A</ci></apply>
+        </math></kineticLaw>
+      </reaction>"#;
+    let cubic_micrometre = r#"<listOfUnitDefinitions>
+      <unitDefinition id="minute"><listOfUnits><unit kind="second" exponent="1" scale="0" multiplier="60"/></listOfUnits></unitDefinition>
+      <unitDefinition id="um3"><listOfUnits><unit kind="metre" exponent="3" scale="-6" multiplier="1"/></listOfUnits></unitDefinition>
+    </listOfUnitDefinitions>"#;
+    let document = sbml(species, reaction, "")
+        .replace(r#"volumeUnits="litre""#, r#"volumeUnits="um3""#)
+        .replace(r#"size="1e-15""#, r#"size="1""#);
+    let start = document.find("<listOfUnitDefinitions>").unwrap();
+    let end = document.find("</listOfUnitDefinitions>").unwrap() + "</listOfUnitDefinitions>".len();
+    let model: SbmlModel = format!("{}{}{}", &document[..start], cubic_micrometre, &document[end..]).parse().unwrap();
+
+    assert_close(model.compartments[0].size.unwrap(), 1e-15);
+    if let InitialValue::Amount(molecules) = model.species[0].initial {
+        assert_close(molecules, 1e-21 * AVOGADRO);
+    } else {
+        panic!("A has an amount");
+    }
+    // `k1 nA^2` moles per minute of amounts in moles: k1 / 60 times the compartment size in litres
+    assert_close(model.reactions[0].rate, 60. / 60. * 1e-15);
+}
+
+#[test]
+fn needs_units() {
+    let document = sbml(SPECIES, REACTIONS, "").replace(r#" timeUnits="minute""#, "");
+    match document.parse::<SbmlModel>() {
+        Err(SbmlError::Unsupported(constructs)) => {
+            assert_eq!(constructs.len(), 2, "{:?}", constructs);
+            assert!(constructs.iter().all(|construct| construct.contains("units of the rate")), "{:?}", constructs);
+        },
+        other => panic!("expected unsupported constructs, got {:?}", other),
+    }
+
+    let document = sbml(SPECIES, "", "").replace(r#" substanceUnits="mole""#, "");
+    match document.parse::<SbmlModel>() {
+        Err(SbmlError::Unsupported(constructs)) => assert_eq!(constructs.len(), 2, "{:?}", constructs),
+        other => panic!("expected unsupported constructs, got {:?}", other),
+    }
+}
+
+#[test]
+fn lists_unsupported_constructs() {
+    let michaelis_menten = r#"
+      <reaction id="degradation" reversible="false">
+        <listOfReactants><speciesReference species="A" stoichiometry="1" constant="true"/></listOfReactants>
+        <kineticLaw><math xmlns="http://www.w3.org/1998/Math/MathML">
+          <apply><divide/><apply><times/><ci>k1</ci><ci>A</ci></apply><apply><plus/><cn>1</cn><ci>A</ci></apply></apply>
+        </math></kineticLaw>
+      </reaction>"#;
+    let rules = r#"<listOfRules><assignmentRule variable="k1"><math xmlns="http://www.w3.org/1998/Math/MathML"><cn>1</cn></math></assignmentRule></listOfRules>"#;
+
+    match sbml(SPECIES, michaelis_menten, rules).parse::<SbmlModel>() {
+        Err(SbmlError::Unsupported(constructs)) => {
+            assert_eq!(constructs.len(), 2, "{:?}", constructs);
+            assert!(constructs[0].contains("assignmentRule `k1`"));
+            assert!(constructs[1].contains("reaction `degradation`"));
+        },
+        other => panic!("expected unsupported constructs, got {:?}", other),
+    }
+}
+
+#[test]
+fn adds_to_simulation() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.8, 0.8, 0.8], [16, 16, 16], 3e-3, 50e-9));
+    simulation.add_region(RegionType::Sphere { name: "interior".to_string(), center: [0.4; 3], radius: 0.3 }, 1e-14);
+    simulation.prepare_regions();
+
+    let model: SbmlModel = sbml(SPECIES, REACTIONS, "").parse().unwrap();
+    simulation.add_sbml(&model).unwrap();
+
+    let written = simulation.to_model();
+    let names = written.particles.iter().map(|p| (p.name.as_str(), p.to_region.as_str())).collect::<Vec<_>>();
+    assert_eq!(names, [("A", "interior"), ("B", "interior"), ("E", "interior")]);
+    // 1 µM in 1 fL
+    assert_eq!(written.particles[0].count, Some(602));
+    assert_eq!(written.particles[2].count, Some(10));
+    assert!(written.particles[2].is_reservoir);
+    let rates = model.reactions.iter().map(|r| ReactionRate::molar(r.rate as f32).into()).collect::<Vec<ReactionModel>>();
+    assert_eq!(written.reactions, vec![
+        ("2 A -> B".to_string(), rates[0].clone()),
+        ("E + A -> B".to_string(), rates[1].clone()),
+        ("B -> A + E".to_string(), rates[2].clone()),
+    ]);
+
+    // A second import clashes with the particles that are already there
+    assert!(matches!(simulation.add_sbml(&model), Err(SbmlError::Invalid { .. })));
+}
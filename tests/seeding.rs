@@ -0,0 +1,75 @@
+use simulation::{LatticeParams, Model, RegionType, Simulation};
+
+fn model(seed: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9{}}},
+        "regions": [{{"type": "sphere", "name": "cell", "center": [0.2, 0.2, 0.2], "radius": 0.15, "base_diffusion_rate": 1e-14}}],
+        "particles": [
+            {{"name": "A", "to_region": "cell", "count": 50}},
+            {{"name": "B", "to_region": "background", "count": 50}}
+        ]
+    }}"#, seed).parse().unwrap()
+}
+
+fn occupancy(simulation: &Simulation) -> Vec<u32> {
+    let res = simulation.lattice_params.get_res_usize();
+    (0..res[0]).flat_map(|x| (0..res[1]).flat_map(move |y| (0..res[2]).map(move |z| [x, y, z])))
+        .map(|voxel| simulation.lattices[0].occupancy[voxel])
+        .collect()
+}
+
+#[test]
+fn same_seed_same_placement() {
+    let first = Simulation::from_model(&model(r#", "seed": 7"#)).unwrap();
+    let second = Simulation::from_model(&model(r#", "seed": 7"#)).unwrap();
+    assert_eq!(first.seed(), 7);
+    assert_eq!(occupancy(&first), occupancy(&second));
+    assert_eq!(first.lattice_params.raw.seed, second.lattice_params.raw.seed);
+
+    let other = Simulation::from_model(&model(r#", "seed": 8"#)).unwrap();
+    assert_ne!(occupancy(&first), occupancy(&other));
+    assert_ne!(first.lattice_params.raw.seed, other.lattice_params.raw.seed);
+}
+
+#[test]
+fn seeds_in_model_files() {
+    let seeded = Simulation::from_model(&model(r#", "seed": 18446744073709551557"#)).unwrap();
+    assert_eq!(seeded.to_model().parameters.seed, Some(18446744073709551557));
+
+    // A drawn seed reproduces the lattice, but it is not written back
+    let drawn = Simulation::from_model(&model("")).unwrap();
+    assert_eq!(drawn.to_model().parameters.seed, None);
+    let mut model = model("");
+    model.parameters.seed = Some(drawn.seed());
+    assert_eq!(occupancy(&Simulation::from_model(&model).unwrap()), occupancy(&drawn));
+}
+
+#[test]
+fn seeds_in_code() {
+    let placed = |seed| {
+        let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [8, 8, 8], 3e-3, 50e-9));
+        simulation.set_seed(seed);
+        simulation.prepare_regions();
+        simulation.add_particle_count("A", "background", 100, false, false);
+        occupancy(&simulation)
+    };
+    assert_eq!(placed(1), placed(1));
+    assert_ne!(placed(1), placed(2));
+}
+
+#[test]
+#[should_panic(expected = "Set the seed before adding particles")]
+fn seed_after_particles() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [8, 8, 8], 3e-3, 50e-9));
+    simulation.prepare_regions();
+    simulation.add_particle_count("A", "background", 10, false, false);
+    simulation.set_seed(3);
+}
+
+#[test]
+#[should_panic(expected = "Set the seed before adding regions")]
+fn seed_after_regions() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [8, 8, 8], 3e-3, 50e-9));
+    simulation.add_region(RegionType::Cube { name: "box".to_string(), p0: [0.; 3], pf: [0.2, 0.4, 0.4] }, 1e-14);
+    simulation.set_seed(3);
+}
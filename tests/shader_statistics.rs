@@ -0,0 +1,160 @@
+//! Statistics of the random numbers and the jumps of the shaders, through their CPU ports in `random` and
+//! `transport`. No GPU needed. The seeds are fixed, so each check passes or fails for good; the thresholds are
+//! those of a 0.1% test.
+
+use simulation::{AxisBoundary, Face, LatticeParams, Simulation};
+use simulation::random::{mulhilo, to_unit_float, Stream, CME_STREAM, RDME_STREAM, SURFACE_STREAM};
+use simulation::transport::select_jump;
+
+const SEED: u64 = 0x5eed;
+
+/// First number of the stream of each slot in a step, as every invocation of `rdme.wgsl` draws.
+fn first_draws(slots: u32, step: u32, stream: u32) -> Vec<f64> {
+    (0..slots).map(|slot| Stream::new(SEED, slot, step, stream).next_f32() as f64).collect()
+}
+
+fn chi_square(samples: &[f64], bins: usize) -> f64 {
+    let mut counts = vec![0usize; bins];
+    for sample in samples {
+        counts[(sample * bins as f64) as usize] += 1;
+    }
+    let expected = samples.len() as f64 / bins as f64;
+    counts.iter().map(|count| (*count as f64 - expected).powi(2) / expected).sum()
+}
+
+/// Largest distance between the empirical distribution and the uniform one.
+fn kolmogorov_smirnov(samples: &[f64]) -> f64 {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = sorted.len() as f64;
+    sorted.iter().enumerate()
+        .map(|(i, x)| (x - i as f64 / n).max((i + 1) as f64 / n - x))
+        .fold(0., f64::max)
+}
+
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let covariance: f64 = a.iter().zip(b).map(|(a, b)| (a - mean_a) * (b - mean_b)).sum();
+    let variance_a: f64 = a.iter().map(|a| (a - mean_a).powi(2)).sum();
+    let variance_b: f64 = b.iter().map(|b| (b - mean_b).powi(2)).sum();
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+#[test]
+fn uniformity() {
+    // Across the slots of a step, and along the stream of one invocation
+    let across = first_draws(100_000, 1, RDME_STREAM);
+    let mut stream = Stream::new(SEED, 42, 1, CME_STREAM);
+    let along: Vec<f64> = (0..100_000).map(|_| stream.next_f32() as f64).collect();
+
+    for samples in [&across, &along] {
+        // 99 degrees of freedom
+        let chi_square = chi_square(samples, 100);
+        assert!(chi_square < 148.2, "chi-square {}", chi_square);
+        let distance = kolmogorov_smirnov(samples);
+        assert!(distance < 1.95 / (samples.len() as f64).sqrt(), "Kolmogorov-Smirnov {}", distance);
+    }
+}
+
+#[test]
+fn independent_streams() {
+    let slots = 100_000;
+    let bound = 3.3 / (slots as f64).sqrt();
+    let draws = first_draws(slots, 1, RDME_STREAM);
+
+    // Neighbouring voxels or slots, the same slot in the next step and in the other kernels
+    let pairs = [
+        (draws[..slots as usize - 1].to_vec(), draws[1..].to_vec()),
+        (draws.clone(), first_draws(slots, 2, RDME_STREAM)),
+        (draws.clone(), first_draws(slots, 1, CME_STREAM)),
+        (first_draws(slots, 1, CME_STREAM), first_draws(slots, 1, SURFACE_STREAM)),
+    ];
+    for (a, b) in pairs.iter() {
+        let r = correlation(a, b);
+        assert!(r.abs() < bound, "correlation {}", r);
+    }
+
+    // Successive draws of an invocation, within and across blocks of 4
+    let mut stream = Stream::new(SEED, 7, 1, CME_STREAM);
+    let along: Vec<f64> = (0..100_001).map(|_| stream.next_f32() as f64).collect();
+    let r = correlation(&along[..100_000], &along[1..]);
+    assert!(r.abs() < bound, "correlation {}", r);
+}
+
+#[test]
+fn jumps_follow_the_probabilities() {
+    let probabilities = [0.05, 0.1, 0.02, 0.08, 0., 0.15];
+    let samples = 200_000;
+    let mut counts = [0usize; 7];
+    for slot in 0..samples {
+        let jump = select_jump(probabilities, Stream::new(SEED, slot, 1, RDME_STREAM).next_f32());
+        counts[jump.map_or(6, |face| face.index())] += 1;
+    }
+    assert_eq!(counts[Face::ZMin.index()], 0);
+
+    // 5 degrees of freedom, without the direction that is never taken
+    let stay = 1. - probabilities.iter().sum::<f32>();
+    let expected = probabilities.into_iter().chain([stay]).map(|p| p as f64 * samples as f64);
+    let chi_square: f64 = counts.iter().zip(expected)
+        .filter(|(_, expected)| *expected > 0.)
+        .map(|(count, expected)| (*count as f64 - expected).powi(2) / expected)
+        .sum();
+    assert!(chi_square < 20.5, "chi-square {}", chi_square);
+}
+
+#[test]
+fn mean_square_displacement() {
+    // Free diffusion: periodic axes, so the particles never meet a face
+    let (tau, lambda, diffusion) = (3e-3, 100e-9, 1e-13);
+    let mut lattice_params = LatticeParams::new([0.8, 0.8, 0.8], [8, 8, 8], tau, lambda);
+    lattice_params.set_boundaries([AxisBoundary::Periodic; 3]);
+    let mut simulation = Simulation::new(lattice_params);
+    simulation.prepare_regions();
+    simulation.add_particle_count("A", "background", 10, false, false);
+    simulation.set_diffusion_rate_particle("A", "background", diffusion);
+    let probabilities = simulation.jump_probabilities("A", [0, 0, 0]);
+
+    let (particles, steps) = (5000u32, 100u32);
+    let mut square_displacement = 0.;
+    for particle in 0..particles {
+        let mut displacement = [0i64; 3];
+        for step in 0..steps {
+            let uniform = Stream::new(SEED, particle, step, RDME_STREAM).next_f32();
+            if let Some(face) = select_jump(probabilities, uniform) {
+                displacement[face.axis()] += if face.index() % 2 == 0 { -1 } else { 1 };
+            }
+        }
+        square_displacement += displacement.iter().map(|d| (d * d) as f64).sum::<f64>();
+    }
+    let msd = square_displacement / particles as f64 * (lambda as f64).powi(2);
+    let expected = 6. * diffusion as f64 * (steps as f64 * tau as f64);
+    assert!((msd / expected - 1.).abs() < 0.05, "MSD {} against {}", msd, expected);
+}
+
+#[test]
+fn mulhilo_matches_the_product() {
+    let mut stream = Stream::new(SEED, 0, 0, CME_STREAM);
+    let words: Vec<u32> = [0, 1, 0xffff, 0x10000, 0x8000_0000, 0xffff_ffff].into_iter()
+        .chain((0..10_000).map(|_| stream.next_u32()))
+        .collect();
+    for (a, b) in words.iter().zip(words.iter().rev()).chain(words.iter().zip(words.iter())) {
+        let product = *a as u64 * *b as u64;
+        assert_eq!(mulhilo(*a, *b), ((product >> 32) as u32, product as u32), "{:#x} * {:#x}", a, b);
+    }
+    assert_eq!(mulhilo(0xffff_ffff, 0xffff_ffff), (0xffff_fffe, 1));
+}
+
+#[test]
+fn unit_float_conversion() {
+    // `f32(x >> 8u) / 16777216.0` of next_float: every value of the top 24 bits gives its own exact float in [0, 1)
+    let mut previous = -1.;
+    for top in 0..1u32 << 24 {
+        let uniform = to_unit_float(top << 8 | 0xff);
+        assert_eq!(uniform as f64, top as f64 / 16777216.);
+        assert!(uniform > previous);
+        previous = uniform;
+    }
+    assert_eq!(to_unit_float(0), 0.);
+    assert!(previous < 1.);
+}
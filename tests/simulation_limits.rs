@@ -0,0 +1,87 @@
+use simulation::{LatticeParams, Model, Simulation};
+use simulation::validation::{Issue, MAX_REACTIONS_CAP};
+
+fn model(parameters: &str, count: u32) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [4, 4, 4], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 100e-9{}}},
+        "particles": [{{"name": "A", "to_region": "background", "count": {}}}]
+    }}"#, parameters, count).parse().unwrap()
+}
+
+#[test]
+fn limits_in_model_files() {
+    // 64 sites with room for 8 particles each by default
+    assert!(Simulation::from_model(&model("", 600)).is_err());
+
+    let simulation = Simulation::from_model(&model(r#", "max_particles_site": 16, "max_reactions": 200"#, 600)).unwrap();
+    assert_eq!(simulation.lattice_params.max_particles_site(), 16);
+    assert_eq!(simulation.max_reactions(), 200);
+
+    let written = simulation.to_model();
+    assert_eq!((written.parameters.max_particles_site, written.parameters.max_reactions), (Some(16), Some(200)));
+
+    // Defaults are not written
+    let written = Simulation::from_model(&model("", 10)).unwrap().to_model();
+    assert_eq!((written.parameters.max_particles_site, written.parameters.max_reactions), (None, None));
+
+    let error = Simulation::from_model(&model(r#", "max_particles_site": 0"#, 10)).err().unwrap();
+    assert_eq!(error.path(), Some("parameters.max_particles_site"));
+    for max_reactions in [0, MAX_REACTIONS_CAP + 1] {
+        let error = Simulation::from_model(&model(&format!(r#", "max_reactions": {}"#, max_reactions), 10)).err().unwrap();
+        assert_eq!(error.path(), Some("parameters.max_reactions"));
+    }
+}
+
+#[test]
+#[should_panic(expected = "max_reactions must be between 1 and")]
+fn max_reactions_cap() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    simulation.set_max_reactions(MAX_REACTIONS_CAP + 1);
+}
+
+#[test]
+fn more_reactions() {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    simulation.prepare_regions();
+    simulation.add_particle_count("A", "background", 10, false, false);
+    for _ in 0..150 {
+        simulation.add_reaction(vec!["A"], vec!["A"], 0.1);
+    }
+    assert_eq!(simulation.validate().errors, vec![Issue::TooManyReactions { reactions: 150, max: 99 }]);
+
+    simulation.set_max_reactions(150);
+    assert!(simulation.validate().is_ok());
+}
+
+#[test]
+fn device_limits() {
+    let mut lattice_params = LatticeParams::new([0.4, 0.4, 0.4], [16, 16, 16], 3e-3, 100e-9);
+    lattice_params.set_max_particles_site(64);
+    let simulation = Simulation::new(lattice_params);
+    assert!(simulation.validate_limits(&wgpu::Limits::default()).is_ok());
+
+    // 16^3 sites with 64 slots of 4 bytes
+    let limits = wgpu::Limits { max_storage_buffer_binding_size: 1 << 19, ..Default::default() };
+    assert_eq!(simulation.validate_limits(&limits).errors, vec![
+        Issue::BufferTooLarge { buffer: "lattice".to_string(), size: 1 << 20, max: 1 << 19 },
+    ]);
+}
+
+#[test]
+fn tables_and_device_limits() {
+    // 4^3 sites with 8 slots of 4 bytes: 2048 bytes for the lattice, but more for three drift fields
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    simulation.prepare_regions();
+    let limits = wgpu::Limits { max_storage_buffer_binding_size: 2048, ..Default::default() };
+    for particle in ["A", "B", "C"] {
+        simulation.add_particle_count(particle, "background", 10, false, false);
+        simulation.set_drift_field_particle(particle, vec![[1e-6, 0., 0.]; 64]);
+    }
+    let buffers: Vec<String> = simulation.validate_limits(&limits).errors.into_iter()
+        .map(|issue| match issue {
+            Issue::BufferTooLarge { buffer, .. } => buffer,
+            other => panic!("unexpected issue {:?}", other),
+        })
+        .collect();
+    assert_eq!(buffers, vec!["transport"]);
+}
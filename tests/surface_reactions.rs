@@ -0,0 +1,91 @@
+use simulation::{Model, Simulation};
+use simulation::equation;
+use simulation::validation::Issue;
+
+fn model(reactions: &str) -> Model {
+    format!(r#"{{
+        "parameters": {{"lattice_resolution": [8, 8, 8], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 50e-9}},
+        "regions": [{{"type": "sphere", "name": "cell", "center": [0.2, 0.2, 0.2], "radius": 0.15, "base_diffusion_rate": 1e-14}}],
+        "particles": [
+            {{"name": "L", "to_region": "background", "count": 10}},
+            {{"name": "R", "to_region": "cell", "count": 10}},
+            {{"name": "C", "to_region": "cell", "count": 0}}
+        ],
+        "reactions": {}
+    }}"#, reactions).parse().unwrap()
+}
+
+#[test]
+fn surface_terms() {
+    let equation = equation::parse("L@background + 2 R@cell -> C@cell").unwrap().unwrap();
+    assert_eq!(equation.reactant_names(), ["L@background", "R@cell", "R@cell"]);
+    assert_eq!(equation.reactants[1].region.as_deref(), Some("cell"));
+    assert_eq!(equation::parse("L@ -> 0").unwrap_err().message, "expected a region after `@`");
+
+    // The host region is the one of the first reactant
+    assert_eq!(equation::surface_regions(&["L@background", "R@cell"], &["C@cell"]), Ok(Some(("background", Some("cell")))));
+    assert_eq!(equation::surface_regions(&["L", "R"], &["C"]), Ok(None));
+    assert!(equation::surface_regions(&["L@background", "R"], &[]).is_err());
+}
+
+#[test]
+fn surface_reactions_in_model_files() {
+    let simulation = Simulation::from_model(&model(r#"{"L@background + R@cell -> C@cell": 1e6}"#)).unwrap();
+
+    // L in the voxel times the R of each neighbour in the cell
+    let counts = [0, 2, 0, 0];
+    let neighbours: [(&str, &[i32]); 3] = [("cell", &[0, 0, 3, 0]), ("cell", &[0, 0, 1, 0]), ("background", &[0, 0, 5, 0])];
+    let single = simulation.surface_propensity(0, &[0, 1, 0, 0], &[("cell", &[0, 0, 1, 0])]);
+    assert!(single > 0.);
+    assert_eq!(simulation.surface_propensity(0, &counts, &neighbours), 8. * single);
+    assert_eq!(simulation.surface_propensity(0, &counts, &neighbours[2..]), 0.);
+    // Not through the kernel of the voxels
+    assert_eq!(simulation.propensities("background", &counts), [0.]);
+
+    let written = simulation.to_model();
+    assert_eq!(written.reactions[0].0, "L@background + R@cell -> C@cell");
+    assert_eq!(Simulation::from_model(&written).unwrap().surface_propensity(0, &counts, &neighbours), 8. * single);
+}
+
+#[test]
+fn surface_reaction_errors() {
+    let error = Simulation::from_model(&model(r#"{"L@nowhere -> 0": 1}"#)).err().unwrap();
+    assert_eq!(error.path(), Some("reactions.L@nowhere -> 0"));
+
+    let error = Simulation::from_model(&model(r#"{"L@background + R -> C": 1}"#)).err().unwrap();
+    assert_eq!(error.path(), Some("reactions.L@background + R -> C"));
+
+    let error = Simulation::from_model(&model(r#"{"L@background -> C@cell": {"rate": 1, "regions": ["cell"]}}"#)).err().unwrap();
+    assert_eq!(error.path(), Some("reactions.L@background -> C@cell.regions"));
+}
+
+#[test]
+fn surface_reactions_in_code() {
+    let mut simulation = Simulation::from_model(&model("{}")).unwrap();
+    simulation.add_reaction(vec!["R@cell"], vec!["L@background"], 0.5);
+
+    // Nothing taken from the neighbours: any of them in the background will do
+    assert_eq!(simulation.surface_propensity(0, &[0, 0, 2, 0], &[("background", &[0; 4]), ("background", &[0; 4])]), 1.);
+    assert_eq!(simulation.surface_propensity(0, &[0, 0, 2, 0], &[("cell", &[0; 4])]), 0.);
+    assert!(simulation.validate().warnings.is_empty());
+
+    // The events lost to a neighbour that was changing are counted in row 0
+    let statistics = simulation.logged_statistics();
+    assert_eq!((statistics.get("surface:lost"), statistics.get("surface:lost@cell")), (Some(&[1, 0]), Some(&[1, 2])));
+    assert!(!statistics.contains_key("surface:lost@background"));
+
+    // Once per step at most: 100 * 3e-3 expected firings in a voxel with an R is too many
+    simulation.add_reaction(vec!["R@cell"], vec!["L@background"], 100.);
+    match simulation.validate().errors.as_slice() {
+        [Issue::SurfaceFirings { reaction, firings, .. }] => {
+            assert_eq!(reaction, "R@cell -> L@background");
+            assert!(*firings >= 0.3 - 1e-6);
+        },
+        errors => panic!("{:?}", errors),
+    }
+    // Unless the undercount is accepted
+    simulation.set_allow_fast_surface_reactions(true);
+    let validation = simulation.validate();
+    assert!(validation.is_ok() && matches!(validation.warnings.as_slice(), [Issue::SurfaceFirings { .. }]), "{}", validation);
+    assert!(simulation.to_model().parameters.allow_fast_surface_reactions);
+}
@@ -0,0 +1,98 @@
+use simulation::{LatticeParams, Model, ModelError, RegionType, Simulation};
+use simulation::validation::Issue;
+
+fn simulation() -> Simulation {
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    simulation.add_region(RegionType::Cube { name: "box".to_string(), p0: [0.; 3], pf: [0.2, 0.4, 0.4] }, 1e-14);
+    simulation.prepare_regions();
+    simulation
+}
+
+#[test]
+fn valid_simulation() {
+    let mut simulation = simulation();
+    simulation.add_particle_count("A", "box", 10, false, false);
+    simulation.add_reaction(vec!["A", "A"], vec![], 0.1);
+
+    let validation = simulation.validate();
+    assert!(validation.is_ok(), "{}", validation);
+    assert!(validation.warnings.is_empty(), "{}", validation);
+}
+
+#[test]
+fn diffusion_probability() {
+    let mut simulation = simulation();
+    simulation.add_particle_count("A", "box", 10, false, false);
+    // 6 * 1e-12 * 3e-3 / (100e-9)^2 = 1.8
+    simulation.set_diffusion_rate_particle("A", "box", 1e-12);
+    simulation.set_transition_rate("box", "background", 2e-12);
+
+    let validation = simulation.validate();
+    match validation.errors.as_slice() {
+        [Issue::DiffusionProbability { particle, from_region, to_region, probability }] => {
+            assert_eq!((particle.as_str(), from_region.as_str(), to_region.as_str()), ("A", "box", "box"));
+            assert!((probability - 1.8).abs() < 1e-6);
+        },
+        other => panic!("unexpected errors {:?}", other),
+    }
+    assert!(matches!(validation.warnings.as_slice(), [Issue::DiffusionProbability { to_region, .. }] if to_region == "background"));
+}
+
+#[test]
+fn reaction_limits() {
+    let mut simulation = simulation();
+    simulation.add_particle_count("A", "box", 10, false, false);
+    simulation.add_reaction(vec!["A", "A", "A", "A"], vec!["A"], 0.1);
+    for _ in 0..99 {
+        simulation.add_reaction(vec!["A"], vec!["A"], 0.1);
+    }
+
+    // Reactions of any order are fine, their number is not
+    let validation = simulation.validate();
+    assert_eq!(validation.errors, vec![Issue::TooManyReactions { reactions: 100, max: 99 }]);
+}
+
+#[test]
+fn too_many_species() {
+    let mut simulation = simulation();
+    for i in 0..256 {
+        simulation.add_particle_count(&format!("S{}", i), "box", 0, false, false);
+    }
+    assert_eq!(simulation.validate().errors, vec![Issue::TooManySpecies { species: 256, max: 255 }]);
+}
+
+#[test]
+fn particles_left_out() {
+    // 64 sites with room for 8 particles each
+    let mut simulation = Simulation::new(LatticeParams::new([0.4, 0.4, 0.4], [4, 4, 4], 3e-3, 100e-9));
+    simulation.prepare_regions();
+    simulation.add_particle_count("A", "background", 510, false, false);
+    simulation.add_particle_count("B", "background", 10, false, false);
+    simulation.fill_region("C", "background", false);
+
+    // What was placed is what a written model holds
+    let counts = simulation.to_model().particles.iter().map(|particle| particle.count).collect::<Vec<_>>();
+    assert_eq!(counts, [Some(510), Some(2), Some(0)]);
+    assert_eq!(simulation.validate().errors, vec![
+        Issue::ParticlesDoNotFit { particle: "B".to_string(), region: "background".to_string(), missing: 8 },
+        Issue::ParticlesDoNotFit { particle: "C".to_string(), region: "background".to_string(), missing: 64 },
+    ]);
+}
+
+#[test]
+fn particles_that_do_not_fit() {
+    // 64 sites with room for 8 particles each
+    let model: Model = r#"{
+        "parameters": {"lattice_resolution": [4, 4, 4], "dimensions": [0.4, 0.4, 0.4], "tau": 3e-3, "lambda": 100e-9},
+        "particles": [
+            {"name": "A", "to_region": "background", "count": 500},
+            {"name": "B", "to_region": "background", "concentration": 0.5}
+        ]
+    }"#.parse().unwrap();
+
+    match Simulation::from_model(&model) {
+        Err(error @ ModelError::Invalid { .. }) => assert_eq!(error.path(), Some("particles[1].concentration")),
+        Err(error) => panic!("unexpected error {}", error),
+        Ok(_) => panic!("532 particles were placed in 512 slots"),
+    }
+}